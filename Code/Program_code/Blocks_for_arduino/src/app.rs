@@ -2,19 +2,56 @@
 // This file contains the core state management, block manipulation, validation, 
 // workspace persistence, and UI rendering for the visual block-based Arduino code editor.
 
+use crate::grade::{self, GradeReport};
 use crate::helper::*;
+use crate::highlight::highlight_line;
+use crate::ir::{self, Expr, MappedSource, Sketch, Stmt, StmtKind};
 use crate::model::*;
+use crate::pattern::{Correction, Edit, SequencePattern};
+use crate::sim::{PinMode, Simulator, VirtualBoard};
+use crate::trace::{self, Trace};
 use eframe::egui;
 use std::collections::{HashMap, HashSet};
+use std::sync::mpsc;
 
 // UI Layout Constants
 const GLOBAL_X: f32 = 140.0;           // Block width in pixels
 const GLOBAL_Y: f32 = 90.0;            // Block height in pixels
 const VALID_SEQUENCES_PATH: &str = "Valid_sequences.txt";
+const VALIDATION_RULES_PATH: &str = "Validation_rules.json";
 const INO_OUTPUT_DIR: &str = "INO";
 const BLOCKS_START_Y: f32 = 120.0;     // Y-position where blocks start rendering
 const PALETTE_BLOCK_GAP: f32 = 10.0;   // Vertical gap between palette blocks
-const MAX_SEQUENCE_BLOCKS_FOR_GENERATION: usize = 7; // Max blocks to use in sequence generation
+const SLOT_LABEL_H: f32 = 18.0;        // Height of the label row above each statement slot
+const EMPTY_SLOT_H: f32 = 30.0;        // Height reserved for a statement slot with no blocks
+const SLOT_INDENT: f32 = 30.0;         // Default X indent of blocks inside a statement slot
+const REPORTER_X: f32 = 130.0;         // Reporter (value) block width in pixels
+const REPORTER_BASE_Y: f32 = 30.0;     // Reporter block height before its input rows
+const INPUT_ROW_Y: f32 = 26.0;         // Y-offset of the first input row inside a block
+const INPUT_ROW_H: f32 = 22.0;         // Height of each input row
+const SOCKET_GAP: f32 = 6.0;           // Gap between a block and a reporter plugged into it
+const MAX_UNDO_STEPS: usize = 50;       // Oldest undo snapshots are dropped beyond this
+const MAX_SEQUENCE_SUGGESTIONS: usize = 3; // Corrected sequences offered per invalid stack
+const SIMULATION_STEPS_PER_FRAME: usize = 200_000; // Keeps the UI responsive in busy loops
+const TIMELINE_LABEL_W: f32 = 56.0;   // Width of the pin name column in the timeline
+const TIMELINE_ROW_H: f32 = 26.0;     // Height of each pin's trace in the timeline
+
+/// C++ types offered for project variables
+const VARIABLE_TYPES: [&str; 6] = ["int", "long", "unsigned long", "float", "bool", "byte"];
+
+/// Validation rules implemented in code; any other rule name must be registered in
+/// Validation_rules.json
+const BUILTIN_RULES: [&str; 14] = [
+    "arduino_pin", "analog_pin", "pwm_pin", "interrupt_pin", "arduino_state", "positive_integer",
+    "identifier", "arduino_condition", "variable_name", "variable_value", "function_name",
+    "function_parameters", "function_call", "function_arguments",
+];
+
+/// Arduino constants that may appear in expressions without being declared as variables.
+/// The analog pin names (A0, A1, ...) depend on the board and come from its profile.
+const ARDUINO_CONSTANTS: [&str; 8] = [
+    "HIGH", "LOW", "INPUT", "OUTPUT", "INPUT_PULLUP", "LED_BUILTIN", "true", "false",
+];
 
 /// Main application state for the Blocks for Arduino editor.
 /// 
@@ -34,16 +71,12 @@ pub struct BlocksForArduino {
     /// Status message displayed in the toolbar
     pub status_message: String,
     
-    /// Valid block sequences loaded from Valid_sequences.txt
-    pub valid_sequences: Vec<Vec<String>>,
+    /// Whole-sequence patterns loaded from Valid_sequences.txt
+    pub valid_sequences: Vec<SequencePattern>,
     
-    /// Popup state for sequence validation suggestions
-    pub show_sequence_popup: bool,
-    pub sequence_popup_text: String,
-    
-    /// Popup state for input validation errors
-    pub show_validation_popup: bool,
-    pub validation_popup_text: String,
+    /// Problems found by the last check, listed in the diagnostics panel
+    pub diagnostics: Vec<Diagnostic>,
+    pub show_diagnostics: bool,
     
     // Workspace management
     /// Name of the currently open workspace
@@ -59,26 +92,108 @@ pub struct BlocksForArduino {
     
     /// Scroll position for palette blocks (left side)
     pub palette_scroll_offset: f32,
+
+    /// Project-level variables, declared as globals in the generated sketch
+    pub variables: Vec<VariableDefinition>,
+
+    /// State for the Variables window
+    pub show_variables_window: bool,
+
+    /// State for the live generated code preview panel
+    pub show_code_preview: bool,
+    pub code_preview: Option<MappedSource>,
+    /// Why the code could not be generated, or the first failing check if it could
+    pub code_preview_error: String,
+
+    /// instance_id of the block highlighted from the code preview or by clicking it,
+    /// which the Delete key removes
+    pub highlighted_block: Option<String>,
+
+    /// Board profiles loaded from Boards/*.json
+    pub boards: Vec<BoardProfile>,
+
+    /// The board the workspace targets; drives pin validation and export
+    pub board: BoardProfile,
+
+    /// Custom validation rules loaded from Validation_rules.json
+    pub validation_rules: Vec<ValidationRule>,
+    /// Compiled regular expressions, keyed by the pattern text from a rule or input
+    pub patterns: HashMap<String, regex::Regex>,
+
+    /// Workspace states saved before each applied quick fix, newest last
+    pub undo_stack: Vec<EditSnapshot>,
+
+    /// The program running on the virtual board, kept after it stops so its final pin
+    /// states stay visible
+    pub simulation: Option<Simulator>,
+    pub run_state: RunState,
+    /// instance_ids of the blocks the program pauses in front of
+    pub breakpoints: HashSet<String>,
+
+    /// State for the logic analyzer timeline of the virtual board's pins
+    pub show_timeline: bool,
+    /// Span of simulated time the timeline shows, ending at the current time
+    pub timeline_window_ms: f64,
+
+    /// Parts on the virtual breadboard, saved with the workspace
+    pub components: Vec<Component>,
+    pub show_breadboard: bool,
+
+    /// Assessments loaded from Assessments/*.json, offered in the grader window
+    pub assessments: Vec<Assessment>,
+    pub show_grader: bool,
+    pub selected_assessment: usize,
+    /// Result of the last grading run in the grader window
+    pub grade_report: Option<GradeReport>,
+    /// Receives the report of a grading run still going on in the background
+    pub grading: Option<mpsc::Receiver<GradeReport>>,
 }
 
 impl Default for BlocksForArduino {
     fn default() -> Self {
+        let validation_rules = Self::load_validation_rules(VALIDATION_RULES_PATH);
+        let mut patterns = HashMap::new();
+        for rule in &validation_rules {
+            Self::compile_pattern(&mut patterns, &rule.constraints, VALIDATION_RULES_PATH);
+        }
+
         Self {
             sections: Vec::new(),
             was_mouse_down: false,
             current_blocks: Vec::new(),
             status_message: String::new(),
             valid_sequences: Self::load_valid_sequences(VALID_SEQUENCES_PATH),
-            show_sequence_popup: false,
-            sequence_popup_text: String::new(),
-            show_validation_popup: false,
-            validation_popup_text: String::new(),
+            diagnostics: Vec::new(),
+            show_diagnostics: false,
             workspace_name: String::new(),
             show_open_dialog: false,
             available_workspaces: Vec::new(),
             show_save_as_dialog: false,
             save_as_name_input: String::new(),
             palette_scroll_offset: 0.0,
+            variables: Vec::new(),
+            show_variables_window: false,
+            show_code_preview: false,
+            code_preview: None,
+            code_preview_error: String::new(),
+            highlighted_block: None,
+            boards: Vec::new(),
+            board: BoardProfile::default(),
+            validation_rules,
+            patterns,
+            undo_stack: Vec::new(),
+            simulation: None,
+            run_state: RunState::Stopped,
+            breakpoints: HashSet::new(),
+            show_timeline: false,
+            timeline_window_ms: 2_000.0,
+            components: Vec::new(),
+            show_breadboard: false,
+            assessments: Vec::new(),
+            show_grader: false,
+            selected_assessment: 0,
+            grade_report: None,
+            grading: None,
         }
     }
 }
@@ -117,17 +232,22 @@ impl BlocksForArduino {
     fn initialise_runtime_fields(block: &mut BlockSection, pos: egui::Pos2, is_palette: bool) {
         block.pos = pos;
         block.attached_to = None;
+        block.attached_slot = None;
+        block.attached_input = None;
         block.children.clear();
         block.input_values = Self::build_input_values(block);
         block.instance_id = Self::new_instance_id();
         block.is_palette = is_palette;
+        block.procedure = None;
     }
 
     /// Loads a block definition from a JSON file and adds it to the palette.
     /// 
     /// The JSON file should contain a "block" key with "sections" containing block definitions.
     /// Only the first "show" section or the section with shown_element is displayed in the palette.
-    /// Looks for an "A_C_E" section to extract the code template.
+    /// Looks for an "A_C_E" section to extract the code template placed where the block sits,
+    /// and for optional "Global", "Setup" and "Loop" sections whose code is placed in that
+    /// part of the sketch instead.
     pub fn load_block_json(&mut self, path: &str) {
         let raw = match std::fs::read_to_string(path) {
             Ok(r) => r,
@@ -149,6 +269,9 @@ impl BlocksForArduino {
 
         let mut show_section = None;
         let mut ace_template = None;
+        let mut global_template = None;
+        let mut setup_template = None;
+        let mut loop_template = None;
 
         for section in sections.by_ref() {
             let section_id = section.id.to_ascii_lowercase();
@@ -158,14 +281,21 @@ impl BlocksForArduino {
                 }
             }
 
-            if section_id == "a_c_e" {
-                ace_template = section.code_equivelant.clone();
+            match section_id.as_str() {
+                "a_c_e" => ace_template = section.code_equivelant.clone(),
+                "global" => global_template = section.code_equivelant.clone(),
+                "setup" => setup_template = section.code_equivelant.clone(),
+                "loop" => loop_template = section.code_equivelant.clone(),
+                _ => {}
             }
         }
 
         let mut block = match show_section {
             Some(mut b) => {
                 b.code_equivelant = ace_template;
+                b.global_code = global_template;
+                b.setup_code = setup_template;
+                b.loop_code = loop_template;
                 b
             }
             None => return,
@@ -176,6 +306,24 @@ impl BlocksForArduino {
             block.unique_id = Some(block.id.clone());
         }
 
+        for input in &block.inputs {
+            if let Some(rule) = &input.validation
+                && !BUILTIN_RULES.contains(&rule.as_str())
+                && self.find_rule(rule).is_none()
+            {
+                println!(
+                    "Unknown validation rule '{}' on input '{}' in {}",
+                    rule, input.name, path
+                );
+                self.status_message = format!(
+                    "Block '{}' uses unknown validation rule '{}'",
+                    block.unique_id.as_deref().unwrap_or(&block.id),
+                    rule
+                );
+            }
+            Self::compile_pattern(&mut self.patterns, &input.constraints, path);
+        }
+
         Self::initialise_runtime_fields(
             &mut block,
             egui::pos2(
@@ -189,85 +337,54 @@ impl BlocksForArduino {
         self.sections.push(block);
     }
 
-    fn loaded_palette_block_ids(&self) -> Vec<String> {
-        let mut seen = HashSet::new();
-        let mut ids = Vec::new();
-
-        for block in self.sections.iter().filter(|block| block.is_palette) {
-            let block_id = block
-                .unique_id
-                .as_ref()
-                .cloned()
-                .unwrap_or_else(|| block.id.clone());
-            if seen.insert(block_id.clone()) {
-                ids.push(block_id);
+    /// Loads a board profile from a JSON file. A profile with the same id as the current
+    /// board replaces it, so the data file takes precedence over the built-in Uno.
+    pub fn load_board_json(&mut self, path: &str) {
+        let raw = match std::fs::read_to_string(path) {
+            Ok(r) => r,
+            Err(e) => {
+                println!("Failed to read {}: {}", path, e);
+                return;
             }
-        }
-
-        ids
-    }
+        };
 
-    /// Recursively generates all possible permutations of block sequences up to MAX_SEQUENCE_BLOCKS_FOR_GENERATION
-    /// Used for validating block connection orders
-    fn generate_sequences_recursive(
-        block_ids: &[String],
-        used: &mut [bool],
-        current: &mut Vec<String>,
-        out: &mut Vec<Vec<String>>,
-    ) {
-        for idx in 0..block_ids.len() {
-            if used[idx] {
-                continue;
+        let board: BoardProfile = match serde_json::from_str(&raw) {
+            Ok(b) => b,
+            Err(e) => {
+                println!("Invalid board profile in {}: {}", path, e);
+                return;
             }
+        };
 
-            used[idx] = true;
-            current.push(block_ids[idx].clone());
-            out.push(current.clone());
-            Self::generate_sequences_recursive(block_ids, used, current, out);
-            current.pop();
-            used[idx] = false;
+        if board.id == self.board.id {
+            self.board = board.clone();
         }
+        self.boards.retain(|existing| existing.id != board.id);
+        self.boards.push(board);
+        self.boards.sort_by(|a, b| a.name.cmp(&b.name));
     }
 
-    /// Generates all valid block sequence permutations from available palette blocks
-    /// Useful for initial setup and validation of block order constraints
-    fn generate_valid_sequences_from_blocks(block_ids: &[String]) -> Vec<Vec<String>> {
-        if block_ids.is_empty() {
-            return Vec::new();
-        }
-
-        let limited_ids: Vec<String> = block_ids
-            .iter()
-            .take(MAX_SEQUENCE_BLOCKS_FOR_GENERATION)
-            .cloned()
-            .collect();
-
-        let mut used = vec![false; limited_ids.len()];
-        let mut current = Vec::new();
-        let mut sequences = Vec::new();
-        Self::generate_sequences_recursive(&limited_ids, &mut used, &mut current, &mut sequences);
-        sequences
+    /// Reads an assessment file: how long to run a workspace and the pin behaviour to
+    /// expect from it
+    pub fn read_assessment(path: &str) -> Result<Assessment, String> {
+        let raw = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        serde_json::from_str(&raw).map_err(|e| format!("Invalid assessment in {}: {}", path, e))
     }
 
-    pub fn sync_valid_sequences_with_loaded_blocks(&mut self) {
-        let palette_ids = self.loaded_palette_block_ids();
-        let generated = Self::generate_valid_sequences_from_blocks(&palette_ids);
-        self.valid_sequences = generated.clone();
-
-        match serde_json::to_string_pretty(&generated) {
-            Ok(serialized) => {
-                if let Err(err) = std::fs::write(VALID_SEQUENCES_PATH, serialized) {
-                    eprintln!("Failed to write {}: {}", VALID_SEQUENCES_PATH, err);
-                }
-            }
-            Err(err) => {
-                eprintln!("Failed to serialise generated sequences: {}", err);
+    /// Loads an assessment from a JSON file into the grader's list
+    pub fn load_assessment_json(&mut self, path: &str) {
+        match Self::read_assessment(path) {
+            Ok(assessment) => {
+                self.assessments.push(assessment);
+                self.assessments.sort_by(|a, b| a.name.cmp(&b.name));
             }
+            Err(err) => eprintln!("{}", err),
         }
     }
 
     /// Creates a new code block instance from a palette block.
-    /// The new block is positioned offset from existing code blocks.
+    /// The new block is positioned offset from existing code blocks and keeps any
+    /// input values preset on the palette entry.
     pub fn spawn_code_block(&mut self, source: usize) {
         let mut new_block = self.sections[source].clone();
         let offset = self.code_block_count() as f32 * 15.0;
@@ -277,6 +394,13 @@ impl BlocksForArduino {
             false,
         );
 
+        // Palette entries may preset some inputs (e.g. the function a "Call" entry calls)
+        for (name, value) in &self.sections[source].input_values {
+            if !value.is_empty() {
+                new_block.input_values.insert(name.clone(), value.clone());
+            }
+        }
+
         self.sections.push(new_block);
         self.refresh_current_blocks();
     }
@@ -286,60 +410,276 @@ impl BlocksForArduino {
     fn detach_block(&mut self, idx: usize) {
         if let Some(parent) = self.sections[idx].attached_to.take() {
             self.sections[parent].children.retain(|&child| child != idx);
+            self.sections[idx].attached_slot = None;
+            self.sections[idx].attached_input = None;
+            self.relayout_stack(parent);
             self.refresh_current_blocks();
         }
     }
 
+    /// Returns the statement children of a block attached to the given slot, in list order.
+    /// `None` selects the blocks attached directly below the block.
+    fn slot_children(&self, idx: usize, slot: Option<&str>) -> Vec<usize> {
+        self.sections[idx]
+            .children
+            .iter()
+            .copied()
+            .filter(|&child| {
+                self.sections[child].attached_input.is_none()
+                    && self.sections[child].attached_slot.as_deref() == slot
+            })
+            .collect()
+    }
+
+    /// Returns the reporter block plugged into the named input of a block, if any
+    fn input_child(&self, idx: usize, input: &str) -> Option<usize> {
+        self.sections[idx]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self.sections[child].attached_input.as_deref() == Some(input))
+    }
+
+    /// Size of a block on the canvas; reporter blocks are narrower and grow with their inputs
+    fn block_size(&self, idx: usize) -> egui::Vec2 {
+        let block = &self.sections[idx];
+        if block.output.is_some() {
+            egui::vec2(REPORTER_X, REPORTER_BASE_Y + block.inputs.len() as f32 * INPUT_ROW_H)
+        } else {
+            egui::vec2(GLOBAL_X, GLOBAL_Y)
+        }
+    }
+
+    /// Checks whether a reporter with the given output type fits an input socket.
+    /// Variable reporters fit any socket; their type is checked during validation.
+    fn socket_accepts(input: &InputDefinition, output: &str) -> bool {
+        output == "variable" || input.input_type.as_deref().is_none_or(|accepted| accepted == output)
+    }
+
+    /// Offsets (relative to the block) where a reporter plugged into each input sits:
+    /// to the right of the block, level with the input row and below any reporter
+    /// plugged into an earlier input.
+    fn input_socket_offsets(&self, idx: usize) -> Vec<egui::Vec2> {
+        let width = self.block_size(idx).x;
+        let mut next_free_y = 0.0f32;
+        self.sections[idx]
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(row, input)| {
+                let y = (INPUT_ROW_Y + row as f32 * INPUT_ROW_H - 4.0).max(next_free_y);
+                if let Some(child) = self.input_child(idx, &input.name) {
+                    next_free_y = y + self.reporter_extent(child) + 4.0;
+                }
+                egui::vec2(width + SOCKET_GAP, y)
+            })
+            .collect()
+    }
+
+    /// Height of a reporter block including any reporters nested inside it
+    fn reporter_extent(&self, idx: usize) -> f32 {
+        let own = self.block_size(idx).y;
+        self.sections[idx]
+            .inputs
+            .iter()
+            .zip(self.input_socket_offsets(idx))
+            .filter_map(|(input, offset)| {
+                self.input_child(idx, &input.name)
+                    .map(|child| offset.y + self.reporter_extent(child))
+            })
+            .fold(own, f32::max)
+    }
+
+    /// Attaches a block to a parent at the given position, in a statement slot or an input
+    fn attach_block(
+        &mut self,
+        idx: usize,
+        parent: usize,
+        pos: egui::Pos2,
+        slot: Option<String>,
+        input: Option<String>,
+    ) {
+        self.detach_block(idx);
+        self.sections[idx].pos = pos;
+        self.sections[idx].attached_to = Some(parent);
+        self.sections[idx].attached_slot = slot;
+        self.sections[idx].attached_input = input;
+        if !self.sections[parent].children.contains(&idx) {
+            self.sections[parent].children.push(idx);
+        }
+        self.relayout_stack(parent);
+        self.refresh_current_blocks();
+    }
+
+    /// Height of the blocks inside a named slot (or the space reserved for an empty slot)
+    fn slot_height(&self, idx: usize, slot: &str) -> f32 {
+        let height: f32 = self
+            .slot_children(idx, Some(slot))
+            .into_iter()
+            .map(|child| self.block_extent(child))
+            .sum();
+        if height > 0.0 { height } else { EMPTY_SLOT_H }
+    }
+
+    /// Height of a block itself, including the space taken by its statement slots
+    fn block_body_height(&self, idx: usize) -> f32 {
+        GLOBAL_Y
+            + self.sections[idx]
+                .slots
+                .iter()
+                .map(|slot| SLOT_LABEL_H + self.slot_height(idx, &slot.name))
+                .sum::<f32>()
+    }
+
+    /// Height of a block plus every block stacked below it
+    fn block_extent(&self, idx: usize) -> f32 {
+        self.block_body_height(idx)
+            + self
+                .slot_children(idx, None)
+                .into_iter()
+                .map(|child| self.block_extent(child))
+                .sum::<f32>()
+    }
+
+    /// Position where the first block of the slot at `slot_pos` snaps
+    fn slot_origin(&self, idx: usize, slot_pos: usize) -> egui::Pos2 {
+        let block = &self.sections[idx];
+        let mut y = block.pos.y + GLOBAL_Y;
+        for slot in &block.slots[..slot_pos] {
+            y += SLOT_LABEL_H + self.slot_height(idx, &slot.name);
+        }
+        let offset = block.slots[slot_pos]
+            .offset
+            .map(|o| o.vec2())
+            .unwrap_or(egui::vec2(SLOT_INDENT, 0.0));
+        egui::pos2(block.pos.x + offset.x, y + SLOT_LABEL_H + offset.y)
+    }
+
+    /// Position where a block attached directly below this one snaps
+    fn next_origin(&self, idx: usize) -> egui::Pos2 {
+        let block = &self.sections[idx];
+        let offset = block
+            .child_offset
+            .map(|o| o.vec2())
+            .unwrap_or(egui::vec2(0.0, 0.0));
+        egui::pos2(
+            block.pos.x + offset.x,
+            block.pos.y + self.block_body_height(idx) + offset.y,
+        )
+    }
+
+    /// Returns the top block of the stack containing `idx`
+    fn stack_root(&self, mut idx: usize) -> usize {
+        while let Some(parent) = self.sections[idx].attached_to {
+            idx = parent;
+        }
+        idx
+    }
+
+    /// Repositions every block in the stack containing `idx`, e.g. after a slot grew
+    fn relayout_stack(&mut self, idx: usize) {
+        let root = self.stack_root(idx);
+        self.move_children(root);
+    }
+
     /// Attempts to snap a block to a parent if it's positioned within snap distance.
-    /// Searches for compatible parents and establishes parent-child connection if appropriate.
+    /// Every statement slot of a candidate parent is a separate snap target, as is the
+    /// position directly below it. Reporter blocks snap into free, type-compatible input
+    /// sockets instead. Establishes the parent-child connection on a match.
     fn try_snap(&mut self, idx: usize) {
-        let size = egui::vec2(GLOBAL_X, GLOBAL_Y);
+        // Hat blocks always start a stack, so they have no parent slot
+        if self.sections[idx].hat.is_some() {
+            return;
+        }
+
         let snap = 12.0;
         let my_pos = self.sections[idx].pos;
+        let mut own_blocks = Vec::new();
+        self.collect_descendants(idx, &mut own_blocks);
+        let output = self.sections[idx].output.clone();
 
         for j in 0..self.sections.len() {
-            if j == idx {
+            if self.sections[j].is_palette || own_blocks.contains(&j) {
                 continue;
             }
-            let parent = &self.sections[j];
-            let offset = parent
-                .child_offset
-                .map(|o| o.vec2())
-                .unwrap_or(egui::vec2(0.0, 0.0));
-
-            let target_x = parent.pos.x + offset.x;
-            let target_y = parent.pos.y + size.y + offset.y;
 
-            if (my_pos.y - target_y).abs() < snap && (my_pos.x - target_x).abs() < snap {
-                self.detach_block(idx);
-                self.sections[idx].pos = egui::pos2(target_x, target_y);
-                self.sections[idx].attached_to = Some(j);
-                if !self.sections[j].children.contains(&idx) {
-                    self.sections[j].children.push(idx);
+            // (slot, input, position) for every place the block could attach to `j`
+            let targets: Vec<(Option<String>, Option<String>, egui::Pos2)> = match &output {
+                Some(output) => self.sections[j]
+                    .inputs
+                    .iter()
+                    .zip(self.input_socket_offsets(j))
+                    .filter(|(input, _)| {
+                        Self::socket_accepts(input, output)
+                            && self.input_child(j, &input.name).is_none()
+                    })
+                    .map(|(input, offset)| {
+                        (None, Some(input.name.clone()), self.sections[j].pos + offset)
+                    })
+                    .collect(),
+                None => {
+                    let mut targets: Vec<_> = self.sections[j]
+                        .slots
+                        .iter()
+                        .enumerate()
+                        .map(|(slot_pos, slot)| {
+                            (Some(slot.name.clone()), None, self.slot_origin(j, slot_pos))
+                        })
+                        .collect();
+                    targets.push((None, None, self.next_origin(j)));
+                    targets
                 }
-                self.refresh_current_blocks();
+            };
+
+            let hit = targets.into_iter().find(|(_, _, target)| {
+                (my_pos.y - target.y).abs() < snap && (my_pos.x - target.x).abs() < snap
+            });
+
+            if let Some((slot, input, target)) = hit {
+                self.attach_block(idx, j, target, slot, input);
                 break;
             }
         }
     }
 
-    /// Repositions all child blocks in a vertical line below their parent.
+    /// Repositions all child blocks below their parent: blocks in each statement slot are
+    /// stacked inside that slot, the remaining children in a vertical line below, and
+    /// reporters next to the inputs they are plugged into.
     /// Called after parent block is moved to maintain proper child alignment.
     fn move_children(&mut self, parent: usize) {
-        let size = egui::vec2(GLOBAL_X, GLOBAL_Y);
-        let base = self.sections[parent].pos;
-        let offset = self.sections[parent]
-            .child_offset
-            .map(|o| o.vec2())
-            .unwrap_or(egui::vec2(0.0, 0.0));
+        let slot_names: Vec<String> = self.sections[parent]
+            .slots
+            .iter()
+            .map(|slot| slot.name.clone())
+            .collect();
 
-        let mut y = base.y + size.y + offset.y;
-        let children = self.sections[parent].children.clone();
+        for (slot_pos, name) in slot_names.iter().enumerate() {
+            let mut pos = self.slot_origin(parent, slot_pos);
+            for child in self.slot_children(parent, Some(name)) {
+                self.sections[child].pos = pos;
+                self.move_children(child);
+                pos.y += self.block_extent(child);
+            }
+        }
 
-        for child in children {
-            self.sections[child].pos = egui::pos2(base.x + offset.x, y);
-            y += size.y;
+        let mut pos = self.next_origin(parent);
+        for child in self.slot_children(parent, None) {
+            self.sections[child].pos = pos;
             self.move_children(child);
+            pos.y += self.block_extent(child);
+        }
+
+        let input_names: Vec<String> = self.sections[parent]
+            .inputs
+            .iter()
+            .map(|input| input.name.clone())
+            .collect();
+        let offsets = self.input_socket_offsets(parent);
+        for (name, offset) in input_names.iter().zip(offsets) {
+            if let Some(child) = self.input_child(parent, name) {
+                self.sections[child].pos = self.sections[parent].pos + offset;
+                self.move_children(child);
+            }
         }
     }
 
@@ -352,16 +692,25 @@ impl BlocksForArduino {
         }
     }
 
-    /// Recursively collects the unique IDs of a block and all its connected descendants
-    /// Used to build the current block sequence representation.
-    fn collect_connected_unique_ids(&self, idx: usize, out: &mut Vec<String>) {
-        out.push(self.block_unique_id(idx));
+    /// Recursively collects a block and all its connected descendants, in the order
+    /// used for the current block sequence representation.
+    /// Hat blocks only decide where a stack runs and reporters only supply values, so
+    /// they are left out of the sequence.
+    fn collect_sequence_blocks(&self, idx: usize, out: &mut Vec<usize>) {
+        if self.sections[idx].output.is_some() {
+            return;
+        }
+        if self.sections[idx].hat.is_none() {
+            out.push(idx);
+        }
         for &child in &self.sections[idx].children {
-            self.collect_connected_unique_ids(child, out);
+            self.collect_sequence_blocks(child, out);
         }
     }
 
-    fn build_current_blocks(&self) -> Vec<BlockListEntry> {
+    /// Returns the block sequence of every stack that has one, top to bottom, together
+    /// with the index of the stack's root block
+    fn sequences_by_root(&self) -> Vec<(usize, Vec<String>)> {
         let mut roots: Vec<usize> = self
             .sections
             .iter()
@@ -374,13 +723,21 @@ impl BlocksForArduino {
         roots
             .into_iter()
             .map(|root| {
-                let mut group = Vec::new();
-                self.collect_connected_unique_ids(root, &mut group);
-                if group.len() == 1 {
-                    BlockListEntry::Single(group.remove(0))
-                } else {
-                    BlockListEntry::Group(group)
-                }
+                let mut blocks = Vec::new();
+                self.collect_sequence_blocks(root, &mut blocks);
+                let group: Vec<String> = blocks.into_iter().map(|idx| self.block_unique_id(idx)).collect();
+                (root, group)
+            })
+            .filter(|(_, group)| !group.is_empty())
+            .collect()
+    }
+
+    fn build_current_blocks(&self) -> Vec<BlockListEntry> {
+        self.sequences_by_root()
+            .into_iter()
+            .map(|(_, mut group)| match group.len() {
+                1 => BlockListEntry::Single(group.remove(0)),
+                _ => BlockListEntry::Group(group),
             })
             .collect()
     }
@@ -390,9 +747,11 @@ impl BlocksForArduino {
         serde_json::to_string(&self.current_blocks).unwrap_or_else(|_| "[]".to_string())
     }
 
-    /// Loads valid block sequences from the specified JSON file.
+    /// Loads the whole-sequence patterns from the specified JSON file. Each entry is
+    /// either pattern text or an exact list of block ids; patterns that do not compile
+    /// are reported and skipped.
     /// Returns empty Vec if file is missing or contains invalid JSON.
-    fn load_valid_sequences(path: &str) -> Vec<Vec<String>> {
+    fn load_valid_sequences(path: &str) -> Vec<SequencePattern> {
         let raw = match std::fs::read_to_string(path) {
             Ok(raw) => raw,
             Err(err) => {
@@ -401,38 +760,68 @@ impl BlocksForArduino {
             }
         };
 
-        match serde_json::from_str::<Vec<Vec<String>>>(&raw) {
-            Ok(sequences) => sequences,
+        let rules = match serde_json::from_str::<Vec<SequenceRule>>(&raw) {
+            Ok(rules) => rules,
             Err(err) => {
                 eprintln!("Invalid sequence JSON in {}: {}", path, err);
-                Vec::new()
+                return Vec::new();
             }
-        }
-    }
+        };
 
-    /// Converts current block connections into a flat list of sequences.
-    /// Each sequence is either a single block or a group of connected blocks.
-    fn flatten_current_sequences(&self) -> Vec<Vec<String>> {
-        self.current_blocks
-            .iter()
-            .map(|entry| match entry {
-                BlockListEntry::Single(id) => vec![id.clone()],
-                BlockListEntry::Group(ids) => ids.clone(),
+        rules
+            .into_iter()
+            .filter_map(|rule| match rule {
+                SequenceRule::Sequence(sequence) => Some(SequencePattern::literal(&sequence)),
+                SequenceRule::Pattern(text) => match SequencePattern::compile(&text) {
+                    Ok(pattern) => Some(pattern),
+                    Err(err) => {
+                        eprintln!("Invalid sequence pattern '{}' in {}: {}", text, path, err);
+                        None
+                    }
+                },
             })
             .collect()
     }
 
-    /// Calculates the edit distance (Levenshtein-like) between two block sequences.
-    /// Used for suggesting corrections when validation fails.
-    fn sequence_distance(a: &[String], b: &[String]) -> usize {
-        let min_len = a.len().min(b.len());
-        let mut distance = a.len().abs_diff(b.len());
-        for i in 0..min_len {
-            if a[i] != b[i] {
-                distance += 1;
+    /// Loads the custom validation rules from the specified JSON file.
+    /// Returns empty Vec if file is missing or contains invalid JSON.
+    fn load_validation_rules(path: &str) -> Vec<ValidationRule> {
+        let raw = match std::fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(err) => {
+                eprintln!("Failed to read {}: {}", path, err);
+                return Vec::new();
+            }
+        };
+
+        match serde_json::from_str::<Vec<ValidationRule>>(&raw) {
+            Ok(rules) => rules,
+            Err(err) => {
+                eprintln!("Invalid validation rules JSON in {}: {}", path, err);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Compiles the pattern of a set of constraints into the cache, anchored so the whole
+    /// value must match. Invalid patterns are reported and skipped.
+    fn compile_pattern(
+        patterns: &mut HashMap<String, regex::Regex>,
+        constraints: &InputConstraints,
+        source: &str,
+    ) {
+        let Some(pattern) = &constraints.pattern else {
+            return;
+        };
+        if patterns.contains_key(pattern) {
+            return;
+        }
+        match regex::Regex::new(&format!("^(?:{pattern})$")) {
+            Ok(re) => {
+                patterns.insert(pattern.clone(), re);
             }
+            Err(err) => eprintln!("Invalid pattern '{}' in {}: {}", pattern, source, err),
         }
-        distance
     }
 
     /// Gathers all available block IDs (both palette and code blocks)
@@ -449,142 +838,393 @@ impl BlocksForArduino {
             .collect()
     }
 
-    /// Filters valid sequences to only include those where all blocks are available
-    fn valid_sequences_for_blocks<'a>(
-        &'a self,
-        available_blocks: &HashSet<String>,
-    ) -> Vec<&'a Vec<String>> {
-        self.valid_sequences
-            .iter()
-            .filter(|sequence| {
-                sequence
-                    .iter()
-                    .all(|block_id| available_blocks.contains(block_id))
-            })
-            .collect()
+    /// Formats a sequence into a readable string like "Block1 -> Block2 -> Block3"
+    fn join_sequence(sequence: &[String]) -> String {
+        sequence.join(" -> ")
     }
 
-    /// Suggests the closest valid sequence to the current one using sequence distance
-    fn suggest_sequence(sequence: &[String], candidates: &[&Vec<String>]) -> Option<Vec<String>> {
-        candidates
-            .iter()
-            .min_by_key(|candidate| Self::sequence_distance(sequence, candidate))
-            .map(|candidate| (*candidate).clone())
+    /// Runs every check over the workspace in one pass: the variable table, block inputs,
+    /// pin usage, stack attachment, connection rules and block order. Errors are listed
+    /// before warnings.
+    fn collect_diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.variable_diagnostics();
+        diagnostics.extend(self.input_diagnostics());
+        diagnostics.extend(self.pin_usage_diagnostics());
+        diagnostics.extend(self.stack_diagnostics());
+        diagnostics.extend(self.connection_diagnostics());
+        if !self.current_blocks.is_empty() {
+            diagnostics.extend(self.sequence_diagnostics());
+        }
+        diagnostics.sort_by_key(|d| d.severity);
+        diagnostics
     }
 
-    /// Formats a sequence into a readable string like "Block1 -> Block2 -> Block3"
-    fn join_sequence(sequence: &[String]) -> String {
-        sequence.join(" -> ")
+    /// Re-runs every check and stores the results for the diagnostics panel and canvas
+    fn refresh_diagnostics(&mut self) {
+        self.diagnostics = self.collect_diagnostics();
+    }
+
+    /// Counts the stored diagnostics of one severity
+    fn diagnostic_count(&self, severity: Severity) -> usize {
+        self.diagnostics.iter().filter(|d| d.severity == severity).count()
     }
 
-    /// Validates that the current block sequence matches one of the valid sequences.
-    /// Returns detailed error messages with suggestions if validation fails.
-    fn validate_current_sequences(&self) -> Result<(), String> {
+    /// Summarises the stored diagnostics, e.g. "2 errors, 1 warning"
+    fn diagnostics_summary(&self) -> String {
+        let plural = |n: usize, word: &str| format!("{n} {word}{}", if n == 1 { "" } else { "s" });
+        format!(
+            "{}, {}",
+            plural(self.diagnostic_count(Severity::Error), "error"),
+            plural(self.diagnostic_count(Severity::Warning), "warning")
+        )
+    }
+
+    /// Checks every current block sequence against the sequence patterns. A sequence
+    /// that no pattern accepts is reported at the block where it leaves the pattern
+    /// that follows it furthest, with the blocks that pattern expected there.
+    fn sequence_diagnostics(&self) -> Vec<Diagnostic> {
+        let error = |text: String| Diagnostic::from_message(Severity::Error, None, None, &text);
+
+        // Whole-sequence rules are optional; most lessons rely on connection rules
         if self.valid_sequences.is_empty() {
-            return Err(format!(
-                "Could not validate because {} is missing or invalid JSON",
-                VALID_SEQUENCES_PATH
-            ));
+            return Vec::new();
         }
 
         let available_blocks = self.available_block_ids();
-        let valid_sequences = self.valid_sequences_for_blocks(&available_blocks);
-        if valid_sequences.is_empty() {
-            return Err(format!(
+        let patterns: Vec<&SequencePattern> = self
+            .valid_sequences
+            .iter()
+            .filter(|pattern| pattern.block_ids().iter().all(|id| available_blocks.contains(*id)))
+            .collect();
+        if patterns.is_empty() {
+            return vec![error(format!(
                 "No valid sequences can be checked because every entry in {} references blocks that are not currently loaded.",
                 VALID_SEQUENCES_PATH
-            ));
+            ))];
         }
 
-        let sequences = self.flatten_current_sequences();
-
-        for (idx, sequence) in sequences.iter().enumerate() {
+        let mut out = Vec::new();
+        for (idx, (root, sequence)) in self.sequences_by_root().iter().enumerate() {
+            let mut blocks = Vec::new();
+            self.collect_sequence_blocks(*root, &mut blocks);
+            let error = |at: usize, text: String| {
+                Diagnostic::from_message(Severity::Error, Some(&self.sections[at].instance_id), None, &text)
+            };
             if let Some(missing_block) =
                 sequence.iter().find(|block_id| !available_blocks.contains(*block_id))
             {
-                return Err(format!(
+                out.push(error(*root, format!(
                     "Sequence {} contains unknown block '{}'.\nCurrent: {}\nSuggested: add '{}' to {} or remove it from the sequence.",
                     idx + 1,
                     missing_block,
                     Self::join_sequence(sequence),
                     missing_block,
                     VALID_SEQUENCES_PATH
-                ));
+                )));
+                continue;
             }
 
-            if valid_sequences.iter().any(|valid| *valid == sequence) {
+            let mut mismatches = Vec::new();
+            for pattern in &patterns {
+                match pattern.check(sequence) {
+                    Ok(()) => break,
+                    Err(mismatch) => mismatches.push((*pattern, mismatch)),
+                }
+            }
+            if mismatches.len() < patterns.len() {
                 continue;
             }
 
-            let current_text = Self::join_sequence(sequence);
-            let suggestion = Self::suggest_sequence(sequence, &valid_sequences)
-                .map(|s| Self::join_sequence(&s))
-                .unwrap_or_else(|| "No suggestion available".to_string());
+            // Report against the pattern the sequence follows for longest
+            let Some((pattern, mismatch)) = mismatches
+                .into_iter()
+                .rev()
+                .max_by_key(|(_, mismatch)| mismatch.position)
+            else {
+                continue;
+            };
+            let expected = Self::quote_block_ids(&mismatch.expected);
+            let (at, text) = match blocks.get(mismatch.position) {
+                Some(&at) => {
+                    let suggestion = match (mismatch.expected.is_empty(), mismatch.can_end) {
+                        (true, _) => "end the stack before this block.".to_string(),
+                        (false, true) => format!("use {expected} here, or end the stack before this block."),
+                        (false, false) => format!("use {expected} here."),
+                    };
+                    (
+                        at,
+                        format!(
+                            "Sequence {} leaves the pattern '{}' at block {} ('{}').\nCurrent: {}\nSuggested: {}",
+                            idx + 1,
+                            pattern.source,
+                            mismatch.position + 1,
+                            sequence[mismatch.position],
+                            Self::join_sequence(sequence),
+                            suggestion
+                        ),
+                    )
+                }
+                None => (
+                    blocks.last().copied().unwrap_or(*root),
+                    format!(
+                        "Sequence {} ends before the pattern '{}' is complete.\nCurrent: {}\nSuggested: add {} after '{}'.",
+                        idx + 1,
+                        pattern.source,
+                        Self::join_sequence(sequence),
+                        expected,
+                        sequence.last().map_or("", String::as_str)
+                    ),
+                ),
+            };
 
-            return Err(format!(
-                "Sequence {} is not valid.\nCurrent: {}\nSuggested: {}",
-                idx + 1,
-                current_text,
-                suggestion
-            ));
+            out.push(error(at, text).with_candidates(self.sequence_candidates(*root, sequence, &patterns)));
         }
-
-        Ok(())
+        out
     }
 
-    // ------------------------------------------------------------------
-    // Input value validation
-    // ------------------------------------------------------------------
+    /// Collects the closest corrections of a stack's sequence over all patterns, cheapest
+    /// first, leaving out any that need far more edits than the best one. Candidates
+    /// that only move blocks can be applied as a reorder.
+    fn sequence_candidates(
+        &self,
+        root: usize,
+        sequence: &[String],
+        patterns: &[&SequencePattern],
+    ) -> Vec<SequenceCandidate> {
+        let mut corrections: Vec<Correction> = patterns
+            .iter()
+            .flat_map(|pattern| pattern.corrections(sequence, MAX_SEQUENCE_SUGGESTIONS))
+            .collect();
+        corrections.sort_by_key(Correction::cost);
+        let best = corrections.first().map_or(0, |correction| correction.edits.len());
 
-    /// Checks a single raw value against a validation rule and returns a
-    /// human-readable error with a concrete suggestion when invalid.
-    fn validate_input_value(value: &str, rule: &str, field_name: &str) -> Result<(), String> {
-        let trimmed = value.trim();
-        if trimmed.is_empty() {
-            return Err(format!(
-                "'{field_name}' must not be empty.\nSuggested: fill in a valid value."
-            ));
+        let mut candidates: Vec<SequenceCandidate> = Vec::new();
+        for correction in corrections {
+            if candidates.len() == MAX_SEQUENCE_SUGGESTIONS {
+                break;
+            }
+            if correction.edits.len() > best + 2
+                || candidates.iter().any(|c| c.sequence == correction.sequence)
+            {
+                continue;
+            }
+            candidates.push(SequenceCandidate {
+                fix: self.reorder_fix(root, &correction.sequence),
+                edits: correction.edits.iter().map(Edit::label).collect(),
+                sequence: correction.sequence,
+            });
         }
-        match rule {
-            "arduino_pin" => {
-                let upper = trimmed.to_uppercase();
-                let valid = upper
-                    .parse::<u8>()
-                    .map_or(false, |n| n <= 13)
-                    || (upper.starts_with('A')
-                        && upper[1..].parse::<u8>().map_or(false, |n| n <= 5));
-                if !valid {
-                    return Err(format!(
-                        "'{field_name}' has value '{trimmed}' which is not a valid Arduino pin.\n\
-                         Suggested: use a number 0–13 for digital pins (e.g. 13),\n\
-                         or A0–A5 for analog pins (e.g. A0)."
-                    ));
-                }
+        candidates
+    }
+
+    /// Returns the blocks whose ids make up the sequence of a stack when the stack is a
+    /// plain chain: each block directly below the previous one, with no blocks inside
+    /// statement slots. Reporters and the hat block are left out, as in the sequence.
+    fn stack_chain(&self, root: usize) -> Option<Vec<usize>> {
+        let mut chain = Vec::new();
+        let mut current = Some(root);
+        while let Some(idx) = current {
+            let block = &self.sections[idx];
+            if block.slots.iter().any(|slot| !self.slot_children(idx, Some(&slot.name)).is_empty()) {
+                return None;
             }
-            "arduino_state" => {
-                let upper = trimmed.to_uppercase();
-                if !matches!(upper.as_str(), "HIGH" | "LOW" | "1" | "0") {
-                    return Err(format!(
-                        "'{field_name}' has value '{trimmed}' which is not a valid pin state.\n\
-                         Suggested: use HIGH or LOW (or the numeric equivalents 1 / 0)."
-                    ));
-                }
+            if block.hat.is_none() {
+                chain.push(idx);
             }
-            "positive_integer" => match trimmed.parse::<u32>() {
-                Ok(0) => {
-                    return Err(format!(
-                        "'{field_name}' must be greater than 0.\n\
-                         Suggested: enter a positive whole number in milliseconds (e.g. 1000)."
-                    ));
-                }
-                Err(_) => {
-                    return Err(format!(
-                        "'{field_name}' has value '{trimmed}' which is not a valid number.\n\
-                         Suggested: enter a positive whole number in milliseconds (e.g. 1000)."
+            current = self.slot_children(idx, None).first().copied();
+        }
+        Some(chain)
+    }
+
+    /// Builds the fix that reorders a stack into the suggested sequence. Only possible
+    /// when the suggestion uses exactly the blocks already in the stack.
+    fn reorder_fix(&self, root: usize, suggested: &[String]) -> Option<QuickFix> {
+        let mut unused = self.stack_chain(root)?;
+        let mut order = Vec::new();
+        for id in suggested {
+            let pos = unused.iter().position(|&idx| self.block_unique_id(idx) == *id)?;
+            order.push(self.sections[unused.remove(pos)].instance_id.clone());
+        }
+        unused.is_empty().then_some(QuickFix::ReorderStack { order })
+    }
+
+    /// Lists a block and everything attached to it, depth first in canvas order
+    fn blocks_in_order(&self, idx: usize, out: &mut Vec<usize>) {
+        out.push(idx);
+        for child in self.sorted_child_indices(idx) {
+            self.blocks_in_order(child, out);
+        }
+    }
+
+    /// Returns the blocks of the hat-started stacks in the order the sketch first runs
+    /// them: "On start" stacks, then "Forever" stacks, each top to bottom. Function
+    /// bodies only run when called, so they are left out.
+    fn program_order(&self) -> Vec<usize> {
+        let roots = self.root_block_indices();
+        let mut order = Vec::new();
+        for kind in [HatKind::Setup, HatKind::Loop] {
+            for &root in roots.iter().filter(|&&root| self.sections[root].hat == Some(kind)) {
+                self.blocks_in_order(root, &mut order);
+            }
+        }
+        order
+    }
+
+    /// Formats block ids for messages, e.g. "'On_start' or 'Forever'"
+    fn quote_block_ids(ids: &[String]) -> String {
+        let quoted: Vec<String> = ids.iter().map(|id| format!("'{id}'")).collect();
+        quoted.join(" or ")
+    }
+
+    /// Checks every block against the connection rules declared in its JSON: which
+    /// blocks it may sit under and hold, which blocks it must run before, which
+    /// containers it must be inside and how many of it the workspace may hold.
+    fn connection_diagnostics(&self) -> Vec<Diagnostic> {
+        let error = |idx: usize, text: String| {
+            Diagnostic::from_message(Severity::Error, Some(&self.sections[idx].instance_id), None, &text)
+        };
+        let mut out = Vec::new();
+
+        let mut all_blocks = Vec::new();
+        for root in self.root_block_indices() {
+            self.blocks_in_order(root, &mut all_blocks);
+        }
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for &idx in &all_blocks {
+            let rules = &self.sections[idx].connections;
+            let id = self.block_unique_id(idx);
+
+            let count = counts.entry(id.clone()).or_default();
+            *count += 1;
+            if let Some(max) = rules.max_count
+                && *count > max
+            {
+                out.push(error(
+                    idx,
+                    format!(
+                        "Only {max} '{id}' block{} allowed in a program.\n\
+                         Suggested: remove this '{id}' block and move its blocks under the first one.",
+                        if max == 1 { " is" } else { "s are" }
+                    ),
+                ));
+            }
+
+            if let Some(parent) = self.sections[idx].attached_to {
+                let parent_id = self.block_unique_id(parent);
+                if !rules.parents.is_empty() && !rules.parents.contains(&parent_id) {
+                    out.push(error(
+                        idx,
+                        format!(
+                            "'{id}' cannot be attached to '{parent_id}'.\n\
+                             Suggested: attach it to {} instead.",
+                            Self::quote_block_ids(&rules.parents)
+                        ),
+                    ));
+                }
+                let accepted = &self.sections[parent].connections.children;
+                if !accepted.is_empty() && !accepted.contains(&id) {
+                    out.push(error(
+                        idx,
+                        format!(
+                            "'{parent_id}' does not accept '{id}'.\n\
+                             Suggested: only attach {} to '{parent_id}'.",
+                            Self::quote_block_ids(accepted)
+                        ),
+                    ));
+                }
+            }
+
+            // Stacks without a hat are already reported by the stack check
+            let root = self.stack_root(idx);
+            if !rules.inside.is_empty() && self.sections[root].hat.is_some() {
+                let mut ancestor = self.sections[idx].attached_to;
+                let mut inside = false;
+                while let Some(current) = ancestor {
+                    inside |= rules.inside.contains(&self.block_unique_id(current));
+                    ancestor = self.sections[current].attached_to;
+                }
+                if !inside {
+                    out.push(error(
+                        idx,
+                        format!(
+                            "'{id}' must be inside {}.\n\
+                             Suggested: move it under {}.",
+                            Self::quote_block_ids(&rules.inside),
+                            Self::quote_block_ids(&rules.inside)
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let order = self.program_order();
+        for (pos, &idx) in order.iter().enumerate() {
+            let id = self.block_unique_id(idx);
+            for later in &self.sections[idx].connections.before {
+                if order[..pos].iter().any(|&earlier| self.block_unique_id(earlier) == *later) {
+                    out.push(error(
+                        idx,
+                        format!(
+                            "'{id}' must come before '{later}', but a '{later}' block runs first.\n\
+                             Suggested: move '{id}' above the first '{later}' block, or into \"On start\"."
+                        ),
+                    ));
+                }
+            }
+        }
+
+        out
+    }
+
+    // ------------------------------------------------------------------
+    // Input value validation
+    // ------------------------------------------------------------------
+
+    /// Checks a single raw value against a validation rule and returns a
+    /// human-readable error with a concrete suggestion when invalid.
+    /// Pin rules are checked against the selected board profile.
+    fn validate_input_value(&self, value: &str, rule: &str, field_name: &str) -> Result<(), String> {
+        let trimmed = value.trim();
+        if trimmed.is_empty() {
+            return Err(format!(
+                "'{field_name}' must not be empty.\nSuggested: fill in a valid value."
+            ));
+        }
+        match rule {
+            "arduino_pin" | "analog_pin" | "pwm_pin" | "interrupt_pin" => {
+                self.validate_board_pin(trimmed, rule, field_name)?;
+            }
+            "arduino_state" => {
+                let upper = trimmed.to_uppercase();
+                if !matches!(upper.as_str(), "HIGH" | "LOW" | "1" | "0") {
+                    return Err(format!(
+                        "'{field_name}' has value '{trimmed}' which is not a valid pin state.\n\
+                         Suggested: use HIGH or LOW (or the numeric equivalents 1 / 0)."
+                    ));
+                }
+            }
+            "positive_integer" => match trimmed.parse::<u32>() {
+                Ok(0) => {
+                    return Err(format!(
+                        "'{field_name}' must be greater than 0.\n\
+                         Suggested: enter a positive whole number in milliseconds (e.g. 1000)."
+                    ));
+                }
+                Err(_) => {
+                    return Err(format!(
+                        "'{field_name}' has value '{trimmed}' which is not a valid number.\n\
+                         Suggested: enter a positive whole number in milliseconds (e.g. 1000)."
                     ));
                 }
                 Ok(_) => {}
             },
+            "identifier" if !ir::is_identifier(trimmed) => {
+                return Err(format!(
+                    "'{field_name}' has value '{trimmed}' which is not a valid name.\n\
+                     Suggested: use letters, digits and underscores, starting with a letter (e.g. myServo)."
+                ));
+            }
             "arduino_condition" => {
                 if trimmed.eq_ignore_ascii_case("true") && trimmed != "true" {
                     return Err(format!(
@@ -600,76 +1240,1215 @@ impl BlocksForArduino {
                     ));
                 }
             }
-            _ => {}
+            _ => {
+                if let Some(custom) = self.find_rule(rule)
+                    && let Err(hint) = self.check_constraints(trimmed, &custom.constraints)
+                {
+                    return Err(format!(
+                        "'{field_name}' has value '{trimmed}' which is not {}.\nSuggested: {}",
+                        custom.description,
+                        custom.suggestion.clone().unwrap_or(hint)
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds a custom validation rule by name
+    fn find_rule(&self, name: &str) -> Option<&ValidationRule> {
+        self.validation_rules.iter().find(|rule| rule.name == name)
+    }
+
+    /// Lists the pins of the selected board that a pin rule accepts, in board order
+    fn board_pins_for_rule(&self, rule: &str) -> Vec<String> {
+        self.board
+            .digital_pins
+            .iter()
+            .map(|pin| pin.to_string())
+            .chain(self.board.analog_pins.iter().cloned())
+            .filter(|pin| self.validate_board_pin(pin, rule, "").is_ok())
+            .collect()
+    }
+
+    /// Chooses the widget for an input of block `idx`: a dropdown for pins, pin states
+    /// and inputs with fixed choices, a drag-value for whole numbers, a checkbox for
+    /// plain booleans and a colour picker for colours. Pin and state dropdowns also offer
+    /// the variables in scope that may stand in for them.
+    fn input_widget(&self, idx: usize, input: &InputDefinition) -> InputWidget {
+        let rule = input.validation.as_deref();
+        let inline = &input.constraints;
+        let custom = rule.and_then(|name| self.find_rule(name)).map(|r| &r.constraints);
+        let choices = match custom {
+            Some(c) if inline.choices.is_empty() => &c.choices,
+            _ => &inline.choices,
+        };
+        let min = inline.min.or(custom.and_then(|c| c.min));
+        let max = inline.max.or(custom.and_then(|c| c.max));
+        let variables = |allow_bool: bool| {
+            self.variables_in_scope(idx)
+                .into_iter()
+                .filter(move |variable| allow_bool || variable.cpp_type != "bool")
+                .map(|variable| variable.name)
+        };
+
+        match rule {
+            Some("arduino_state") => InputWidget::Choice(
+                ["HIGH", "LOW"].map(str::to_string).into_iter().chain(variables(true)).collect(),
+            ),
+            Some(pin_rule @ ("arduino_pin" | "analog_pin" | "pwm_pin" | "interrupt_pin")) => {
+                InputWidget::Choice(
+                    self.board_pins_for_rule(pin_rule).into_iter().chain(variables(false)).collect(),
+                )
+            }
+            _ if !choices.is_empty() => InputWidget::Choice(choices.clone()),
+            Some("positive_integer") => InputWidget::Integer { min: min.or(Some(1)), max },
+            _ if min.is_some() || max.is_some() => InputWidget::Integer { min, max },
+            _ if input.input_type.as_deref() == Some("colour") => InputWidget::Colour,
+            None if input.input_type.as_deref() == Some("boolean") => InputWidget::Checkbox,
+            _ => InputWidget::Text,
+        }
+    }
+
+    /// Shows the widget for one input and writes the edited value back as text.
+    ///
+    /// # Arguments
+    /// * `id` - Salt that is unique to this block and input, for dropdown state
+    /// * `value` - The input's stored text
+    /// * `widget` - The widget chosen by `input_widget`
+    /// * `hint` - The input name, shown when the value is empty
+    /// * `error` - Live validation error; tints text fields and becomes the tooltip
+    ///
+    /// # Returns
+    /// The widget's response, which reports whether the value changed
+    fn show_input_widget(
+        ui: &mut egui::Ui,
+        id: &str,
+        value: &mut String,
+        widget: &InputWidget,
+        hint: &str,
+        error: Option<&str>,
+    ) -> egui::Response {
+        let tint = if ui.visuals().dark_mode {
+            egui::Color32::from_rgb(110, 30, 30)
+        } else {
+            egui::Color32::from_rgb(255, 200, 200)
+        };
+
+        let mut response = match widget {
+            InputWidget::Choice(options) => {
+                let mut changed = false;
+                let selected = if value.trim().is_empty() { hint } else { value.as_str() };
+                let mut response = egui::ComboBox::from_id_salt(id)
+                    .width(ui.available_width())
+                    .selected_text(selected.to_string())
+                    .show_ui(ui, |ui| {
+                        for option in options {
+                            if ui.selectable_label(value.trim() == option, option).clicked() {
+                                *value = option.clone();
+                                changed = true;
+                            }
+                        }
+                    })
+                    .response;
+                if changed {
+                    response.mark_changed();
+                }
+                response
+            }
+            // A stored number gets a drag-value, with a button to type a variable name
+            // instead; anything else, including an empty value, keeps the text field
+            InputWidget::Integer { min, max } if value.trim().parse::<i64>().is_ok() => {
+                let text_mode = egui::Id::new(id).with("text");
+                let mut as_text = ui.data(|d| d.get_temp::<bool>(text_mode)).unwrap_or(false);
+                let response = ui
+                    .horizontal(|ui| {
+                        let switch = if as_text { "123" } else { "abc" };
+                        if ui
+                            .small_button(switch)
+                            .on_hover_text(if as_text { "Drag to set the number" } else { "Type a variable name" })
+                            .clicked()
+                        {
+                            as_text = !as_text;
+                        }
+
+                        if as_text {
+                            return ui.add(egui::TextEdit::singleline(value).hint_text(hint));
+                        }
+                        let mut number = value.trim().parse::<i64>().unwrap_or_default();
+                        let low = min.unwrap_or(i64::from(i32::MIN));
+                        let high = max.unwrap_or(i64::from(i32::MAX));
+                        let speed = match (min, max) {
+                            (Some(min), Some(max)) => ((max - min) as f64 / 200.0).max(0.1),
+                            _ => 1.0,
+                        };
+                        let response = ui.add(egui::DragValue::new(&mut number).range(low..=high).speed(speed));
+                        if response.changed() {
+                            *value = number.to_string();
+                        }
+                        response
+                    })
+                    .inner;
+                ui.data_mut(|d| d.insert_temp(text_mode, as_text));
+                response
+            }
+            // An empty number shows its default as a hint; nothing is stored until typed
+            InputWidget::Integer { min, .. } if value.trim().is_empty() => {
+                let default = format!("{hint}: {}", min.unwrap_or(0));
+                ui.add(egui::TextEdit::singleline(value).hint_text(default))
+            }
+            InputWidget::Checkbox => {
+                let mut on = value.trim() == "true";
+                let response = ui.checkbox(&mut on, hint);
+                if response.changed() {
+                    *value = on.to_string();
+                }
+                response
+            }
+            InputWidget::Colour => {
+                let mut colour = parse_hex_colour(value);
+                let response = egui::color_picker::color_edit_button_srgba(
+                    ui,
+                    &mut colour,
+                    egui::color_picker::Alpha::Opaque,
+                );
+                if response.changed() {
+                    *value = format!("#{:02x}{:02x}{:02x}", colour.r(), colour.g(), colour.b());
+                }
+                response
+            }
+            InputWidget::Integer { .. } | InputWidget::Text => {
+                let mut edit = egui::TextEdit::singleline(value).hint_text(hint);
+                if error.is_some() {
+                    edit = edit.background_color(tint);
+                }
+                ui.add(edit)
+            }
+        };
+
+        if let Some(message) = error {
+            response = response.on_hover_text(message);
+        }
+        response
+    }
+
+    /// The unit shown beside an input: its own, or else the one of its custom rule
+    fn input_unit<'a>(&'a self, input: &'a InputDefinition) -> Option<&'a str> {
+        input.constraints.unit.as_deref().or_else(|| {
+            let rule = self.find_rule(input.validation.as_deref()?)?;
+            rule.constraints.unit.as_deref()
+        })
+    }
+
+    /// Checks a value against declarative constraints.
+    ///
+    /// # Returns
+    /// Ok if every constraint holds, otherwise a hint on how to fix the value
+    fn check_constraints(&self, value: &str, constraints: &InputConstraints) -> Result<(), String> {
+        if !constraints.choices.is_empty() && !constraints.choices.iter().any(|c| c == value) {
+            return Err(format!("use one of {}.", constraints.choices.join(", ")));
+        }
+
+        if constraints.is_numeric() {
+            let unit = constraints
+                .unit
+                .as_deref()
+                .map(|unit| format!(" {unit}"))
+                .unwrap_or_default();
+            let in_range = value.parse::<i64>().is_ok_and(|n| {
+                constraints.min.is_none_or(|min| n >= min) && constraints.max.is_none_or(|max| n <= max)
+            });
+            if !in_range {
+                return Err(match (constraints.min, constraints.max) {
+                    (Some(min), Some(max)) => format!("use a whole number from {min} to {max}{unit}."),
+                    (Some(min), None) => format!("use a whole number of at least {min}{unit}."),
+                    (None, _) => format!(
+                        "use a whole number of at most {}{unit}.",
+                        constraints.max.unwrap_or_default()
+                    ),
+                });
+            }
+        }
+
+        if let Some(pattern) = &constraints.pattern
+            && let Some(re) = self.patterns.get(pattern)
+            && !re.is_match(value)
+        {
+            return Err(format!("enter a value matching the pattern {pattern}."));
+        }
+        Ok(())
+    }
+
+    /// Checks an input's value against the constraints written on the input itself.
+    /// A non-bool variable may stand in for a number, as its value is only known at run
+    /// time.
+    fn validate_input_constraints(&self, idx: usize, value: &str, input: &InputDefinition) -> Result<(), String> {
+        let constraints = &input.constraints;
+        let trimmed = value.trim();
+        if constraints.is_empty() {
+            return Ok(());
+        }
+        if constraints.is_numeric()
+            && Self::find_variable(&self.variables_in_scope(idx), trimmed)
+                .is_some_and(|variable| variable.cpp_type != "bool")
+        {
+            return Ok(());
+        }
+        self.check_constraints(trimmed, constraints).map_err(|hint| {
+            if trimmed.is_empty() {
+                format!("'{}' must not be empty.\nSuggested: {hint}", input.name)
+            } else {
+                format!(
+                    "'{}' has value '{trimmed}' which is not allowed here.\nSuggested: {hint}",
+                    input.name
+                )
+            }
+        })
+    }
+
+    /// Checks a pin against the selected board: it must exist, must not be reserved and,
+    /// for "analog_pin", "pwm_pin" and "interrupt_pin", must have that capability.
+    fn validate_board_pin(&self, pin: &str, rule: &str, field_name: &str) -> Result<(), String> {
+        let board = &self.board;
+        let name = BoardProfile::normalise_pin(pin);
+        let analog = board.analog_pins.join(", ");
+
+        if !board.has_pin(&name) {
+            return Err(format!(
+                "'{field_name}' has value '{pin}' which is not a pin on the {}.\n\
+                 Suggested: use {} for digital pins, or {analog} for analog pins.",
+                board.name,
+                BoardProfile::describe_pins(&board.digital_pins)
+            ));
+        }
+        if let Some(reason) = board.reserved_reason(&name) {
+            return Err(format!(
+                "'{field_name}' uses pin {name}, which the {} reserves: {reason}.\n\
+                 Suggested: choose another pin.",
+                board.name
+            ));
+        }
+
+        let number = name.parse::<u8>().ok();
+        let (capable, kind, pins) = match rule {
+            "analog_pin" => (board.analog_pins.contains(&name), "read analog values", analog),
+            "pwm_pin" => (
+                number.is_some_and(|n| board.pwm_pins.contains(&n)),
+                "output PWM (analogWrite)",
+                BoardProfile::describe_pins(&board.pwm_pins),
+            ),
+            "interrupt_pin" => (
+                number.is_some_and(|n| board.interrupt_pins.contains(&n)),
+                "trigger interrupts",
+                BoardProfile::describe_pins(&board.interrupt_pins),
+            ),
+            _ => return Ok(()),
+        };
+        if !capable {
+            return Err(format!(
+                "'{field_name}' uses pin {name}, which cannot {kind} on the {}.\n\
+                 Suggested: use one of {pins}.",
+                board.name
+            ));
         }
         Ok(())
     }
 
-    /// Validates all required inputs across every non-palette block.
-    /// Returns the first error found, formatted ready for the popup.
-    fn validate_block_inputs(&self) -> Result<(), String> {
-        for block in &self.sections {
+    /// Validates the value typed into one input of block `idx` against the input's named
+    /// rule and its inline constraints. Inputs with neither are always valid.
+    fn validate_typed_input(&self, idx: usize, input: &InputDefinition) -> Result<(), String> {
+        let value = self.sections[idx]
+            .input_values
+            .get(&input.name)
+            .map(|s| s.as_str())
+            .unwrap_or("");
+        self.validate_input_text(idx, input, value)
+    }
+
+    /// Validates a value for one input of block `idx`, as if it had been typed there
+    fn validate_input_text(&self, idx: usize, input: &InputDefinition, value: &str) -> Result<(), String> {
+        if let Some(rule) = &input.validation {
+            self.validate_input_with_variables(idx, value, rule, &input.name)?;
+        }
+        self.validate_input_constraints(idx, value, input)
+    }
+
+    /// Finds a corrected value for an invalid input: the matching choice in the right
+    /// case, a number clamped into range, a lowercase boolean literal or the closest
+    /// variable name. A correction is only offered if it passes validation.
+    fn input_fix(&self, idx: usize, input: &InputDefinition) -> Option<QuickFix> {
+        let value = self.sections[idx].input_values.get(&input.name)?.trim();
+        if value.is_empty() {
+            return None;
+        }
+
+        let candidate = match self.input_widget(idx, input) {
+            InputWidget::Choice(options) => {
+                options.into_iter().find(|option| option.eq_ignore_ascii_case(value))
+            }
+            InputWidget::Integer { min, max } => value.parse::<i64>().ok().map(|n| {
+                n.max(min.unwrap_or(i64::MIN)).min(max.unwrap_or(i64::MAX)).to_string()
+            }),
+            _ => None,
+        }
+        .or_else(|| match input.validation.as_deref() {
+            Some("arduino_condition")
+                if value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false") =>
+            {
+                Some(value.to_ascii_lowercase())
+            }
+            Some("variable_name") => self
+                .variables_in_scope(idx)
+                .into_iter()
+                .map(|variable| (edit_distance(value, &variable.name), variable.name))
+                .min()
+                .filter(|(distance, _)| *distance <= 2)
+                .map(|(_, name)| name),
+            _ => None,
+        })?;
+
+        if candidate == value || self.validate_input_text(idx, input, &candidate).is_err() {
+            return None;
+        }
+        Some(QuickFix::SetInput {
+            instance_id: self.sections[idx].instance_id.clone(),
+            input: input.name.clone(),
+            value: candidate,
+        })
+    }
+
+    /// Validates all required inputs across every non-palette block, reporting every
+    /// invalid input against its block.
+    fn input_diagnostics(&self) -> Vec<Diagnostic> {
+        let mut out = Vec::new();
+        for (idx, block) in self.sections.iter().enumerate() {
             if block.is_palette {
                 continue;
             }
-            let block_name = block
-                .unique_id
-                .as_deref()
-                .unwrap_or(&block.id)
-                .to_string();
             for input in &block.inputs {
-                if let Some(ref rule) = input.validation {
-                    let value = block
-                        .input_values
-                        .get(&input.name)
-                        .map(|s| s.as_str())
-                        .unwrap_or("");
-                    if let Err(msg) =
-                        Self::validate_input_value(value, rule, &input.name)
-                    {
-                        return Err(format!("Block '{block_name}':\n{msg}"));
-                    }
+                // Inputs filled by a reporter block have no typed value to check,
+                // but a variable reporter must hold the type the socket expects
+                let (result, fix) = match self.input_child(idx, &input.name) {
+                    Some(child) => (self.validate_reporter_type(child, input), None),
+                    None => (self.validate_typed_input(idx, input), Some(input)),
+                };
+                if let Err(msg) = result {
+                    out.push(
+                        Diagnostic::from_message(
+                            Severity::Error,
+                            Some(&block.instance_id),
+                            Some(&input.name),
+                            &msg,
+                        )
+                        .with_fix(fix.and_then(|input| self.input_fix(idx, input))),
+                    );
                 }
             }
         }
-        Ok(())
+        out
+    }
+
+    // ------------------------------------------------------------------
+    // Variables
+    // ------------------------------------------------------------------
+
+    /// Finds a variable by name in a scope (see `variables_in_scope`)
+    fn find_variable<'a>(scope: &'a [VariableDefinition], name: &str) -> Option<&'a VariableDefinition> {
+        scope.iter().find(|variable| variable.name == name.trim())
+    }
+
+    /// Returns the variables visible to a block: the parameters of the function it sits in
+    /// (if its stack starts with a "Define function" block), then the project variables.
+    fn variables_in_scope(&self, idx: usize) -> Vec<VariableDefinition> {
+        let root = self.stack_root(idx);
+        let mut scope = if self.sections[root].hat == Some(HatKind::Function) {
+            let parameters = Self::resolve_input_value(&self.sections[root].input_values, "Parameters")
+                .unwrap_or("");
+            Self::parse_function_parameters(parameters).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        scope.extend(self.variables.iter().cloned());
+        scope
+    }
+
+    /// Builds the "Suggested:" line for an unknown variable name, pointing at the closest
+    /// name in scope when it looks like a typo.
+    fn suggest_variable_name(scope: &[VariableDefinition], name: &str) -> String {
+        let closest = scope
+            .iter()
+            .map(|variable| (edit_distance(name, &variable.name), &variable.name))
+            .min();
+
+        match closest {
+            Some((distance, closest)) if distance <= 2 => {
+                format!("Suggested: did you mean '{closest}'?")
+            }
+            _ if scope.is_empty() => {
+                "Suggested: add the variable in the Variables window first.".to_string()
+            }
+            _ => {
+                let names: Vec<&str> = scope.iter().map(|v| v.name.as_str()).collect();
+                format!(
+                    "Suggested: use one of the declared variables ({}) or add it in the Variables window.",
+                    names.join(", ")
+                )
+            }
+        }
+    }
+
+    /// Checks a literal value against a C++ variable type
+    fn validate_value_for_type(value: &str, cpp_type: &str, field_name: &str) -> Result<(), String> {
+        let trimmed = value.trim();
+        let valid = match cpp_type {
+            "int" => trimmed.parse::<i16>().is_ok(),
+            "long" => trimmed.parse::<i32>().is_ok(),
+            "unsigned long" => trimmed.parse::<u32>().is_ok(),
+            "byte" => trimmed.parse::<u8>().is_ok(),
+            "float" => trimmed.parse::<f32>().is_ok(),
+            "bool" => matches!(trimmed, "true" | "false"),
+            _ => true,
+        };
+
+        if valid {
+            return Ok(());
+        }
+
+        let example = match cpp_type {
+            "int" => "a whole number from -32768 to 32767",
+            "long" => "a whole number from -2147483648 to 2147483647",
+            "unsigned long" => "a whole number from 0 to 4294967295",
+            "byte" => "a whole number from 0 to 255",
+            "float" => "a number such as 1.5",
+            _ => "true or false (lowercase)",
+        };
+        Err(format!(
+            "'{field_name}' has value '{trimmed}' which is not a valid {cpp_type}.\n\
+             Suggested: use {example}."
+        ))
     }
 
-    /// Validates that all blocks use the same Pin value (if they have one)
-    /// Prevents mixing of pins across different code blocks
-    fn validate_pin_consistency(&self) -> Result<(), String> {
-        let mut expected_pin: Option<String> = None;
-        let mut pin_sources: Vec<String> = Vec::new();
+    /// True for names the Arduino core defines on the selected board: the constants and
+    /// the board's analog pins
+    fn is_builtin_name(&self, name: &str) -> bool {
+        ARDUINO_CONSTANTS.contains(&name) || self.board.analog_pins.iter().any(|pin| pin == name)
+    }
 
-        for block in self.sections.iter().filter(|b| !b.is_palette) {
-            let pin_value = Self::resolve_input_value(&block.input_values, "Pin")
-                .map(|v| v.trim())
-                .filter(|v| !v.is_empty());
+    /// Checks that every identifier in an expression is a variable in scope or a known
+    /// Arduino constant, so typos are caught before export.
+    fn validate_expression_identifiers(
+        &self,
+        scope: &[VariableDefinition],
+        value: &str,
+        field_name: &str,
+    ) -> Result<(), String> {
+        let trimmed = value.trim();
+        let Some(expr) = ir::parse_expr(trimmed) else {
+            return Err(format!(
+                "'{field_name}' has value '{trimmed}' which is not a valid expression.\n\
+                 Suggested: check for missing brackets or operators."
+            ));
+        };
 
-            let Some(pin) = pin_value else {
+        let mut identifiers = Vec::new();
+        expr.identifiers(&mut identifiers);
+        for name in identifiers {
+            if name.contains('.') || self.is_builtin_name(name) {
                 continue;
-            };
+            }
+            if Self::find_variable(scope, name).is_none() {
+                return Err(format!(
+                    "'{field_name}' uses '{name}', which is not a declared variable.\n{}",
+                    Self::suggest_variable_name(scope, name)
+                ));
+            }
+        }
+        Ok(())
+    }
 
-            let block_name = block.unique_id.as_deref().unwrap_or(&block.id);
-            pin_sources.push(format!("{} -> {}", block_name, pin));
+    /// Validates an input value of block `idx`, taking the variables in scope into account:
+    /// "variable_name" inputs must name a declared variable, "variable_value" inputs must
+    /// suit the type of the variable named in the same block, and a variable may stand in
+    /// for a pin, state or number when its type fits. Function definitions and calls are
+    /// checked against the "Define function" blocks in the workspace.
+    fn validate_input_with_variables(
+        &self,
+        idx: usize,
+        value: &str,
+        rule: &str,
+        field_name: &str,
+    ) -> Result<(), String> {
+        let block = &self.sections[idx];
+        let scope = self.variables_in_scope(idx);
+        let trimmed = value.trim();
 
-            if let Some(ref expected) = expected_pin {
-                if !expected.eq_ignore_ascii_case(pin) {
+        match rule {
+            "variable_name" => {
+                if trimmed.is_empty() {
                     return Err(format!(
-                        "Pin mismatch detected across code blocks.\nAll blocks with a Pin field must use the same value.\nFound: {}\nExpected (first value): {}\nDetails:\n{}",
-                        pin,
-                        expected,
-                        pin_sources.join("\n")
+                        "'{field_name}' must not be empty.\nSuggested: enter the name of a variable."
+                    ));
+                }
+                if Self::find_variable(&scope, trimmed).is_none() {
+                    return Err(format!(
+                        "'{field_name}' refers to '{trimmed}', which is not a declared variable.\n{}",
+                        Self::suggest_variable_name(&scope, trimmed)
                     ));
                 }
+                Ok(())
+            }
+            "variable_value" => {
+                let target = block
+                    .inputs
+                    .iter()
+                    .find(|input| input.validation.as_deref() == Some("variable_name"))
+                    .and_then(|input| Self::resolve_input_value(&block.input_values, &input.name))
+                    .and_then(|name| Self::find_variable(&scope, name));
+
+                if trimmed.is_empty() {
+                    return self.validate_input_value(value, rule, field_name);
+                }
+                // A literal must suit the variable's type; anything else is an expression
+                let is_literal =
+                    trimmed.parse::<f64>().is_ok() || matches!(trimmed, "true" | "false");
+                match target {
+                    Some(variable) if is_literal => {
+                        Self::validate_value_for_type(trimmed, &variable.cpp_type, field_name)
+                    }
+                    _ => self.validate_expression_identifiers(&scope, trimmed, field_name),
+                }
+            }
+            "arduino_condition" => {
+                self.validate_input_value(value, rule, field_name)?;
+                self.validate_expression_identifiers(&scope, trimmed, field_name)
+            }
+            "function_name" => {
+                self.validate_input_value(value, rule, field_name)?;
+                self.validate_function_name(idx, trimmed, field_name)
+            }
+            "function_parameters" => Self::parse_function_parameters(trimmed).map(|_| ()),
+            "function_call" => {
+                self.validate_input_value(value, rule, field_name)?;
+                self.find_function(trimmed, field_name).map(|_| ())
+            }
+            "function_arguments" => {
+                let name = Self::resolve_input_value(&block.input_values, "Name").unwrap_or("");
+                // An unknown function is reported on its Name input
+                let Ok(function) = self.find_function(name, "Name") else {
+                    return Ok(());
+                };
+                self.validate_call_arguments(function, &scope, trimmed, field_name)
+            }
+            // Pins, states and numbers, including custom number rules
+            _ if matches!(
+                rule,
+                "arduino_pin" | "analog_pin" | "pwm_pin" | "interrupt_pin" | "arduino_state"
+                    | "positive_integer"
+            ) || self.find_rule(rule).is_some_and(|custom| custom.constraints.is_numeric()) =>
+            {
+                match Self::find_variable(&scope, trimmed) {
+                    Some(variable) if variable.cpp_type == "bool" && rule != "arduino_state" => {
+                        Err(format!(
+                            "'{field_name}' uses the variable '{}', which is a bool.\n\
+                             Suggested: use a number variable (int, long or byte) here.",
+                            variable.name
+                        ))
+                    }
+                    Some(_) => Ok(()),
+                    None => self.validate_input_value(value, rule, field_name),
+                }
+            }
+            _ => self.validate_input_value(value, rule, field_name),
+        }
+    }
+
+    /// Checks that a variable reporter plugged into an input holds the type the socket
+    /// expects (a bool for "boolean" sockets, a number for "number" sockets)
+    fn validate_reporter_type(&self, reporter: usize, input: &InputDefinition) -> Result<(), String> {
+        let block = &self.sections[reporter];
+        if block.output.as_deref() != Some("variable") {
+            return Ok(());
+        }
+
+        let name = block
+            .inputs
+            .iter()
+            .find(|candidate| candidate.validation.as_deref() == Some("variable_name"))
+            .and_then(|candidate| Self::resolve_input_value(&block.input_values, &candidate.name))
+            .unwrap_or("");
+        let scope = self.variables_in_scope(reporter);
+        let Some(variable) = Self::find_variable(&scope, name) else {
+            return Err(format!(
+                "'{}' uses the variable '{}', which is not declared.\n{}",
+                input.name,
+                name.trim(),
+                Self::suggest_variable_name(&scope, name.trim())
+            ));
+        };
+
+        let is_bool = variable.cpp_type == "bool";
+        match input.input_type.as_deref() {
+            Some("boolean") if !is_bool => Err(format!(
+                "'{}' expects true/false, but the variable '{}' has type {}.\n\
+                 Suggested: compare it with a value, e.g. with a '>' block.",
+                input.name, variable.name, variable.cpp_type
+            )),
+            Some("number") if is_bool => Err(format!(
+                "'{}' expects a number, but the variable '{}' is a bool.\n\
+                 Suggested: use a number variable here.",
+                input.name, variable.name
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validates the variable table: names must be unique C++ identifiers that do not clash
+    /// with Arduino names, types must be supported and initial values must suit the type.
+    /// Reports the first problem with each variable.
+    fn variable_diagnostics(&self) -> Vec<Diagnostic> {
+        let mut seen = HashSet::new();
+        let mut out = Vec::new();
+        for variable in &self.variables {
+            let name = variable.name.trim();
+            let problem = if !ir::is_identifier(name) {
+                Err(format!(
+                    "Variable '{name}' is not a valid name.\n\
+                     Suggested: use letters, digits and underscores, starting with a letter (e.g. ledState)."
+                ))
+            } else if self.is_builtin_name(name) || matches!(name, "setup" | "loop") {
+                Err(format!(
+                    "Variable '{name}' clashes with a built-in Arduino name.\n\
+                     Suggested: choose a different name."
+                ))
+            } else if !seen.insert(name) {
+                Err(format!(
+                    "Variable '{name}' is declared more than once.\n\
+                     Suggested: rename or remove the duplicate."
+                ))
+            } else if !VARIABLE_TYPES.contains(&variable.cpp_type.as_str()) {
+                Err(format!(
+                    "Variable '{name}' has unsupported type '{}'.\nSuggested: use one of {}.",
+                    variable.cpp_type,
+                    VARIABLE_TYPES.join(", ")
+                ))
+            } else if !variable.initial_value.trim().is_empty() {
+                Self::validate_value_for_type(
+                    &variable.initial_value,
+                    &variable.cpp_type,
+                    &format!("Initial value of {name}"),
+                )
             } else {
-                expected_pin = Some(pin.to_string());
+                Ok(())
+            };
+
+            if let Err(msg) = problem {
+                out.push(Diagnostic::from_message(Severity::Error, None, None, &msg));
+            }
+        }
+        out
+    }
+
+    /// Builds the global declarations for the project's variables
+    fn variable_declarations(&self) -> Vec<Stmt> {
+        self.variables
+            .iter()
+            .map(|variable| {
+                let initial = match variable.initial_value.trim() {
+                    "" if variable.cpp_type == "bool" => "false",
+                    "" => "0",
+                    value => value,
+                };
+                Stmt::new(StmtKind::Decl {
+                    ty: variable.cpp_type.clone(),
+                    name: variable.name.trim().to_string(),
+                    init: ir::parse_expr(initial),
+                })
+            })
+            .collect()
+    }
+
+    // ------------------------------------------------------------------
+    // Functions
+    // ------------------------------------------------------------------
+
+    /// Parses a function parameter list such as "int pin, long wait" into typed names.
+    /// An empty list is valid (a function without parameters).
+    fn parse_function_parameters(text: &str) -> Result<Vec<VariableDefinition>, String> {
+        let mut parameters: Vec<VariableDefinition> = Vec::new();
+        for parameter in text.split(',').map(str::trim) {
+            if parameter.is_empty() && text.trim().is_empty() {
+                break;
+            }
+
+            let parsed = VARIABLE_TYPES.iter().find_map(|ty| {
+                let name = parameter.strip_prefix(ty)?;
+                (name.starts_with(char::is_whitespace) && ir::is_identifier(name.trim()))
+                    .then(|| (ty, name.trim()))
+            });
+            let Some((ty, name)) = parsed else {
+                return Err(format!(
+                    "'Parameters' has '{parameter}' which is not a valid parameter.\n\
+                     Suggested: write each parameter as a type and a name, separated by commas \
+                     (e.g. int pin, long wait). Types: {}.",
+                    VARIABLE_TYPES.join(", ")
+                ));
+            };
+            if parameters.iter().any(|existing| existing.name == name) {
+                return Err(format!(
+                    "'Parameters' names '{name}' more than once.\n\
+                     Suggested: give each parameter a different name."
+                ));
+            }
+            parameters.push(VariableDefinition {
+                name: name.to_string(),
+                cpp_type: ty.to_string(),
+                initial_value: String::new(),
+            });
+        }
+        Ok(parameters)
+    }
+
+    /// Returns the "Define function" blocks in the workspace with the names they define
+    fn defined_functions(&self) -> Vec<(usize, &str)> {
+        self.sections
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| !block.is_palette && block.hat == Some(HatKind::Function))
+            .map(|(idx, block)| {
+                let name = Self::resolve_input_value(&block.input_values, "Name").unwrap_or("");
+                (idx, name.trim())
+            })
+            .collect()
+    }
+
+    /// Finds the "Define function" block for a called name, suggesting the closest
+    /// defined name when there is none.
+    fn find_function(&self, name: &str, field_name: &str) -> Result<usize, String> {
+        let name = name.trim();
+        let functions = self.defined_functions();
+        if let Some(&(idx, _)) = functions.iter().find(|(_, defined)| *defined == name) {
+            return Ok(idx);
+        }
+
+        let closest = functions
+            .iter()
+            .map(|(_, defined)| (edit_distance(name, defined), *defined))
+            .min();
+        let suggestion = match closest {
+            Some((distance, closest)) if distance <= 2 => format!("did you mean '{closest}'?"),
+            Some(_) => {
+                let names: Vec<&str> = functions.iter().map(|(_, defined)| *defined).collect();
+                format!("call one of the defined functions ({}).", names.join(", "))
+            }
+            None => "add a \"Define function\" block first.".to_string(),
+        };
+        Err(format!(
+            "'{field_name}' calls '{name}', which is not a defined function.\nSuggested: {suggestion}"
+        ))
+    }
+
+    /// Checks the name of a "Define function" block: a valid C++ identifier that is not
+    /// already used by another function, a variable or the Arduino core.
+    fn validate_function_name(&self, idx: usize, name: &str, field_name: &str) -> Result<(), String> {
+        if !ir::is_identifier(name) {
+            return Err(format!(
+                "'{field_name}' has value '{name}' which is not a valid function name.\n\
+                 Suggested: use letters, digits and underscores, starting with a letter (e.g. blinkTwice)."
+            ));
+        }
+        if self.is_builtin_name(name) || matches!(name, "setup" | "loop") {
+            return Err(format!(
+                "'{field_name}' uses '{name}', which clashes with a built-in Arduino name.\n\
+                 Suggested: choose a different name."
+            ));
+        }
+        if Self::find_variable(&self.variables, name).is_some() {
+            return Err(format!(
+                "'{field_name}' uses '{name}', which is already a variable.\n\
+                 Suggested: give the function a different name."
+            ));
+        }
+        if self
+            .defined_functions()
+            .iter()
+            .any(|&(other, defined)| other != idx && defined == name)
+        {
+            return Err(format!(
+                "'{field_name}': the function '{name}' is defined more than once.\n\
+                 Suggested: rename or remove one of the \"Define function\" blocks."
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the arguments of a call against the called function's parameter list:
+    /// the count must match and every identifier used must be in scope.
+    fn validate_call_arguments(
+        &self,
+        function: usize,
+        scope: &[VariableDefinition],
+        arguments: &str,
+        field_name: &str,
+    ) -> Result<(), String> {
+        let definition = &self.sections[function];
+        let name = Self::resolve_input_value(&definition.input_values, "Name").unwrap_or("");
+        let parameter_text =
+            Self::resolve_input_value(&definition.input_values, "Parameters").unwrap_or("");
+        // Invalid parameter lists are reported on the definition itself
+        let Ok(parameters) = Self::parse_function_parameters(parameter_text) else {
+            return Ok(());
+        };
+
+        let args = match ir::parse_expr(&format!("{name}({arguments})")) {
+            Some(Expr::Call { args, .. }) => args,
+            _ => {
+                return Err(format!(
+                    "'{field_name}' has value '{arguments}' which is not a valid argument list.\n\
+                     Suggested: separate the values with commas (e.g. 13, 500)."
+                ));
             }
+        };
+
+        if args.len() != parameters.len() {
+            let expected: Vec<String> = parameters
+                .iter()
+                .map(|parameter| format!("{} {}", parameter.cpp_type, parameter.name))
+                .collect();
+            return Err(format!(
+                "'{field_name}' passes {} value{} but '{}' takes {}.\nSuggested: pass values for ({}).",
+                args.len(),
+                if args.len() == 1 { "" } else { "s" },
+                name.trim(),
+                parameters.len(),
+                expected.join(", ")
+            ));
         }
 
+        for arg in &args {
+            self.validate_expression_identifiers(scope, &arg.to_source(), field_name)?;
+        }
         Ok(())
     }
 
+    /// Keeps one "Call" palette entry per function defined in the workspace. Entries are
+    /// clones of the generic "Call_function" palette block with the Name input preset.
+    /// Nothing changes until the set of names does; entries are then renamed in place,
+    /// new ones appended and only surplus ones removed, so the indices of other blocks
+    /// (open editors, drags) stay valid while a function name is typed.
+    fn sync_procedure_palette(&mut self) {
+        let mut names: Vec<String> = self
+            .defined_functions()
+            .into_iter()
+            .map(|(_, name)| name.to_string())
+            .filter(|name| ir::is_identifier(name))
+            .collect();
+        names.sort();
+        names.dedup();
+
+        let entries: Vec<usize> = self
+            .sections
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| block.procedure.is_some())
+            .map(|(idx, _)| idx)
+            .collect();
+        let mut current: Vec<&str> = entries
+            .iter()
+            .filter_map(|&idx| self.sections[idx].procedure.as_deref())
+            .collect();
+        current.sort_unstable();
+        if current == names {
+            return;
+        }
+
+        // Entries for names that are still defined stay as they are; the others are
+        // reused for the new names
+        let (kept, mut free): (Vec<usize>, Vec<usize>) = entries.into_iter().partition(|&idx| {
+            self.sections[idx]
+                .procedure
+                .as_ref()
+                .is_some_and(|name| names.contains(name))
+        });
+        let new_names: Vec<String> = names
+            .iter()
+            .filter(|name| !kept.iter().any(|&idx| self.sections[idx].procedure.as_ref() == Some(*name)))
+            .cloned()
+            .collect();
+
+        let template = self
+            .sections
+            .iter()
+            .position(|block| block.is_palette && block.unique_id.as_deref() == Some("Call_function"));
+        for name in new_names {
+            let idx = match free.pop() {
+                Some(idx) => idx,
+                None => {
+                    let Some(template) = template else {
+                        break;
+                    };
+                    let mut entry = self.sections[template].clone();
+                    Self::initialise_runtime_fields(&mut entry, egui::pos2(20.0, 0.0), true);
+                    self.sections.push(entry);
+                    self.sections.len() - 1
+                }
+            };
+            let entry = &mut self.sections[idx];
+            entry.shown_element = Some(format!("Call {name}"));
+            entry.input_values.insert("Name".to_string(), name.clone());
+            entry.procedure = Some(name);
+        }
+        self.remove_blocks(free);
+
+        // Lay the entries out below the loaded palette blocks in name order
+        let loaded = self
+            .sections
+            .iter()
+            .filter(|block| block.is_palette && block.procedure.is_none())
+            .count();
+        let mut entries: Vec<usize> = self
+            .sections
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| block.procedure.is_some())
+            .map(|(idx, _)| idx)
+            .collect();
+        entries.sort_by(|a, b| self.sections[*a].procedure.cmp(&self.sections[*b].procedure));
+        for (row, idx) in entries.into_iter().enumerate() {
+            self.sections[idx].pos.y =
+                BLOCKS_START_Y + (loaded + row) as f32 * (GLOBAL_Y + PALETTE_BLOCK_GAP);
+        }
+    }
+
+    /// Analyses how the blocks in hat-started stacks use each pin: which blocks set its
+    /// mode with pinMode and to what, and which blocks read or write it. Returns warnings
+    /// for uses that will not behave as expected, such as writing to a pin that was never
+    /// set to OUTPUT or reading a pin set to OUTPUT, each against the block and pin input
+    /// at fault. Pins given by a variable or a reporter are only known at run time and are
+    /// skipped.
+    fn pin_usage_diagnostics(&self) -> Vec<Diagnostic> {
+        // (access, mode, block index, pin input) for each use of a pin, in stack order
+        type PinUses<'a> = Vec<(PinAccess, Option<String>, usize, &'a str)>;
+        let mut uses: Vec<(String, PinUses)> = Vec::new();
+
+        for root in self.root_block_indices() {
+            if self.sections[root].hat.is_none() {
+                continue;
+            }
+            let mut blocks = Vec::new();
+            self.collect_descendants(root, &mut blocks);
+
+            for idx in blocks {
+                let block = &self.sections[idx];
+                for usage in &block.pin_usage {
+                    if self.input_child(idx, &usage.input).is_some() {
+                        continue;
+                    }
+                    let Some(pin) = Self::resolve_input_value(&block.input_values, &usage.input)
+                        .map(BoardProfile::normalise_pin)
+                        .filter(|pin| self.board.has_pin(pin))
+                    else {
+                        continue;
+                    };
+
+                    let mode = usage.mode.as_deref().map(|mode| {
+                        let key = mode.trim_start_matches('{').trim_end_matches('}');
+                        Self::resolve_input_value(&block.input_values, key)
+                            .unwrap_or(mode)
+                            .trim()
+                            .to_string()
+                    });
+                    let entry = (usage.access, mode, idx, usage.input.as_str());
+                    match uses.iter_mut().find(|(existing, _)| *existing == pin) {
+                        Some((_, list)) => list.push(entry),
+                        None => uses.push((pin, vec![entry])),
+                    }
+                }
+            }
+        }
+
+        let mut warnings = Vec::new();
+        let mut warn = |idx: usize, input: &str, text: String, fix: Option<QuickFix>| {
+            warnings.push(
+                Diagnostic::from_message(
+                    Severity::Warning,
+                    Some(&self.sections[idx].instance_id),
+                    Some(input),
+                    &text,
+                )
+                .with_fix(fix),
+            );
+        };
+        for (pin, list) in &uses {
+            let modes: Vec<(&str, usize, &str)> = list
+                .iter()
+                .filter(|(access, _, _, _)| *access == PinAccess::Configure)
+                .filter_map(|(_, mode, idx, input)| Some((mode.as_deref()?, *idx, *input)))
+                .collect();
+            let has_mode = |wanted: &str| modes.iter().any(|(mode, _, _)| *mode == wanted);
+            let first_with = |access: PinAccess| {
+                list.iter()
+                    .find(|(a, _, _, _)| *a == access)
+                    .map(|(_, _, idx, input)| (*idx, *input))
+            };
+
+            let mut distinct: Vec<&str> = modes.iter().map(|(mode, _, _)| *mode).collect();
+            distinct.sort_unstable();
+            distinct.dedup();
+            if distinct.len() > 1 {
+                let setters: Vec<String> = modes
+                    .iter()
+                    .map(|(mode, idx, _)| format!("'{}' ({mode})", self.block_unique_id(*idx)))
+                    .collect();
+                // Flag the first block that disagrees with the pin's first mode
+                let (first_mode, _, _) = modes[0];
+                if let Some((_, idx, input)) = modes.iter().find(|(mode, _, _)| *mode != first_mode) {
+                    warn(
+                        *idx,
+                        input,
+                        format!(
+                            "Pin {pin} is set to different modes by {}.\n\
+                             Suggested: configure each pin once, with the mode it is used in.",
+                            setters.join(", ")
+                        ),
+                        None,
+                    );
+                }
+            }
+
+            if let Some((idx, input)) = first_with(PinAccess::Write) {
+                let writer = self.block_unique_id(idx);
+                if has_mode("INPUT") || has_mode("INPUT_PULLUP") {
+                    warn(
+                        idx,
+                        input,
+                        format!(
+                            "Pin {pin} is written by '{writer}' but is set as an input; writing to an \
+                             input pin only switches its pull-up resistor.\n\
+                             Suggested: set pin {pin} to OUTPUT with a PinMode block."
+                        ),
+                        None,
+                    );
+                } else if !has_mode("OUTPUT") {
+                    warn(
+                        idx,
+                        input,
+                        format!(
+                            "Pin {pin} is written by '{writer}' but never set to OUTPUT.\n\
+                             Suggested: add a PinMode block for pin {pin} under \"On start\"."
+                        ),
+                        Some(QuickFix::AddToSetup {
+                            block: "PinMode".to_string(),
+                            values: vec![("Pin".to_string(), pin.clone())],
+                        }),
+                    );
+                }
+            }
+
+            for (access, what) in [(PinAccess::Read, "read"), (PinAccess::AnalogRead, "read as analog")] {
+                if let Some((idx, input)) = first_with(access)
+                    && has_mode("OUTPUT")
+                    && distinct.len() == 1
+                {
+                    let reader = self.block_unique_id(idx);
+                    warn(
+                        idx,
+                        input,
+                        format!(
+                            "Pin {pin} is {what} by '{reader}' but is set to OUTPUT, so it reads back \
+                             the value the sketch wrote.\n\
+                             Suggested: set pin {pin} to INPUT or INPUT_PULLUP instead."
+                        ),
+                        None,
+                    );
+                }
+            }
+
+            if let Some((servo, _)) = first_with(PinAccess::Servo) {
+                let others: Vec<(usize, &str)> = list
+                    .iter()
+                    .filter(|(access, _, _, _)| matches!(access, PinAccess::Write | PinAccess::AnalogWrite))
+                    .map(|(_, _, idx, input)| (*idx, *input))
+                    .collect();
+                if let Some(&(idx, input)) = others.first() {
+                    let names: Vec<String> =
+                        others.iter().map(|(idx, _)| self.block_unique_id(*idx)).collect();
+                    warn(
+                        idx,
+                        input,
+                        format!(
+                            "Pin {pin} drives a servo ('{}') but is also written by '{}'.\n\
+                             Suggested: use a separate pin for each device.",
+                            self.block_unique_id(servo),
+                            names.join("', '")
+                        ),
+                        None,
+                    );
+                }
+            }
+        }
+
+        warnings
+    }
+
+    /// Describes a stack by its first block and length, e.g. "'delay' (3 blocks)"
+    fn describe_stack(&self, root: usize) -> String {
+        let mut blocks = Vec::new();
+        self.collect_descendants(root, &mut blocks);
+        let count = blocks.len();
+        format!(
+            "'{}' ({} block{})",
+            self.block_unique_id(root),
+            count,
+            if count == 1 { "" } else { "s" }
+        )
+    }
+
+    /// Returns the roots of stacks that do not start with an "On start" or "Forever" hat
+    fn unattached_stack_roots(&self) -> Vec<usize> {
+        self.root_block_indices()
+            .into_iter()
+            .filter(|&root| self.sections[root].hat.is_none())
+            .collect()
+    }
+
+    /// Checks that every stack starts with a hat block, so no code is silently dropped
+    /// from (or misplaced in) the exported sketch.
+    fn validate_stack_attachment(&self) -> Result<(), String> {
+        let unattached = self.unattached_stack_roots();
+        if unattached.is_empty() {
+            return Ok(());
+        }
+
+        let stacks: Vec<String> = unattached
+            .iter()
+            .map(|&root| format!("- stack starting at {}", self.describe_stack(root)))
+            .collect();
+        Err(format!(
+            "{} stack{} not attached to an \"On start\" or \"Forever\" block:\n{}\nSuggested: drag each stack under an \"On start\" block (runs once) or a \"Forever\" block (runs repeatedly).",
+            unattached.len(),
+            if unattached.len() == 1 { " is" } else { "s are" },
+            stacks.join("\n")
+        ))
+    }
+
+    /// Reports each stack that does not start with a hat block, against its first block
+    fn stack_diagnostics(&self) -> Vec<Diagnostic> {
+        self.unattached_stack_roots()
+            .into_iter()
+            .map(|root| {
+                Diagnostic::from_message(
+                    Severity::Error,
+                    Some(&self.sections[root].instance_id),
+                    None,
+                    &format!(
+                        "Stack starting at {} is not attached to an \"On start\" or \"Forever\" block.\nSuggested: drag it under an \"On start\" block (runs once) or a \"Forever\" block (runs repeatedly).",
+                        self.describe_stack(root)
+                    ),
+                )
+            })
+            .collect()
+    }
+
     fn root_block_indices(&self) -> Vec<usize> {
         let mut roots: Vec<usize> = self
             .sections
@@ -703,27 +2482,31 @@ impl BlocksForArduino {
         children
     }
 
-    /// Indents a line of code by the specified number of levels (2 spaces per level)
-    /// Empty lines are returned unchanged
-    fn indent_line(line: &str, level: usize) -> String {
-        if line.trim().is_empty() {
-            return String::new();
+    /// Fills a block's code template with its input values, substituting the generated
+    /// expression of any reporter block plugged into an input.
+    fn fill_block_template(&self, idx: usize, template: &str) -> Result<String, String> {
+        let block = &self.sections[idx];
+        let mut plugged = HashMap::new();
+        for input in &block.inputs {
+            if let Some(child) = self.input_child(idx, &input.name) {
+                plugged.insert(input.name.clone(), self.render_expression(child)?);
+            }
         }
 
-        let mut out = String::new();
-        out.push_str(&"  ".repeat(level));
-        out.push_str(line);
-        out
+        let (resolved, missing) = Self::fill_template_with_inputs(template, block, &plugged);
+        if !missing.is_empty() {
+            return Err(format!(
+                "Block '{}' is missing values for placeholders: {}.",
+                block.unique_id.as_deref().unwrap_or(&block.id),
+                missing.join(", ")
+            ));
+        }
+        Ok(resolved)
     }
 
-    /// Recursively renders a block and its children to Arduino code.
-    /// Handles template substitution, indentation, and brace nesting for control structures.
-    fn render_block_recursive(
-        &self,
-        idx: usize,
-        indent_level: usize,
-        out: &mut Vec<String>,
-    ) -> Result<(), String> {
+    /// Generates the C++ expression for a reporter block. Expressions made of more than a
+    /// single term are wrapped in parentheses so they keep their meaning inside the parent.
+    fn render_expression(&self, idx: usize) -> Result<String, String> {
         let block = &self.sections[idx];
         let block_name = block.unique_id.as_deref().unwrap_or(&block.id);
         let template = block.code_equivelant.as_deref().ok_or_else(|| {
@@ -733,47 +2516,134 @@ impl BlocksForArduino {
             )
         })?;
 
-        let (resolved, missing) = Self::fill_template_with_inputs(template, block);
-        if !missing.is_empty() {
-            return Err(format!(
-                "Block '{}' is missing values for placeholders: {}.",
+        let resolved = self.fill_block_template(idx, template)?;
+        let expr = ir::parse_expr(resolved.trim()).ok_or_else(|| {
+            format!(
+                "Block '{}' does not generate a valid expression: {}",
                 block_name,
-                missing.join(", ")
-            ));
+                resolved.trim()
+            )
+        })?;
+
+        Ok(match expr {
+            Expr::Binary { .. } | Expr::Assign { .. } | Expr::Unary { .. } => {
+                format!("({})", expr.to_source())
+            }
+            _ => expr.to_source(),
+        })
+    }
+
+    /// Fills a block's code template and parses it into IR statements tagged with the
+    /// block's instance_id.
+    fn render_template(&self, idx: usize, template: &str) -> Result<Vec<Stmt>, String> {
+        let block = &self.sections[idx];
+        let block_name = block.unique_id.as_deref().unwrap_or(&block.id);
+        let resolved = self.fill_block_template(idx, template)?;
+
+        let mut stmts = ir::parse_template(&resolved)
+            .map_err(|e| format!("Block '{}' has an invalid template: {}", block_name, e))?;
+        for stmt in &mut stmts {
+            stmt.set_origin(&block.instance_id);
         }
+        Ok(stmts)
+    }
 
-        let template_lines: Vec<String> = resolved.lines().map(|line| line.trim_end().to_string()).collect();
-        let children = self.sorted_child_indices(idx);
-        let close_idx = template_lines.iter().rposition(|line| line.trim() == "}");
-        let has_open_brace = template_lines.iter().any(|line| line.contains('{'));
+    /// Recursively builds the IR statements for a block and its children.
+    /// The A_C_E template is placed in `out` where the block sits in its stack. Children in
+    /// a named slot replace its {@slot} placeholder; for blocks without slots whose template
+    /// contains a compound statement (like a loop) the children are placed at the end of
+    /// its body, otherwise they follow the block at the same level.
+    /// Global, Setup and Loop section templates are added to those parts of `sketch`.
+    fn render_block_recursive(
+        &self,
+        idx: usize,
+        sketch: &mut Sketch,
+        out: &mut Vec<Stmt>,
+    ) -> Result<(), String> {
+        let block = &self.sections[idx];
+        let has_placement_sections =
+            block.global_code.is_some() || block.setup_code.is_some() || block.loop_code.is_some();
 
-        // For blocks with braces (like if/loop), insert children inside the braces
-        if !children.is_empty() && has_open_brace {
-            if let Some(close_idx) = close_idx {
-                for line in template_lines.iter().take(close_idx) {
-                    out.push(Self::indent_line(line, indent_level));
-                }
+        let mut stmts = match block.code_equivelant.as_deref() {
+            Some(template) => self.render_template(idx, template)?,
+            None if has_placement_sections || block.hat.is_some() => Vec::new(),
+            None => {
+                return Err(format!(
+                    "Block '{}' does not define an A_C_E Code_Equivelant template.",
+                    block.unique_id.as_deref().unwrap_or(&block.id)
+                ));
+            }
+        };
 
-                for child in children {
-                    self.render_block_recursive(child, indent_level + 1, out)?;
-                }
+        if let Some(template) = block.global_code.as_deref() {
+            Sketch::push_unique(&mut sketch.globals, self.render_template(idx, template)?);
+        }
+        if let Some(template) = block.setup_code.as_deref() {
+            Sketch::push_unique(&mut sketch.setup, self.render_template(idx, template)?);
+        }
+        if let Some(template) = block.loop_code.as_deref() {
+            Sketch::push_unique(&mut sketch.loop_body, self.render_template(idx, template)?);
+        }
 
-                for line in template_lines.iter().skip(close_idx) {
-                    out.push(Self::indent_line(line, indent_level));
+        // Children in named slots replace the matching {@slot} placeholders
+        for slot in &block.slots {
+            let mut slot_stmts = Vec::new();
+            for child in self.sorted_child_indices(idx) {
+                if self.sections[child].attached_input.is_none()
+                    && self.sections[child].attached_slot.as_deref() == Some(slot.name.as_str())
+                {
+                    self.render_block_recursive(child, sketch, &mut slot_stmts)?;
                 }
-
-                return Ok(());
+            }
+            if !ir::fill_slot(&mut stmts, &slot.name, &slot_stmts) && !slot_stmts.is_empty() {
+                return Err(format!(
+                    "Block '{}' has blocks in slot '{}' but its template has no {{@{}}} placeholder.",
+                    block.unique_id.as_deref().unwrap_or(&block.id),
+                    slot.name,
+                    slot.name
+                ));
             }
         }
+        ir::remove_slots(&mut stmts);
 
-        for line in &template_lines {
-            out.push(Self::indent_line(line, indent_level));
+        let mut child_stmts = Vec::new();
+        for child in self.sorted_child_indices(idx) {
+            if self.sections[child].attached_slot.is_none()
+                && self.sections[child].attached_input.is_none()
+            {
+                self.render_block_recursive(child, sketch, &mut child_stmts)?;
+            }
         }
 
-        for child in children {
-            self.render_block_recursive(child, indent_level, out)?;
+        // Blocks with named slots keep the remaining children after themselves.
+        // For other blocks with a body (like if/loop), insert children inside the last one
+        let body = stmts.iter_mut().rev().find_map(|stmt| match &mut stmt.kind {
+            StmtKind::Compound { body, .. } if block.slots.is_empty() => Some(body),
+            _ => None,
+        });
+        match body {
+            Some(body) => body.extend(child_stmts),
+            None => stmts.extend(child_stmts),
         }
 
+        out.extend(stmts);
+        Ok(())
+    }
+
+    /// Adds the #include lines and global object declarations needed by a stack, including
+    /// reporter blocks plugged into its inputs. Both are de-duplicated by the sketch.
+    fn add_library_code(&self, root: usize, sketch: &mut Sketch) -> Result<(), String> {
+        let mut blocks = Vec::new();
+        self.collect_descendants(root, &mut blocks);
+
+        for idx in blocks {
+            for header in &self.sections[idx].includes {
+                sketch.add_include(header);
+            }
+            for template in &self.sections[idx].declarations {
+                Sketch::push_unique(&mut sketch.declarations, self.render_template(idx, template)?);
+            }
+        }
         Ok(())
     }
 
@@ -829,11 +2699,13 @@ impl BlocksForArduino {
     }
 
     /// Substitutes placeholder values in a code template.
-    /// Placeholders are marked with curly braces like {variable_name}.
+    /// Placeholders are marked with curly braces like {variable_name}. Expressions in
+    /// `plugged` (keyed by input name) take precedence over the typed-in input values.
     /// Returns the filled template and a list of any missing placeholders.
     fn fill_template_with_inputs(
         template: &str,
         block: &BlockSection,
+        plugged: &HashMap<String, String>,
     ) -> (String, Vec<String>) {
         let mut out = String::with_capacity(template.len());
         let mut missing = Vec::new();
@@ -861,7 +2733,9 @@ impl BlocksForArduino {
             let key = raw_key.trim();
 
             if Self::is_placeholder_token(key) {
-                if let Some(value) = Self::resolve_input_value(&block.input_values, key) {
+                if let Some(expression) = Self::resolve_input_value(plugged, key) {
+                    out.push_str(expression);
+                } else if let Some(value) = Self::resolve_input_value(&block.input_values, key) {
                     let rule = Self::resolve_input_definition(block, key)
                         .and_then(|input| input.validation.as_deref());
                     out.push_str(&Self::normalize_value_for_rule(value, rule));
@@ -881,103 +2755,158 @@ impl BlocksForArduino {
         (out, missing)
     }
 
-    /// Generates code lines from all root blocks to be placed in the loop() function
-    fn render_ino_loop_lines(&self) -> Result<Vec<String>, String> {
+    /// Generates the complete Arduino .ino source code from the current blocks, together
+    /// with the instance_id of the block behind each line.
+    fn build_ino_source(&self) -> Result<MappedSource, String> {
+        Ok(self.build_sketch()?.print())
+    }
+
+    /// Builds the sketch IR from the current blocks.
+    /// Library includes and object declarations come first, then the project variables.
+    /// Each block's Global and Setup sections are placed at global scope and in setup(),
+    /// its Loop section at the top of loop(). A stack's A_C_E code goes into setup() or
+    /// loop() depending on its hat block, or becomes a function above setup() under a
+    /// "Define function" block; stacks without a hat are reported as an error.
+    fn build_sketch(&self) -> Result<Sketch, String> {
         let roots = self.root_block_indices();
         if roots.is_empty() {
             return Err("No code blocks in workspace. Add blocks to generate an .ino file.".to_string());
         }
+        self.validate_stack_attachment()?;
 
-        let mut lines = Vec::new();
+        let mut sketch = Sketch {
+            comments: vec![format!("Board: {} ({})", self.board.name, self.board.fqbn)],
+            globals: self.variable_declarations(),
+            ..Default::default()
+        };
+        let mut setup_stmts = Vec::new();
+        let mut loop_stmts = Vec::new();
 
         for root in roots {
-            self.render_block_recursive(root, 0, &mut lines)?;
+            self.add_library_code(root, &mut sketch)?;
+
+            let mut stmts = Vec::new();
+            self.render_block_recursive(root, &mut sketch, &mut stmts)?;
+            match self.sections[root].hat {
+                Some(HatKind::Setup) => setup_stmts.extend(stmts),
+                Some(HatKind::Loop) => loop_stmts.extend(stmts),
+                Some(HatKind::Function) => sketch.functions.extend(stmts),
+                None => {}
+            }
         }
 
-        Ok(lines)
+        sketch.setup.extend(setup_stmts);
+        sketch.loop_body.extend(loop_stmts);
+        Ok(sketch)
     }
 
-    /// Checks if a line is a pinMode() call, which should be in the setup() method
-    fn is_setup_line(line: &str) -> bool {
-        line.trim_start().to_ascii_lowercase().starts_with("pinmode(")
-    }
+    /// Exports the current workspace as an Arduino .ino file.
+    /// Creates a directory structure and saves the generated code, a source map and a
+    /// sketch.yaml naming the target board.
+    /// Returns the path where the file was written.
+    fn export_ino(&self) -> Result<std::path::PathBuf, String> {
+        let (file_stem, workspace_dir) = self.export_dir()?;
 
-    /// Generates the complete Arduino .ino source code from the current blocks.
-    /// Separates pinMode() calls into setup() and other code into loop().
-    fn build_ino_source(&self) -> Result<String, String> {
-        let generated_lines = self.render_ino_loop_lines()?;
-        let mut setup_lines = Vec::new();
-        let mut loop_lines = Vec::new();
+        let path = workspace_dir.join(format!("{}.ino", file_stem));
+        let source = self.build_ino_source()?;
 
-        for line in generated_lines {
-            if Self::is_setup_line(&line) {
-                setup_lines.push(line);
-            } else {
-                loop_lines.push(line);
-            }
-        }
+        std::fs::write(&path, &source.text)
+            .map_err(|e| format!("Could not write {}: {e}", path.display()))?;
 
-        let mut src = String::new();
-        src.push_str("void setup() {\n");
-        for line in setup_lines {
-            if line.trim().is_empty() {
-                src.push('\n');
-            } else {
-                src.push_str("  ");
-                src.push_str(&line);
-                src.push('\n');
-            }
-        }
-        src.push_str("}\n\n");
-        src.push_str("void loop() {\n");
+        let map = SourceMapFile {
+            sketch: format!("{}.ino", file_stem),
+            board: self.board.id.clone(),
+            lines: self.source_map_lines(&source),
+        };
+        let map_path = workspace_dir.join(format!("{}.map.json", file_stem));
+        let json = serde_json::to_string_pretty(&map)
+            .map_err(|e| format!("Could not serialise source map: {e}"))?;
+        std::fs::write(&map_path, json)
+            .map_err(|e| format!("Could not write {}: {e}", map_path.display()))?;
 
-        for line in loop_lines {
-            if line.trim().is_empty() {
-                src.push('\n');
-            } else {
-                src.push_str("  ");
-                src.push_str(&line);
-                src.push('\n');
-            }
-        }
+        // arduino-cli picks up the target board from the sketch project file
+        let project_path = workspace_dir.join("sketch.yaml");
+        std::fs::write(&project_path, format!("default_fqbn: {}\n", self.board.fqbn))
+            .map_err(|e| format!("Could not write {}: {e}", project_path.display()))?;
 
-        src.push_str("}\n");
-        Ok(src)
+        Ok(path)
     }
 
-    /// Exports the current workspace as an Arduino .ino file.
-    /// Creates a directory structure and saves the generated code.
-    /// Returns the path where the file was written.
-    fn export_ino(&self) -> Result<std::path::PathBuf, String> {
-        let file_stem = if self.workspace_name.trim().is_empty() {
-            "Untitled".to_string()
-        } else {
-            let safe = Self::safe_filename(&self.workspace_name);
-            if safe.is_empty() {
-                "Untitled".to_string()
-            } else {
-                safe
-            }
-        };
+    /// Creates the output folder for the workspace's exported files.
+    /// Returns the file name stem to use, "Untitled" for unnamed workspaces, and the
+    /// folder path.
+    fn export_dir(&self) -> Result<(String, std::path::PathBuf), String> {
+        let safe = Self::safe_filename(&self.workspace_name);
+        let file_stem = if safe.is_empty() { "Untitled".to_string() } else { safe };
 
         let workspace_dir = std::path::Path::new(INO_OUTPUT_DIR).join(&file_stem);
         std::fs::create_dir_all(&workspace_dir)
             .map_err(|e| format!("Could not create {}: {e}", workspace_dir.display()))?;
+        Ok((file_stem, workspace_dir))
+    }
 
-        let path = workspace_dir.join(format!("{}.ino", file_stem));
-        let source = self.build_ino_source()?;
-
-        std::fs::write(&path, source)
-            .map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+    /// Lists the generated lines that came from a block, with 1-based line numbers
+    fn source_map_lines(&self, source: &MappedSource) -> Vec<SourceMapLine> {
+        source
+            .line_origins
+            .iter()
+            .enumerate()
+            .filter_map(|(line, origin)| {
+                let instance_id = origin.as_ref()?;
+                let idx = self
+                    .sections
+                    .iter()
+                    .position(|block| !block.is_palette && &block.instance_id == instance_id)?;
+                Some(SourceMapLine {
+                    line: line + 1,
+                    instance_id: instance_id.clone(),
+                    block: self.block_unique_id(idx),
+                })
+            })
+            .collect()
+    }
 
-        Ok(path)
+    /// Regenerates the code shown in the preview panel. A generation error is shown
+    /// in place of the code; if the code builds but a check still fails, the first
+    /// error is shown above it. The checks are re-run once and stored, so the
+    /// diagnostics panel shares the result.
+    fn refresh_code_preview(&mut self) {
+        self.refresh_diagnostics();
+        match self.build_ino_source() {
+            Ok(source) => {
+                self.code_preview = Some(source);
+                self.code_preview_error = self
+                    .diagnostics
+                    .iter()
+                    .find(|d| d.severity == Severity::Error)
+                    .map(|d| d.message.clone())
+                    .unwrap_or_default();
+            }
+            Err(err) => {
+                self.code_preview = None;
+                self.code_preview_error = err;
+            }
+        }
     }
 
-    /// Rebuilds the current_blocks list from the block tree structure.
+    /// Rebuilds the current_blocks list from the block tree structure and, while their
+    /// panels are open, the generated code and the diagnostics.
     /// Called whenever the block tree changes (attach, detach, delete).
     fn refresh_current_blocks(&mut self) {
         self.current_blocks = self.build_current_blocks();
-        println!("Current blocks: {}", self.current_blocks_json());
+        if self.show_code_preview {
+            self.refresh_code_preview();
+        } else if self.show_diagnostics {
+            self.refresh_diagnostics();
+        }
+    }
+
+    /// Called once per frame after any edit made by hand, including changes to the
+    /// board and the breadboard. Undo only restores snapshots taken before fixes, so
+    /// a manual edit ends the undo history rather than being lost by the next undo.
+    fn workspace_edited(&mut self) {
+        self.undo_stack.clear();
+        self.refresh_current_blocks();
     }
 
     /// Deletes a block and all its children from the workspace.
@@ -988,6 +2917,13 @@ impl BlocksForArduino {
         let mut to_delete = Vec::new();
         self.collect_descendants(idx, &mut to_delete);
 
+        self.remove_blocks(to_delete);
+        self.refresh_current_blocks();
+    }
+
+    /// Removes the given blocks from `sections` and renumbers the parent/child indices
+    /// of the remaining blocks. Links to removed blocks are dropped.
+    fn remove_blocks(&mut self, mut to_delete: Vec<usize>) {
         to_delete.sort_unstable_by(|a, b| b.cmp(a));
 
         for &i in &to_delete {
@@ -1012,8 +2948,151 @@ impl BlocksForArduino {
             }
             block.children = updated_children;
         }
+    }
+
+    // ------------------------------------------------------------------
+    // Quick fixes and undo
+    // ------------------------------------------------------------------
+
+    /// Finds the workspace block with the given instance_id
+    fn block_by_instance(&self, instance_id: &str) -> Option<usize> {
+        self.sections
+            .iter()
+            .position(|block| !block.is_palette && block.instance_id == instance_id)
+    }
 
+    /// Saves the current blocks and variables so the next edit can be undone
+    fn record_undo(&mut self) {
+        self.undo_stack.push(EditSnapshot {
+            sections: self.sections.clone(),
+            variables: self.variables.clone(),
+        });
+        if self.undo_stack.len() > MAX_UNDO_STEPS {
+            self.undo_stack.remove(0);
+        }
+    }
+
+    /// Restores the workspace to how it was before the last recorded edit
+    fn undo(&mut self) {
+        let Some(snapshot) = self.undo_stack.pop() else {
+            return;
+        };
+        self.sections = snapshot.sections;
+        self.variables = snapshot.variables;
         self.refresh_current_blocks();
+        self.status_message = "Undid the last fix".to_string();
+    }
+
+    /// Applies a quick fix as a single undoable edit
+    fn apply_fix(&mut self, fix: &QuickFix) -> Result<(), String> {
+        self.record_undo();
+        let result = match fix {
+            QuickFix::SetInput { instance_id, input, value } => {
+                match self.block_by_instance(instance_id) {
+                    Some(idx) => {
+                        self.sections[idx].input_values.insert(input.clone(), value.clone());
+                        Ok(())
+                    }
+                    None => Err("The block this fix applies to no longer exists.".to_string()),
+                }
+            }
+            QuickFix::ReorderStack { order } => self.reorder_stack(order),
+            QuickFix::AddToSetup { block, values } => self.add_to_setup(block, values),
+        };
+
+        match result {
+            Ok(()) => {
+                self.refresh_current_blocks();
+                self.status_message = format!("Applied fix: {}", fix.label());
+                Ok(())
+            }
+            Err(err) => {
+                self.undo_stack.pop();
+                Err(err)
+            }
+        }
+    }
+
+    /// Rearranges a plain chain of blocks into the given order. The first block takes
+    /// the place of the chain's current top block; blocks plugged into or nested in a
+    /// block move with it.
+    fn reorder_stack(&mut self, order: &[String]) -> Result<(), String> {
+        let blocks: Vec<usize> = order
+            .iter()
+            .map(|id| self.block_by_instance(id))
+            .collect::<Option<_>>()
+            .ok_or("A block in this stack no longer exists.")?;
+        let Some(&top) = blocks.iter().find(|&&idx| {
+            self.sections[idx].attached_to.is_none_or(|parent| !blocks.contains(&parent))
+        }) else {
+            return Ok(());
+        };
+        let parent = self.sections[top].attached_to;
+        let top_pos = self.sections[top].pos;
+
+        // Unlink the chain, keeping each block's own slots and plugged reporters
+        for &idx in &blocks {
+            if let Some(from) = self.sections[idx].attached_to.take() {
+                self.sections[from].children.retain(|&child| child != idx);
+            }
+        }
+
+        let mut above = parent;
+        for &idx in &blocks {
+            match above {
+                Some(above) => {
+                    self.sections[idx].attached_to = Some(above);
+                    self.sections[above].children.push(idx);
+                }
+                None => self.sections[idx].pos = top_pos,
+            }
+            above = Some(idx);
+        }
+        self.relayout_stack(blocks[0]);
+        Ok(())
+    }
+
+    /// Adds a block from the palette, with preset input values, to the end of the first
+    /// "On start" stack. The stack is created below the existing blocks if there is none.
+    fn add_to_setup(&mut self, block: &str, values: &[(String, String)]) -> Result<(), String> {
+        let palette = |app: &Self, wanted: &dyn Fn(&BlockSection) -> bool| {
+            app.sections.iter().position(|b| b.is_palette && wanted(b))
+        };
+        let source = palette(self, &|b| b.unique_id.as_deref().unwrap_or(&b.id) == block)
+            .ok_or(format!("The block '{block}' is not loaded."))?;
+
+        let setup = self
+            .root_block_indices()
+            .into_iter()
+            .find(|&root| self.sections[root].hat == Some(HatKind::Setup));
+        let mut last = match setup {
+            Some(root) => root,
+            None => {
+                let hat = palette(self, &|b| b.hat == Some(HatKind::Setup))
+                    .ok_or("No \"On start\" block is loaded.")?;
+                let bottom = self
+                    .root_block_indices()
+                    .into_iter()
+                    .map(|root| self.sections[root].pos.y + self.block_extent(root))
+                    .fold(BLOCKS_START_Y, f32::max);
+                self.spawn_code_block(hat);
+                let idx = self.sections.len() - 1;
+                self.sections[idx].pos = egui::pos2(320.0, bottom + 30.0);
+                idx
+            }
+        };
+        while let Some(&next) = self.slot_children(last, None).first() {
+            last = next;
+        }
+
+        self.spawn_code_block(source);
+        let idx = self.sections.len() - 1;
+        for (name, value) in values {
+            self.sections[idx].input_values.insert(name.clone(), value.clone());
+        }
+        let pos = self.next_origin(last);
+        self.attach_block(idx, last, pos, None, None);
+        Ok(())
     }
 }
 
@@ -1078,12 +3157,17 @@ impl BlocksForArduino {
                 attached_to_instance_id: b
                     .attached_to
                     .map(|idx| self.sections[idx].instance_id.clone()),
+                attached_slot: b.attached_slot.clone(),
+                attached_input: b.attached_input.clone(),
             })
             .collect();
 
         let snapshot = WorkspaceSnapshot {
             name: safe.clone(),
             blocks,
+            variables: self.variables.clone(),
+            board: Some(self.board.id.clone()),
+            components: self.components.clone(),
         };
         let json = serde_json::to_string_pretty(&snapshot)
             .map_err(|e| format!("Serialisation error: {e}"))?;
@@ -1097,9 +3181,18 @@ impl BlocksForArduino {
 
     /// Loads a saved workspace by name, restor all code blocks and their connections.
     /// Requires that all referenced block types are already loaded as palette blocks.
-    pub fn load_workspace_by_name(&mut self, name: &str) -> Result<(), String> {
-        let path = Self::workspace_path(name);
-        let json = std::fs::read_to_string(&path)
+    pub fn load_workspace_by_name(&mut self, name: &str) -> Result<Option<String>, String> {
+        self.load_workspace_file(&Self::workspace_path(name))
+    }
+
+    /// Loads a workspace from a JSON file anywhere on disk, e.g. a student's submission
+    /// in batch grading.
+    ///
+    /// # Returns
+    /// A note for the user if the workspace was saved before hat blocks and had to be
+    /// updated, or an error if it could not be loaded
+    pub fn load_workspace_file(&mut self, path: &std::path::Path) -> Result<Option<String>, String> {
+        let json = std::fs::read_to_string(path)
             .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
         let snapshot: WorkspaceSnapshot = serde_json::from_str(&json)
             .map_err(|e| format!("Invalid workspace JSON: {e}"))?;
@@ -1136,6 +3229,8 @@ impl BlocksForArduino {
             new_block.input_values = snap.input_values.clone();
             new_block.is_palette = false;
             new_block.attached_to = None;
+            new_block.attached_slot = None;
+            new_block.attached_input = None;
             new_block.children.clear();
             self.sections.push(new_block);
         }
@@ -1154,6 +3249,8 @@ impl BlocksForArduino {
                 let child_idx = base_index + offset;
                 if let Some(&parent_idx) = id_to_idx.get(parent_iid) {
                     self.sections[child_idx].attached_to = Some(parent_idx);
+                    self.sections[child_idx].attached_slot = snap.attached_slot.clone();
+                    self.sections[child_idx].attached_input = snap.attached_input.clone();
                     if !self.sections[parent_idx].children.contains(&child_idx) {
                         self.sections[parent_idx].children.push(child_idx);
                     }
@@ -1161,9 +3258,66 @@ impl BlocksForArduino {
             }
         }
 
+        let wrapped = self.wrap_hatless_stacks();
+        let note = (wrapped > 0).then(|| {
+            format!(
+                "Saved before hat blocks: {} stack{} now run{} under a \"Forever\" block.",
+                wrapped,
+                if wrapped == 1 { "" } else { "s" },
+                if wrapped == 1 { "s" } else { "" }
+            )
+        });
+
         self.workspace_name = snapshot.name;
+        self.variables = snapshot.variables;
+        self.components = snapshot.components;
+        self.grade_report = None;
+        self.grading = None;
+        self.undo_stack.clear();
+        self.stop_simulation();
+        self.simulation = None;
+        self.breakpoints.clear();
+        if let Some(id) = snapshot.board {
+            match self.boards.iter().find(|board| board.id == id) {
+                Some(board) => self.board = board.clone(),
+                None => eprintln!("Workspace targets unknown board '{}', keeping {}", id, self.board.name),
+            }
+        }
         self.refresh_current_blocks();
-        Ok(())
+        Ok(note)
+    }
+
+    /// Puts every top-level stack under a new "Forever" block if the workspace has no
+    /// hat blocks at all, i.e. was saved before they existed; the old generator ran
+    /// every stack in loop(). In newer workspaces a stack without a hat is left loose so
+    /// it is reported as unattached. Reporters lying loose on the canvas are left alone.
+    /// Returns the number of stacks wrapped.
+    fn wrap_hatless_stacks(&mut self) -> usize {
+        if self.sections.iter().any(|b| !b.is_palette && b.hat.is_some()) {
+            return 0;
+        }
+        let Some(forever) = self
+            .sections
+            .iter()
+            .position(|b| b.is_palette && b.hat == Some(HatKind::Loop))
+        else {
+            return 0;
+        };
+        let roots: Vec<usize> = self
+            .root_block_indices()
+            .into_iter()
+            .filter(|&root| self.sections[root].hat.is_none() && self.sections[root].output.is_none())
+            .collect();
+
+        for &root in &roots {
+            self.spawn_code_block(forever);
+            let hat = self.sections.len() - 1;
+            let height = self.block_body_height(hat);
+            self.sections[hat].pos = self.sections[root].pos - egui::vec2(0.0, height);
+            let pos = self.next_origin(hat);
+            self.attach_block(root, hat, pos, None, None);
+        }
+        roots.len()
     }
 
     /// Closes the current workspace by removing all code blocks and clearing the workspace name.
@@ -1175,11 +3329,240 @@ impl BlocksForArduino {
             b.attached_to = None;
         }
         self.workspace_name.clear();
+        self.variables.clear();
+        self.components.clear();
+        self.grade_report = None;
+        self.grading = None;
+        self.undo_stack.clear();
+        self.stop_simulation();
+        self.simulation = None;
+        self.breakpoints.clear();
         self.status_message.clear();
         self.refresh_current_blocks();
     }
 }
 
+/* ---------- SIMULATION ---------- */
+
+impl BlocksForArduino {
+    /// Builds the sketch from the current blocks and prepares it to run on a virtual copy
+    /// of the selected board. Nothing runs until the simulator is advanced, so this is
+    /// also the entry point for running programs without the UI.
+    pub fn start_simulation(&self) -> Result<Simulator, String> {
+        let sketch = self.build_sketch()?;
+        Simulator::new(&sketch, &self.board)
+    }
+
+    /// Starts the program from the beginning on the virtual board, running or paused in
+    /// front of its first block. A breakpoint on the first block pauses it straight away.
+    fn run_simulation(&mut self, state: RunState) {
+        match self.start_simulation() {
+            Ok(simulation) => {
+                let at_breakpoint = simulation
+                    .next_block()
+                    .is_some_and(|block| self.breakpoints.contains(block));
+                self.simulation = Some(simulation);
+                self.apply_components();
+                self.run_state = if at_breakpoint { RunState::Paused } else { state };
+                self.status_message = match self.run_state {
+                    RunState::Running => format!("Running on a virtual {}", self.board.name),
+                    _ => "Paused at the first block".to_string(),
+                };
+            }
+            Err(err) => self.status_message = format!("Cannot run: {err}"),
+        }
+    }
+
+    /// Stops the program for good, keeping its last state on show
+    fn stop_simulation(&mut self) {
+        self.run_state = RunState::Stopped;
+    }
+
+    /// Runs the current block and pauses in front of the next one. Starts the program
+    /// paused at its first block when it is not running yet.
+    fn step_simulation(&mut self) {
+        if self.run_state == RunState::Stopped || self.simulation.is_none() {
+            self.run_simulation(RunState::Paused);
+            return;
+        }
+        self.apply_components();
+        let Some(simulation) = &mut self.simulation else {
+            return;
+        };
+        self.run_state = RunState::Paused;
+        if let Err(err) = simulation.step_block(SIMULATION_STEPS_PER_FRAME) {
+            self.status_message = format!("Program stopped: {err}");
+            self.run_state = RunState::Stopped;
+        }
+    }
+
+    /// Runs the program for the real time that passed since the last frame. Reaching a
+    /// breakpoint pauses it; a runtime error stops it and is shown in the status bar.
+    fn advance_simulation(&mut self, seconds: f32) {
+        self.apply_components();
+        let Some(simulation) = &mut self.simulation else {
+            return;
+        };
+        // Long frames (e.g. while the window was hidden) are not caught up on
+        let us = (seconds.min(0.1) * 1_000_000.0) as u64;
+        match simulation.advance(us, SIMULATION_STEPS_PER_FRAME, &self.breakpoints) {
+            Ok(false) => {}
+            Ok(true) => {
+                self.run_state = RunState::Paused;
+                let block = simulation
+                    .next_block()
+                    .and_then(|id| self.sections.iter().position(|b| b.instance_id == id));
+                self.status_message = match block {
+                    Some(idx) => format!("Paused at breakpoint on '{}'", self.block_unique_id(idx)),
+                    None => "Paused at breakpoint".to_string(),
+                };
+            }
+            Err(err) => {
+                self.status_message = format!("Program stopped: {err}");
+                self.run_state = RunState::Stopped;
+            }
+        }
+    }
+
+    /// Feeds the breadboard's buttons and potentiometers into the pins they are wired
+    /// to, so the program reads them like real inputs
+    fn apply_components(&mut self) {
+        if let Some(simulation) = &mut self.simulation {
+            Self::wire_components(&self.components, &mut simulation.board);
+        }
+    }
+
+    /// Applies the inputs of a breadboard's buttons and potentiometers to a board
+    fn wire_components(components: &[Component], board: &mut VirtualBoard) {
+        for component in components {
+            let Some(pin) = board.pin_number(&component.pin) else {
+                continue;
+            };
+            match component.kind {
+                ComponentKind::Button => board.set_button(pin, component.pressed),
+                ComponentKind::Potentiometer => board.set_input(pin, component.position),
+                ComponentKind::Led | ComponentKind::Buzzer => {}
+            }
+        }
+    }
+
+    /// Adds a part to the breadboard, wired to the pin the usual example sketches use
+    /// for it, or to the first suitable pin when the board does not have that one
+    fn add_component(&mut self, kind: ComponentKind) {
+        let pins = self.board_pins_for_rule(Self::component_pin_rule(kind));
+        let pin = if pins.iter().any(|pin| pin == kind.default_pin()) {
+            kind.default_pin().to_string()
+        } else {
+            pins.first().cloned().unwrap_or_default()
+        };
+        self.components.push(Component {
+            kind,
+            pin,
+            colour: "#ff3030".to_string(),
+            position: 512,
+            pressed: false,
+        });
+    }
+
+    /// Pin validation rule a part's pin must satisfy
+    fn component_pin_rule(kind: ComponentKind) -> &'static str {
+        match kind {
+            ComponentKind::Potentiometer => "analog_pin",
+            _ => "arduino_pin",
+        }
+    }
+
+    /// How strongly an output part is driven, from 0 (off) to 1 (fully on), given the
+    /// board the program runs on
+    fn component_drive(board: Option<&VirtualBoard>, pin: &str) -> f32 {
+        let Some(board) = board else {
+            return 0.0;
+        };
+        let state = board.pin_number(pin).and_then(|pin| board.pins.get(&pin));
+        match state {
+            Some(state) if state.mode == Some(PinMode::Output) || state.written => {
+                if state.pwm {
+                    state.output.clamp(0, 255) as f32 / 255.0
+                } else {
+                    (state.output != 0) as u8 as f32
+                }
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Runs the workspace on a fresh virtual board, with its breadboard wired up, and
+    /// checks it against an assessment
+    pub fn grade_workspace(&self, assessment: &Assessment) -> GradeReport {
+        match self.build_sketch() {
+            Ok(sketch) => Self::grade_sketch(&sketch, &self.board, &self.components, assessment),
+            Err(err) => GradeReport::not_run(assessment, format!("Cannot run: {err}")),
+        }
+    }
+
+    /// Grades a sketch on a fresh virtual board of the given profile with the given
+    /// breadboard parts wired up
+    fn grade_sketch(
+        sketch: &Sketch,
+        board: &BoardProfile,
+        components: &[Component],
+        assessment: &Assessment,
+    ) -> GradeReport {
+        match Simulator::new(sketch, board) {
+            Ok(mut simulation) => {
+                Self::wire_components(components, &mut simulation.board);
+                grade::grade(assessment, simulation)
+            }
+            Err(err) => GradeReport::not_run(assessment, format!("Cannot run: {err}")),
+        }
+    }
+
+    /// Grades the workspace on a background thread, since a slow program can take
+    /// seconds to run for the assessment's duration. The report arrives through
+    /// `grading`.
+    fn start_grading(&mut self, assessment: Assessment) {
+        let sketch = match self.build_sketch() {
+            Ok(sketch) => sketch,
+            Err(err) => {
+                self.status_message = format!("Graded against '{}': fail", assessment.name);
+                self.grade_report = Some(GradeReport::not_run(&assessment, format!("Cannot run: {err}")));
+                return;
+            }
+        };
+        let board = self.board.clone();
+        let components = self.components.clone();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            // The window may have moved on to another workspace; the report is then dropped
+            let _ = sender.send(Self::grade_sketch(&sketch, &board, &components, &assessment));
+        });
+        self.grade_report = None;
+        self.grading = Some(receiver);
+    }
+
+    /// Saves the pin activity of the last run as a VCD file next to the exported sketch.
+    /// Returns the path where the file was written.
+    fn export_vcd(&self) -> Result<std::path::PathBuf, String> {
+        let simulation = self
+            .simulation
+            .as_ref()
+            .ok_or_else(|| "Run the program first to record a trace".to_string())?;
+        let (file_stem, workspace_dir) = self.export_dir()?;
+        let trace = Trace::from_board(&simulation.board, simulation.time_us);
+        let path = workspace_dir.join(format!("{}.vcd", file_stem));
+        std::fs::write(&path, trace.to_vcd(&file_stem))
+            .map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Adds or removes the breakpoint on a block
+    fn toggle_breakpoint(&mut self, instance_id: &str) {
+        if !self.breakpoints.remove(instance_id) {
+            self.breakpoints.insert(instance_id.to_string());
+        }
+    }
+}
+
 /* ---------- UI ---------- */
 
 /// Implementation of the egui App trait for rendering and handling the UI
@@ -1190,7 +3573,10 @@ impl eframe::App for BlocksForArduino {
         let mouse_released = self.was_mouse_down && !mouse_down;
         self.was_mouse_down = mouse_down;
 
+        self.sync_procedure_palette();
+
         let mut delete_request: Option<usize> = None;
+        let mut edited = false;
 
         // --- TOP TOOLBAR PANEL ---
         egui::TopBottomPanel::top("toolbar").show(ctx, |ui| {
@@ -1215,120 +3601,581 @@ impl eframe::App for BlocksForArduino {
                         }
                     }
                 }
-                if ui.button("Save As").clicked() {
-                    self.save_as_name_input = self.workspace_name.clone();
-                    self.show_save_as_dialog = true;
-                }
-                if ui.add_enabled(!self.workspace_name.is_empty(), egui::Button::new("Reload")).clicked() {
-                    let name = self.workspace_name.clone();
-                    match self.load_workspace_by_name(&name) {
-                        Ok(()) => self.status_message = format!("Reloaded \"{}\"", name),
-                        Err(e) => self.status_message = e,
+                if ui.button("Save As").clicked() {
+                    self.save_as_name_input = self.workspace_name.clone();
+                    self.show_save_as_dialog = true;
+                }
+                if ui.add_enabled(!self.workspace_name.is_empty(), egui::Button::new("Reload")).clicked() {
+                    let name = self.workspace_name.clone();
+                    match self.load_workspace_by_name(&name) {
+                        Ok(None) => self.status_message = format!("Reloaded \"{}\"", name),
+                        Ok(Some(note)) => self.status_message = format!("Reloaded \"{}\". {}", name, note),
+                        Err(e) => self.status_message = e,
+                    }
+                }
+                if ui.button("Close").clicked() {
+                    self.close_workspace();
+                }
+
+                ui.separator();
+
+                let name_label = if self.workspace_name.is_empty() {
+                    "Untitled".to_string()
+                } else {
+                    self.workspace_name.clone()
+                };
+                ui.label(format!("Workspace: {}", name_label));
+
+                ui.separator();
+
+                let mut selected_board = None;
+                egui::ComboBox::from_id_salt("board")
+                    .selected_text(self.board.name.as_str())
+                    .show_ui(ui, |ui| {
+                        for (i, board) in self.boards.iter().enumerate() {
+                            if ui.selectable_label(board.id == self.board.id, &board.name).clicked() {
+                                selected_board = Some(i);
+                            }
+                        }
+                    });
+                if let Some(i) = selected_board {
+                    self.board = self.boards[i].clone();
+                    edited = true;
+                }
+
+                if ui
+                    .add_enabled(!self.undo_stack.is_empty(), egui::Button::new("Undo"))
+                    .on_hover_text("Undo the last applied fix")
+                    .clicked()
+                {
+                    self.undo();
+                }
+                if ui.button("Variables").clicked() {
+                    self.show_variables_window = !self.show_variables_window;
+                }
+                if ui.toggle_value(&mut self.show_code_preview, "Code preview").clicked() {
+                    self.refresh_code_preview();
+                    if !self.show_code_preview {
+                        self.highlighted_block = None;
+                    }
+                }
+
+                ui.separator();
+
+                // --- Validation ---
+                if ui.button("Check connections").clicked() {
+                    self.refresh_diagnostics();
+                    self.show_diagnostics = true;
+                    if self.diagnostic_count(Severity::Error) > 0 {
+                        self.status_message =
+                            format!("Problems found: {}", self.diagnostics_summary());
+                    } else if self.current_blocks.is_empty() {
+                        self.status_message = "No code-block connections found".to_string();
+                    } else {
+                        let current_blocks = self.current_blocks_json();
+                        self.status_message =
+                            format!("Connected blocks are valid: {}", current_blocks);
+                        let warnings = self.diagnostic_count(Severity::Warning);
+                        if warnings > 0 {
+                            self.status_message.push_str(&format!(
+                                " ({} warning{})",
+                                warnings,
+                                if warnings == 1 { "" } else { "s" }
+                            ));
+                        }
+                    }
+                }
+
+                if ui.button("Export .ino").clicked() {
+                    // Only invalid inputs or variables stop the export; block order
+                    // problems are advice and unattached stacks are reported by the
+                    // code generator itself
+                    self.refresh_diagnostics();
+                    let invalid_values = !self.variable_diagnostics().is_empty()
+                        || !self.input_diagnostics().is_empty();
+                    if invalid_values {
+                        self.status_message = format!(
+                            "Export blocked: {} – see Diagnostics",
+                            self.diagnostics_summary()
+                        );
+                        self.show_diagnostics = true;
+                    } else {
+                        match self.export_ino() {
+                            Ok(path) => {
+                                self.status_message = format!(
+                                    "Exported Arduino sketch to {}",
+                                    path.display()
+                                );
+                                let warnings = self.diagnostic_count(Severity::Warning);
+                                if warnings > 0 {
+                                    self.status_message.push_str(&format!(
+                                        " ({} warning{} – see Diagnostics)",
+                                        warnings,
+                                        if warnings == 1 { "" } else { "s" }
+                                    ));
+                                }
+                            }
+                            Err(err) => {
+                                self.status_message = err;
+                            }
+                        }
+                    }
+                }
+
+                ui.separator();
+
+                // --- Simulation ---
+                match self.run_state {
+                    RunState::Stopped => {
+                        if ui
+                            .button("▶ Run")
+                            .on_hover_text("Run the program on a virtual board")
+                            .clicked()
+                        {
+                            self.run_simulation(RunState::Running);
+                        }
+                    }
+                    RunState::Running => {
+                        if ui.button("⏸ Pause").clicked() {
+                            self.run_state = RunState::Paused;
+                            self.status_message = "Paused".to_string();
+                        }
+                    }
+                    RunState::Paused => {
+                        if ui.button("▶ Continue").clicked() {
+                            self.run_state = RunState::Running;
+                            self.status_message.clear();
+                        }
                     }
                 }
-                if ui.button("Close").clicked() {
-                    self.close_workspace();
+                if ui
+                    .add_enabled(self.run_state != RunState::Running, egui::Button::new("Step"))
+                    .on_hover_text("Run one block, then pause")
+                    .clicked()
+                {
+                    self.step_simulation();
+                }
+                if ui
+                    .add_enabled(self.run_state != RunState::Stopped, egui::Button::new("■ Stop"))
+                    .clicked()
+                {
+                    self.stop_simulation();
+                    self.status_message = "Program stopped".to_string();
+                }
+                ui.toggle_value(&mut self.show_breadboard, "Breadboard")
+                    .on_hover_text("Wire LEDs, buttons and other parts to the virtual board");
+                ui.toggle_value(&mut self.show_grader, "Grade")
+                    .on_hover_text("Check the program against an assessment");
+                ui.toggle_value(&mut self.show_timeline, "Timeline")
+                    .on_hover_text("Plot every pin's level over simulated time");
+                if ui
+                    .add_enabled(self.simulation.is_some(), egui::Button::new("Export VCD"))
+                    .on_hover_text("Save the pin activity for GTKWave or other waveform viewers")
+                    .clicked()
+                {
+                    self.status_message = match self.export_vcd() {
+                        Ok(path) => format!("Exported pin trace to {}", path.display()),
+                        Err(err) => err,
+                    };
                 }
 
-                ui.separator();
-
-                let name_label = if self.workspace_name.is_empty() {
-                    "Untitled".to_string()
-                } else {
-                    self.workspace_name.clone()
-                };
-                ui.label(format!("Workspace: {}", name_label));
+                if !self.status_message.is_empty() {
+                    ui.label(&self.status_message);
+                }
+            });
+        });
 
-                ui.separator();
+        // --- VIRTUAL BOARD PANEL ---
+        if self.run_state == RunState::Running {
+            self.advance_simulation(ctx.input(|i| i.stable_dt));
+            ctx.request_repaint();
+        }
+        let mut close_simulation = false;
+        let running = self.run_state != RunState::Stopped;
+        if let Some(simulation) = &mut self.simulation {
+            egui::SidePanel::right("simulation")
+                .resizable(true)
+                .default_width(240.0)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.heading("Virtual board");
+                        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                            if ui.add_enabled(!running, egui::Button::new("Close")).clicked() {
+                                close_simulation = true;
+                            }
+                        });
+                    });
+                    ui.label(format!("Time: {:.3} s", simulation.time_us as f64 / 1_000_000.0));
+                    if let Some(error) = &simulation.error {
+                        ui.colored_label(egui::Color32::from_rgb(200, 60, 60), error);
+                    }
+                    ui.separator();
 
-                // --- Validation ---
-                if ui.button("Check connections").clicked() {
-                    // Step 1 – validate field values before checking sequences
-                    match self.validate_block_inputs() {
-                        Err(message) => {
-                            self.status_message =
-                                "Invalid block inputs detected.".to_string();
-                            self.validation_popup_text = message;
-                            self.show_validation_popup = true;
-                        }
-                        Ok(()) => {
-                            // Step 2 – validate cross-block pin consistency
-                            match self.validate_pin_consistency() {
-                                Err(message) => {
-                                    self.status_message =
-                                        "Pin mismatch detected.".to_string();
-                                    self.validation_popup_text = message;
-                                    self.show_validation_popup = true;
+                    ui.strong("Pins");
+                    if simulation.board.pins.is_empty() {
+                        ui.weak("The program has not used any pins yet.");
+                    }
+                    let mut input_change: Option<(i64, i64)> = None;
+                    egui::Grid::new("simulation_pins").striped(true).show(ui, |ui| {
+                        for (&pin, state) in &simulation.board.pins {
+                            ui.label(format!("Pin {}", simulation.board.pin_label(pin)));
+                            if let Some(angle) = state.servo {
+                                ui.label("Servo");
+                                ui.label(format!("{angle}°"));
+                            } else if state.mode == Some(PinMode::Output) || state.written {
+                                let level = if state.pwm { state.output as f32 / 255.0 } else { state.output as f32 };
+                                let (rect, _) = ui.allocate_exact_size(egui::vec2(14.0, 14.0), egui::Sense::hover());
+                                let lit = egui::Color32::from_rgb(255, 210, 40);
+                                let colour = egui::Color32::from_gray(60).lerp_to_gamma(lit, level);
+                                ui.painter().circle_filled(rect.center(), 6.0, colour);
+                                ui.label(match (state.pwm, state.output) {
+                                    (true, duty) => format!("PWM {duty}"),
+                                    (false, 0) => "LOW".to_string(),
+                                    _ => "HIGH".to_string(),
+                                });
+                            } else if simulation.board.is_analog(pin) && state.mode.is_none() {
+                                let mut value = state.input.unwrap_or(0);
+                                ui.label("Analog in");
+                                if ui.add(egui::Slider::new(&mut value, 0..=1023)).changed() {
+                                    input_change = Some((pin, value));
                                 }
-                                Ok(()) => {
-                                    // Step 3 – validate block-order sequences
-                                    if self.current_blocks.is_empty() {
-                                        self.status_message =
-                                            "No code-block connections found".to_string();
-                                    } else {
-                                        match self.validate_current_sequences() {
-                                            Ok(()) => {
-                                                let current_blocks = self.current_blocks_json();
-                                                self.status_message = format!(
-                                                    "Connected blocks are valid: {}",
-                                                    current_blocks
-                                                );
-                                                println!(
-                                                    "Connected blocks are valid: {}",
-                                                    current_blocks
-                                                );
-                                                self.show_sequence_popup = false;
-                                                self.sequence_popup_text.clear();
-                                            }
-                                            Err(message) => {
-                                                self.status_message =
-                                                    "Invalid block order detected".to_string();
-                                                self.sequence_popup_text = message;
-                                                self.show_sequence_popup = true;
-                                            }
-                                        }
-                                    }
+                            } else {
+                                let pullup = state.mode == Some(PinMode::InputPullup);
+                                let mut high = state.level() != 0;
+                                ui.label(if pullup { "Input (pull-up)" } else { "Input" });
+                                if ui.checkbox(&mut high, "HIGH").changed() {
+                                    input_change = Some((pin, high as i64));
                                 }
                             }
+                            ui.end_row();
                         }
+                    });
+                    if let Some((pin, level)) = input_change {
+                        simulation.board.set_input(pin, level);
                     }
-                }
 
-                if ui.button("Export .ino").clicked() {
-                    match self.validate_block_inputs() {
-                        Err(message) => {
-                            self.status_message =
-                                "Invalid block inputs detected.".to_string();
-                            self.validation_popup_text = message;
-                            self.show_validation_popup = true;
+                    ui.separator();
+                    ui.strong("Variables");
+                    let variables = simulation.variables();
+                    if variables.is_empty() {
+                        ui.weak("No variables in scope.");
+                    }
+                    egui::Grid::new("simulation_variables").striped(true).show(ui, |ui| {
+                        for (name, value) in variables {
+                            ui.monospace(name);
+                            ui.monospace(value.to_text());
+                            ui.end_row();
+                        }
+                    });
+
+                    ui.separator();
+                    egui::CollapsingHeader::new("Serial monitor")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            egui::ScrollArea::vertical()
+                                .id_salt("serial_monitor")
+                                .max_height(160.0)
+                                .stick_to_bottom(true)
+                                .show(ui, |ui| {
+                                    ui.monospace(&simulation.board.serial);
+                                });
+                        });
+                    egui::CollapsingHeader::new("Pin changes").show(ui, |ui| {
+                        for event in simulation.board.events.iter().rev().take(10) {
+                            ui.monospace(format!(
+                                "{:>9.3} s  pin {:>3} → {}",
+                                event.time_us as f64 / 1_000_000.0,
+                                simulation.board.pin_label(event.pin),
+                                event.value
+                            ));
+                        }
+                    });
+                });
+        }
+        if close_simulation {
+            self.simulation = None;
+        }
+
+        // --- TIMELINE PANEL ---
+        if self.show_timeline && let Some(simulation) = &self.simulation {
+            let trace = Trace::from_board(&simulation.board, simulation.time_us);
+            let window_ms = &mut self.timeline_window_ms;
+            egui::TopBottomPanel::bottom("timeline")
+                .resizable(true)
+                .default_height(180.0)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.heading("Timeline");
+                        ui.label("Window:");
+                        ui.add(
+                            egui::Slider::new(window_ms, 1.0..=60_000.0)
+                                .logarithmic(true)
+                                .suffix(" ms"),
+                        );
+                    });
+                    ui.separator();
+                    if trace.signals.is_empty() {
+                        ui.weak("No pin has changed yet.");
+                        return;
+                    }
+
+                    let span_us = ((*window_ms * 1_000.0) as u64).max(1);
+                    let end_us = trace.end_us.max(span_us);
+                    let start_us = end_us - span_us;
+
+                    egui::ScrollArea::vertical().id_salt("timeline_rows").show(ui, |ui| {
+                        let width = ui.available_width();
+                        let rows_h = TIMELINE_ROW_H * trace.signals.len() as f32;
+                        let (rect, response) = ui.allocate_exact_size(
+                            egui::vec2(width, rows_h + 18.0),
+                            egui::Sense::hover(),
+                        );
+                        let painter = ui.painter_at(rect);
+                        let plot_left = rect.left() + TIMELINE_LABEL_W;
+                        let plot_w = (rect.right() - plot_left - 4.0).max(1.0);
+                        let x_of = |time_us: u64| {
+                            plot_left + (time_us.saturating_sub(start_us)) as f32 / span_us as f32 * plot_w
+                        };
+                        let text_colour = ui.visuals().text_color();
+                        let grid_colour = ui.visuals().widgets.noninteractive.bg_stroke.color;
+                        let wave = egui::Stroke::new(1.5, egui::Color32::from_rgb(60, 220, 90));
+
+                        // Time axis with ticks below the traces
+                        let step = trace::tick_step(span_us, 8);
+                        let axis_y = rect.top() + rows_h;
+                        let mut tick = start_us.div_ceil(step) * step;
+                        while tick <= end_us {
+                            let x = x_of(tick);
+                            painter.line_segment(
+                                [egui::pos2(x, rect.top()), egui::pos2(x, axis_y)],
+                                egui::Stroke::new(0.5, grid_colour),
+                            );
+                            let label = if step >= 1_000 {
+                                format!("{} ms", tick / 1_000)
+                            } else {
+                                format!("{} µs", tick)
+                            };
+                            painter.text(
+                                egui::pos2(x, axis_y + 2.0),
+                                egui::Align2::CENTER_TOP,
+                                label,
+                                egui::FontId::proportional(11.0),
+                                text_colour,
+                            );
+                            tick += step;
                         }
-                        Ok(()) => match self.validate_pin_consistency() {
-                            Err(message) => {
-                                self.status_message =
-                                    "Pin mismatch detected.".to_string();
-                                self.validation_popup_text = message;
-                                self.show_validation_popup = true;
+
+                        for (row, signal) in trace.signals.iter().enumerate() {
+                            let top = rect.top() + row as f32 * TIMELINE_ROW_H;
+                            let y_of = |value: i64| {
+                                let level = value.clamp(0, signal.full_scale()) as f32
+                                    / signal.full_scale() as f32;
+                                top + TIMELINE_ROW_H - 5.0 - level * (TIMELINE_ROW_H - 10.0)
+                            };
+                            painter.text(
+                                egui::pos2(rect.left() + 4.0, top + TIMELINE_ROW_H / 2.0),
+                                egui::Align2::LEFT_CENTER,
+                                &signal.name,
+                                egui::FontId::monospace(12.0),
+                                text_colour,
+                            );
+
+                            // Step waveform from the level at the window start to now;
+                            // nothing is drawn before the sketch first drives the pin
+                            let mut points = Vec::new();
+                            let mut level = signal.value_at(start_us);
+                            if let Some(value) = level {
+                                points.push(egui::pos2(x_of(start_us), y_of(value)));
                             }
-                            Ok(()) => match self.export_ino() {
-                                Ok(path) => {
-                                    self.status_message = format!(
-                                        "Exported Arduino sketch to {}",
-                                        path.display()
-                                    );
+                            for &(time, value) in
+                                signal.changes.iter().filter(|(time, _)| *time > start_us && *time <= end_us)
+                            {
+                                let x = x_of(time);
+                                if let Some(previous) = level {
+                                    points.push(egui::pos2(x, y_of(previous)));
                                 }
-                                Err(err) => {
-                                    self.status_message = err;
+                                points.push(egui::pos2(x, y_of(value)));
+                                level = Some(value);
+                            }
+                            if let Some(value) = level {
+                                points.push(egui::pos2(x_of(trace.end_us), y_of(value)));
+                            }
+                            painter.add(egui::Shape::line(points, wave));
+                        }
+
+                        // Cursor with the time and every pin's value under the pointer
+                        if let Some(pointer) = response.hover_pos()
+                            && pointer.x >= plot_left
+                        {
+                            let time = start_us + ((pointer.x - plot_left) / plot_w * span_us as f32) as u64;
+                            painter.line_segment(
+                                [egui::pos2(pointer.x, rect.top()), egui::pos2(pointer.x, axis_y)],
+                                egui::Stroke::new(1.0, text_colour),
+                            );
+                            let values: Vec<String> = trace
+                                .signals
+                                .iter()
+                                .map(|signal| match signal.value_at(time) {
+                                    Some(value) => format!("{} = {}", signal.name, value),
+                                    None => format!("{} = –", signal.name),
+                                })
+                                .collect();
+                            response.on_hover_text(format!(
+                                "{:.3} ms\n{}",
+                                time as f64 / 1_000.0,
+                                values.join("\n")
+                            ));
+                        }
+                    });
+                });
+        }
+
+        // --- LIVE CODE PREVIEW PANEL ---
+        if self.show_code_preview {
+            let mut clicked_origin: Option<Option<String>> = None;
+
+            egui::SidePanel::right("code_preview")
+                .resizable(true)
+                .default_width(380.0)
+                .show(ctx, |ui| {
+                    ui.heading("Generated code");
+                    ui.label("Click a line to highlight the block that generated it.");
+                    ui.separator();
+
+                    match &self.code_preview {
+                        Some(source) => {
+                            if !self.code_preview_error.is_empty() {
+                                ui.colored_label(
+                                    egui::Color32::from_rgb(200, 60, 60),
+                                    &self.code_preview_error,
+                                );
+                                ui.separator();
+                            }
+                            let visuals = ui.visuals().clone();
+                            egui::ScrollArea::both().show(ui, |ui| {
+                                for (n, (line, origin)) in
+                                    source.text.lines().zip(&source.line_origins).enumerate()
+                                {
+                                    let selected = origin.is_some() && *origin == self.highlighted_block;
+                                    let job = highlight_line(&format!("{:>3}  ", n + 1), line, &visuals);
+                                    if ui.selectable_label(selected, job).clicked() {
+                                        clicked_origin = Some(origin.clone());
+                                    }
                                 }
-                            },
-                        },
+                            });
+                        }
+                        None => {
+                            ui.colored_label(
+                                egui::Color32::from_rgb(200, 60, 60),
+                                &self.code_preview_error,
+                            );
+                        }
                     }
-                }
+                });
 
-                if !self.status_message.is_empty() {
-                    ui.label(&self.status_message);
+            if let Some(origin) = clicked_origin {
+                self.highlighted_block = origin;
+            }
+        }
+
+        // --- DIAGNOSTICS PANEL ---
+        if self.show_diagnostics {
+            let mut clicked_block: Option<String> = None;
+            let mut close_requested = false;
+            let mut fix_request: Option<QuickFix> = None;
+
+            egui::TopBottomPanel::bottom("diagnostics")
+                .resizable(true)
+                .default_height(160.0)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.heading("Diagnostics");
+                        ui.label(self.diagnostics_summary());
+                        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                            if ui.button("Close").clicked() {
+                                close_requested = true;
+                            }
+                        });
+                    });
+                    ui.separator();
+
+                    if self.diagnostics.is_empty() {
+                        ui.label("No problems found.");
+                    }
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        for diagnostic in &self.diagnostics {
+                            let (icon, colour) = match diagnostic.severity {
+                                Severity::Error => ("⛔", egui::Color32::from_rgb(200, 60, 60)),
+                                Severity::Warning => ("⚠", egui::Color32::from_rgb(220, 150, 40)),
+                            };
+                            let block = diagnostic.instance_id.as_deref().and_then(|id| {
+                                self.sections.iter().position(|b| b.instance_id == id)
+                            });
+                            let mut location = block
+                                .map(|idx| self.block_unique_id(idx))
+                                .unwrap_or_else(|| "Workspace".to_string());
+                            if let Some(input) = &diagnostic.input {
+                                location.push_str(&format!(" › {input}"));
+                            }
+
+                            ui.horizontal(|ui| {
+                                ui.colored_label(colour, icon);
+                                let label = egui::Label::new(egui::RichText::new(location).strong());
+                                if block.is_none() {
+                                    ui.add(label);
+                                } else if ui
+                                    .add(label.sense(egui::Sense::click()))
+                                    .on_hover_text("Click to highlight the block")
+                                    .clicked()
+                                {
+                                    clicked_block = diagnostic.instance_id.clone();
+                                }
+                                ui.vertical(|ui| {
+                                    ui.label(&diagnostic.message);
+                                    if let Some(suggestion) = &diagnostic.suggestion {
+                                        ui.weak(format!("Suggested: {suggestion}"));
+                                    }
+                                    for (n, candidate) in diagnostic.candidates.iter().enumerate() {
+                                        ui.horizontal(|ui| {
+                                            ui.weak(format!(
+                                                "Option {}: {}",
+                                                n + 1,
+                                                Self::join_sequence(&candidate.sequence)
+                                            ));
+                                            ui.label(format!("({})", candidate.edits.join(", ")));
+                                            if let Some(fix) = &candidate.fix
+                                                && ui.small_button("Apply").clicked()
+                                            {
+                                                fix_request = Some(fix.clone());
+                                            }
+                                        });
+                                    }
+                                });
+                                if let Some(fix) = &diagnostic.fix
+                                    && ui.button(format!("Apply: {}", fix.label())).clicked()
+                                {
+                                    fix_request = Some(fix.clone());
+                                }
+                            });
+                            ui.separator();
+                        }
+                    });
+                });
+
+            if clicked_block.is_some() {
+                self.highlighted_block = clicked_block;
+            }
+            if let Some(fix) = fix_request {
+                if let Err(err) = self.apply_fix(&fix) {
+                    self.status_message = err;
+                    self.refresh_diagnostics();
                 }
-            });
-        });
+            }
+            if close_requested {
+                self.show_diagnostics = false;
+            }
+        }
+
 
         egui::CentralPanel::default().show(ctx, |ui| {
             // --- CENTRAL CANVAS ---
@@ -1410,10 +4257,35 @@ impl eframe::App for BlocksForArduino {
             self.palette_scroll_offset =
                 (self.palette_scroll_offset - scroll_delta_y).clamp(0.0, max_scroll);
 
+            // Blocks and inputs named by the diagnostics panel, outlined red for errors
+            // and orange for warnings. Errors sort first so they win over warnings.
+            let mut flagged_blocks: HashMap<String, egui::Color32> = HashMap::new();
+            let mut flagged_inputs: HashMap<(String, String), egui::Color32> = HashMap::new();
+            if self.show_diagnostics {
+                for diagnostic in &self.diagnostics {
+                    let Some(id) = &diagnostic.instance_id else {
+                        continue;
+                    };
+                    let colour = match diagnostic.severity {
+                        Severity::Error => egui::Color32::from_rgb(220, 40, 40),
+                        Severity::Warning => egui::Color32::from_rgb(230, 150, 30),
+                    };
+                    flagged_blocks.entry(id.clone()).or_insert(colour);
+                    if let Some(input) = &diagnostic.input {
+                        flagged_inputs.entry((id.clone(), input.clone())).or_insert(colour);
+                    }
+                }
+            }
+
+            let executing_block = match self.run_state {
+                RunState::Stopped => None,
+                _ => self.simulation.as_ref().and_then(Simulator::position),
+            };
+
             // ---- blocks -----------------------------------------------------
-            let size = egui::vec2(GLOBAL_X, GLOBAL_Y);
             for i in 0..self.sections.len() {
                 let is_palette = self.sections[i].is_palette;
+                let size = self.block_size(i);
 
                 // For palette blocks apply the scroll offset to the render position.
                 let render_pos = if is_palette {
@@ -1436,10 +4308,16 @@ impl eframe::App for BlocksForArduino {
 
                 if response.clicked() && is_palette {
                     self.spawn_code_block(i);
+                    edited = true;
+                }
+
+                if response.clicked() && !is_palette {
+                    self.highlighted_block = Some(self.sections[i].instance_id.clone());
                 }
 
                 if response.drag_started() && !is_palette {
                     self.detach_block(i);
+                    edited = true;
                 }
 
                 if response.dragged() && !is_palette {
@@ -1452,8 +4330,23 @@ impl eframe::App for BlocksForArduino {
                     self.try_snap(i);
                 }
 
-                if response.secondary_clicked() && !is_palette {
-                    delete_request = Some(i);
+                if !is_palette {
+                    let instance_id = self.sections[i].instance_id.clone();
+                    response.context_menu(|ui| {
+                        let label = if self.breakpoints.contains(&instance_id) {
+                            "Remove breakpoint"
+                        } else {
+                            "Add breakpoint"
+                        };
+                        if ui.button(label).clicked() {
+                            self.toggle_breakpoint(&instance_id);
+                            ui.close();
+                        }
+                        if ui.button("Delete block").clicked() {
+                            delete_request = Some(i);
+                            ui.close();
+                        }
+                    });
                 }
 
                 // Clip palette blocks so they don't overdraw outside their column.
@@ -1469,7 +4362,82 @@ impl eframe::App for BlocksForArduino {
                     .map(|c| parse_hex_colour(c))
                     .unwrap_or(egui::Color32::from_rgb(80, 160, 240));
 
-                painter.rect_filled(rect, 6.0, color);
+                // Hat blocks get a rounded cap so the start of each stack stands out,
+                // and reporter blocks are drawn as rounded pills
+                let corner_radius = if self.sections[i].hat.is_some() {
+                    egui::CornerRadius {
+                        nw: 24,
+                        ne: 24,
+                        sw: 6,
+                        se: 6,
+                    }
+                } else if self.sections[i].output.is_some() {
+                    egui::CornerRadius::same(14)
+                } else {
+                    egui::CornerRadius::same(6)
+                };
+                painter.rect_filled(rect, corner_radius, color);
+
+                // Block selected by clicking it or a line in the code preview
+                if !is_palette
+                    && self.highlighted_block.as_deref() == Some(self.sections[i].instance_id.as_str())
+                {
+                    painter.rect_stroke(
+                        rect,
+                        corner_radius,
+                        egui::Stroke::new(3.0, egui::Color32::YELLOW),
+                        egui::StrokeKind::Outside,
+                    );
+                }
+
+                // Block the program on the virtual board is running or paused at
+                if !is_palette && executing_block.as_deref() == Some(self.sections[i].instance_id.as_str()) {
+                    painter.rect_stroke(
+                        rect,
+                        corner_radius,
+                        egui::Stroke::new(3.0, egui::Color32::from_rgb(60, 220, 90)),
+                        egui::StrokeKind::Outside,
+                    );
+                }
+
+                // Breakpoint marker on the left edge
+                if !is_palette && self.breakpoints.contains(&self.sections[i].instance_id) {
+                    painter.circle_filled(
+                        rect.left_top() + egui::vec2(0.0, 12.0),
+                        5.0,
+                        egui::Color32::from_rgb(220, 40, 40),
+                    );
+                }
+
+                // Block with a problem listed in the diagnostics panel
+                if let Some(colour) = flagged_blocks.get(&self.sections[i].instance_id) {
+                    painter.rect_stroke(
+                        rect,
+                        corner_radius,
+                        egui::Stroke::new(2.0, *colour),
+                        egui::StrokeKind::Inside,
+                    );
+                }
+
+                // Statement slots: a coloured spine down the left with each slot's name
+                if !is_palette && !self.sections[i].slots.is_empty() {
+                    let spine = egui::Rect::from_min_size(
+                        render_pos + egui::vec2(0.0, GLOBAL_Y - 6.0),
+                        egui::vec2(SLOT_INDENT - 8.0, self.block_body_height(i) - GLOBAL_Y + 6.0),
+                    );
+                    painter.rect_filled(spine, 4.0, color);
+
+                    for slot_pos in 0..self.sections[i].slots.len() {
+                        let origin = self.slot_origin(i, slot_pos);
+                        painter.text(
+                            egui::pos2(render_pos.x + SLOT_INDENT, origin.y - SLOT_LABEL_H + 2.0),
+                            egui::Align2::LEFT_TOP,
+                            &self.sections[i].slots[slot_pos].name,
+                            egui::TextStyle::Small.resolve(ui.style()),
+                            ui.style().visuals.text_color(),
+                        );
+                    }
+                }
 
                 let label = self.sections[i]
                     .shown_element
@@ -1484,7 +4452,7 @@ impl eframe::App for BlocksForArduino {
                     ui.style().visuals.text_color(),
                 );
 
-                let mut y_offset = 26.0;
+                let mut y_offset = INPUT_ROW_Y;
 
                 if is_palette {
                     // Palette blocks: render input names as static clipped text so they
@@ -1500,18 +4468,72 @@ impl eframe::App for BlocksForArduino {
                         y_offset += 18.0;
                     }
                 } else {
-                    let block = &mut self.sections[i];
-                    for (key, value) in block.input_values.iter_mut() {
+                    let inputs = self.sections[i].inputs.clone();
+                    for input in &inputs {
+                        let key = input.name.clone();
                         let input_rect = egui::Rect::from_min_size(
                             render_pos + egui::vec2(10.0, y_offset),
                             egui::vec2(100.0, 15.0),
                         );
 
-                        ui.scope_builder(egui::UiBuilder::new().max_rect(input_rect), |ui| {
-                            ui.add(egui::TextEdit::singleline(value).hint_text(key));
-                        });
+                        if self.input_child(i, &key).is_some() {
+                            // The value comes from the reporter plugged in beside this row
+                            painter.text(
+                                input_rect.left_top(),
+                                egui::Align2::LEFT_TOP,
+                                format!("{} \u{25B6}", key),
+                                egui::TextStyle::Small.resolve(ui.style()),
+                                ui.style().visuals.text_color(),
+                            );
+                        } else {
+                            let widget = self.input_widget(i, input);
+                            let unit = self.input_unit(input).map(str::to_string);
+
+                            // Check the value as it is typed; fields left empty are only
+                            // reported by "Check connections"
+                            let error = self.sections[i]
+                                .input_values
+                                .get(&key)
+                                .is_some_and(|v| !v.trim().is_empty())
+                                .then(|| self.validate_typed_input(i, input).err())
+                                .flatten();
+
+                            let id = format!("{}/{}", self.sections[i].instance_id, key);
+                            let value = self.sections[i].input_values.entry(key.clone()).or_default();
+                            ui.scope_builder(egui::UiBuilder::new().max_rect(input_rect), |ui| {
+                                edited |= Self::show_input_widget(
+                                    ui,
+                                    &id,
+                                    value,
+                                    &widget,
+                                    &key,
+                                    error.as_deref(),
+                                )
+                                .changed();
+                            });
+                            if let Some(unit) = unit {
+                                painter.text(
+                                    input_rect.right_center() + egui::vec2(4.0, 0.0),
+                                    egui::Align2::LEFT_CENTER,
+                                    unit,
+                                    egui::TextStyle::Small.resolve(ui.style()),
+                                    ui.style().visuals.text_color(),
+                                );
+                            }
+                        }
 
-                        y_offset += 22.0;
+                        if let Some(colour) =
+                            flagged_inputs.get(&(self.sections[i].instance_id.clone(), key))
+                        {
+                            painter.rect_stroke(
+                                input_rect.expand(2.0),
+                                2.0,
+                                egui::Stroke::new(2.0, *colour),
+                                egui::StrokeKind::Outside,
+                            );
+                        }
+
+                        y_offset += INPUT_ROW_H;
                     }
                 }
             }
@@ -1538,46 +4560,377 @@ impl eframe::App for BlocksForArduino {
             }
         });
 
+        // The Delete key removes the selected block, unless a text field is being edited
+        if delete_request.is_none()
+            && !ctx.wants_keyboard_input()
+            && ctx.input(|i| i.key_pressed(egui::Key::Delete))
+        {
+            delete_request = self
+                .highlighted_block
+                .as_deref()
+                .and_then(|instance_id| self.block_by_instance(instance_id));
+        }
+
         if let Some(idx) = delete_request {
             self.delete_block(idx);
+            edited = true;
         }
 
-        if self.show_sequence_popup {
-            let mut open = self.show_sequence_popup;
-            let mut close_requested = false;
+        // --- Variables window ---
+        if self.show_variables_window {
+            let mut open = self.show_variables_window;
+            let mut remove_request: Option<usize> = None;
+            let before = self.variables.clone();
 
-            egui::Window::new("Sequence suggestion")
+            egui::Window::new("Variables")
                 .collapsible(false)
-                .resizable(false)
+                .resizable(true)
                 .open(&mut open)
                 .show(ctx, |ui| {
-                    ui.label(&self.sequence_popup_text);
+                    egui::Grid::new("variables_grid")
+                        .num_columns(4)
+                        .spacing([8.0, 4.0])
+                        .show(ui, |ui| {
+                            ui.label("Name");
+                            ui.label("Type");
+                            ui.label("Initial value");
+                            ui.end_row();
+
+                            for (i, variable) in self.variables.iter_mut().enumerate() {
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut variable.name)
+                                        .desired_width(110.0),
+                                );
+                                egui::ComboBox::from_id_salt(("variable_type", i))
+                                    .selected_text(variable.cpp_type.as_str())
+                                    .show_ui(ui, |ui| {
+                                        for ty in VARIABLE_TYPES {
+                                            ui.selectable_value(
+                                                &mut variable.cpp_type,
+                                                ty.to_string(),
+                                                ty,
+                                            );
+                                        }
+                                    });
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut variable.initial_value)
+                                        .desired_width(80.0),
+                                );
+                                if ui.button("🗑").clicked() {
+                                    remove_request = Some(i);
+                                }
+                                ui.end_row();
+                            }
+                        });
+
                     ui.add_space(8.0);
-                    if ui.button("Close").clicked() {
-                        close_requested = true;
+                    if ui.button("Add variable").clicked() {
+                        let name = (1..)
+                            .map(|n| format!("var{n}"))
+                            .find(|name| Self::find_variable(&self.variables, name).is_none())
+                            .unwrap_or_default();
+                        self.variables.push(VariableDefinition {
+                            name,
+                            cpp_type: "int".to_string(),
+                            initial_value: "0".to_string(),
+                        });
+                    }
+
+                    let problems = self.variable_diagnostics();
+                    if !problems.is_empty() {
+                        ui.add_space(4.0);
+                    }
+                    for problem in problems {
+                        ui.colored_label(egui::Color32::from_rgb(200, 60, 60), &problem.message);
+                        if let Some(suggestion) = &problem.suggestion {
+                            ui.weak(format!("Suggested: {suggestion}"));
+                        }
                     }
                 });
 
-            self.show_sequence_popup = open && !close_requested;
+            if let Some(i) = remove_request {
+                self.variables.remove(i);
+            }
+            edited |= self.variables != before;
+            self.show_variables_window = open;
         }
 
-        if self.show_validation_popup {
-            let mut open = self.show_validation_popup;
-            let mut close_requested = false;
+        // --- Breadboard window ---
+        if self.show_breadboard {
+            let mut open = self.show_breadboard;
+            let mut add_request: Option<ComponentKind> = None;
+            let mut remove_request: Option<usize> = None;
+            let before = self.components.clone();
+            let pin_choices: Vec<(ComponentKind, Vec<String>)> = ComponentKind::ALL
+                .iter()
+                .map(|&kind| (kind, self.board_pins_for_rule(Self::component_pin_rule(kind))))
+                .collect();
+            let pin_problems: Vec<Option<String>> = self
+                .components
+                .iter()
+                .map(|component| {
+                    self.validate_board_pin(&component.pin, Self::component_pin_rule(component.kind), "Pin")
+                        .err()
+                        .and_then(|err| err.lines().next().map(str::to_string))
+                })
+                .collect();
+            let board = self.simulation.as_ref().map(|simulation| &simulation.board);
+
+            egui::Window::new("Breadboard")
+                .collapsible(false)
+                .resizable(true)
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Add:");
+                        for kind in ComponentKind::ALL {
+                            if ui.button(kind.name()).clicked() {
+                                add_request = Some(kind);
+                            }
+                        }
+                    });
+                    ui.separator();
+                    if self.components.is_empty() {
+                        ui.weak("Nothing is wired to the board yet.");
+                    }
+
+                    egui::Grid::new("breadboard_grid")
+                        .num_columns(5)
+                        .spacing([8.0, 6.0])
+                        .show(ui, |ui| {
+                            for (i, component) in self.components.iter_mut().enumerate() {
+                                let drive = Self::component_drive(board, &component.pin);
+                                let (rect, response) = ui.allocate_exact_size(
+                                    egui::vec2(28.0, 28.0),
+                                    egui::Sense::click(),
+                                );
+                                let painter = ui.painter();
+                                let centre = rect.center();
+                                match component.kind {
+                                    ComponentKind::Led => {
+                                        let lit = parse_hex_colour(&component.colour);
+                                        let colour = egui::Color32::from_gray(50).lerp_to_gamma(lit, drive);
+                                        if drive > 0.0 {
+                                            painter.circle_filled(centre, 13.0, lit.gamma_multiply(0.3 * drive));
+                                        }
+                                        painter.circle_filled(centre, 9.0, colour);
+                                    }
+                                    ComponentKind::Button => {
+                                        // Held down while the mouse button is
+                                        component.pressed = response.is_pointer_button_down_on();
+                                        let cap = if component.pressed {
+                                            egui::Color32::from_rgb(120, 30, 30)
+                                        } else {
+                                            egui::Color32::from_rgb(200, 50, 50)
+                                        };
+                                        painter.rect_filled(rect.shrink(3.0), 3.0, egui::Color32::from_gray(70));
+                                        painter.circle_filled(centre, if component.pressed { 6.0 } else { 7.5 }, cap);
+                                    }
+                                    ComponentKind::Potentiometer => {
+                                        let turn = component.position.clamp(0, 1023) as f32 / 1023.0;
+                                        let angle = std::f32::consts::PI * (0.75 + 1.5 * turn);
+                                        painter.circle_filled(centre, 11.0, egui::Color32::from_rgb(40, 90, 160));
+                                        painter.line_segment(
+                                            [centre, centre + 9.0 * egui::vec2(angle.cos(), angle.sin())],
+                                            egui::Stroke::new(2.0, egui::Color32::WHITE),
+                                        );
+                                    }
+                                    ComponentKind::Buzzer => {
+                                        painter.circle_filled(centre, 10.0, egui::Color32::from_gray(30));
+                                        painter.circle_filled(centre, 2.5, egui::Color32::from_gray(120));
+                                        if drive > 0.0 {
+                                            let stroke = egui::Stroke::new(1.5, ui.visuals().text_color());
+                                            painter.circle_stroke(centre, 12.5, stroke);
+                                            painter.circle_stroke(centre, 15.5, (1.0, stroke.color.gamma_multiply(0.5)));
+                                        }
+                                    }
+                                }
+                                if component.kind == ComponentKind::Button {
+                                    response.on_hover_text("Hold to press");
+                                }
+
+                                ui.label(component.kind.name());
+
+                                let choices = pin_choices
+                                    .iter()
+                                    .find(|(kind, _)| *kind == component.kind)
+                                    .map(|(_, pins)| pins.as_slice())
+                                    .unwrap_or_default();
+                                ui.vertical(|ui| {
+                                    egui::ComboBox::from_id_salt(("component_pin", i))
+                                        .selected_text(format!("Pin {}", component.pin))
+                                        .show_ui(ui, |ui| {
+                                            for pin in choices {
+                                                ui.selectable_value(&mut component.pin, pin.clone(), pin);
+                                            }
+                                        });
+                                    if let Some(problem) = &pin_problems[i] {
+                                        ui.colored_label(egui::Color32::from_rgb(200, 60, 60), problem);
+                                    }
+                                });
+
+                                match component.kind {
+                                    ComponentKind::Led => {
+                                        let mut colour = parse_hex_colour(&component.colour);
+                                        if ui.color_edit_button_srgba(&mut colour).changed() {
+                                            component.colour = format!(
+                                                "#{:02x}{:02x}{:02x}",
+                                                colour.r(),
+                                                colour.g(),
+                                                colour.b()
+                                            );
+                                        }
+                                    }
+                                    ComponentKind::Button => {
+                                        ui.label(if component.pressed { "Pressed" } else { "Released" });
+                                    }
+                                    ComponentKind::Potentiometer => {
+                                        ui.add(egui::Slider::new(&mut component.position, 0..=1023));
+                                    }
+                                    ComponentKind::Buzzer => {
+                                        ui.label(if drive > 0.0 { "Sounding" } else { "Silent" });
+                                    }
+                                }
+
+                                if ui.button("🗑").clicked() {
+                                    remove_request = Some(i);
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+
+            if let Some(kind) = add_request {
+                self.add_component(kind);
+            }
+            if let Some(i) = remove_request {
+                self.components.remove(i);
+            }
+            // A paused program sees the new inputs straight away
+            if self.components != before {
+                self.apply_components();
+            }
+            // Pressing a button or turning a knob only drives the running program
+            edited |= self.components.len() != before.len()
+                || self
+                    .components
+                    .iter()
+                    .zip(&before)
+                    .any(|(now, was)| now.pin != was.pin || now.colour != was.colour);
+            self.show_breadboard = open;
+        }
+
+        if edited {
+            self.workspace_edited();
+        }
+
+        // --- Grader window ---
+        if let Some(receiver) = &self.grading {
+            match receiver.try_recv() {
+                Ok(report) => {
+                    self.status_message = format!(
+                        "Graded against '{}': {}",
+                        report.assessment,
+                        if report.passed() { "pass" } else { "fail" }
+                    );
+                    self.grade_report = Some(report);
+                    self.grading = None;
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    ctx.request_repaint_after(std::time::Duration::from_millis(100));
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.status_message = "Grading stopped without a report".to_string();
+                    self.grading = None;
+                }
+            }
+        }
+        if self.show_grader {
+            let mut open = self.show_grader;
+            let mut grade_request = false;
 
-            egui::Window::new("Input validation error")
+            egui::Window::new("Grade")
                 .collapsible(false)
                 .resizable(true)
                 .open(&mut open)
                 .show(ctx, |ui| {
-                    ui.label(&self.validation_popup_text);
-                    ui.add_space(8.0);
-                    if ui.button("Close").clicked() {
-                        close_requested = true;
+                    if self.assessments.is_empty() {
+                        ui.weak("No assessments found in Assessments/.");
+                        return;
+                    }
+                    self.selected_assessment = self.selected_assessment.min(self.assessments.len() - 1);
+                    ui.horizontal(|ui| {
+                        ui.label("Assessment:");
+                        egui::ComboBox::from_id_salt("assessment")
+                            .selected_text(&self.assessments[self.selected_assessment].name)
+                            .show_ui(ui, |ui| {
+                                for (i, assessment) in self.assessments.iter().enumerate() {
+                                    if ui
+                                        .selectable_value(&mut self.selected_assessment, i, &assessment.name)
+                                        .changed()
+                                    {
+                                        self.grade_report = None;
+                                        self.grading = None;
+                                    }
+                                }
+                            });
+                        if ui
+                            .add_enabled(self.grading.is_none(), egui::Button::new("Grade workspace"))
+                            .clicked()
+                        {
+                            grade_request = true;
+                        }
+                        if self.grading.is_some() {
+                            ui.spinner();
+                            ui.weak("Running the program…");
+                        }
+                    });
+                    let description = &self.assessments[self.selected_assessment].description;
+                    if !description.is_empty() {
+                        ui.weak(description);
+                    }
+
+                    let Some(report) = &self.grade_report else {
+                        return;
+                    };
+                    ui.separator();
+                    let pass = egui::Color32::from_rgb(60, 170, 80);
+                    let fail = egui::Color32::from_rgb(200, 60, 60);
+                    ui.colored_label(
+                        if report.passed() { pass } else { fail },
+                        format!(
+                            "{} – {}/{} checks passed",
+                            if report.passed() { "PASS" } else { "FAIL" },
+                            report.passed_count(),
+                            report.results.len()
+                        ),
+                    );
+                    if let Some(error) = &report.error {
+                        ui.colored_label(fail, error);
                     }
+                    egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                        for result in &report.results {
+                            ui.horizontal(|ui| {
+                                if result.passed {
+                                    ui.colored_label(pass, "✔");
+                                } else {
+                                    ui.colored_label(fail, "✖");
+                                }
+                                ui.label(&result.description);
+                            });
+                            ui.indent(("grade_details", &result.description), |ui| {
+                                for detail in &result.details {
+                                    ui.weak(detail);
+                                }
+                            });
+                        }
+                    });
                 });
 
-            self.show_validation_popup = open && !close_requested;
+            if grade_request {
+                self.start_grading(self.assessments[self.selected_assessment].clone());
+            }
+            self.show_grader = open;
         }
 
         // --- Open workspace dialog ---
@@ -1612,7 +4965,8 @@ impl eframe::App for BlocksForArduino {
 
             if let Some(name) = selected {
                 match self.load_workspace_by_name(&name) {
-                    Ok(()) => self.status_message = format!("Opened \"{}\"", name),
+                    Ok(None) => self.status_message = format!("Opened \"{}\"", name),
+                    Ok(Some(note)) => self.status_message = format!("Opened \"{}\". {}", name, note),
                     Err(e) => self.status_message = e,
                 }
                 close_requested = true;
@@ -0,0 +1,340 @@
+// Automatic grading of workspaces against an assessment
+// Runs a program on the virtual board for the assessment's duration, applying its
+// stimuli on the way, then checks the recorded pin trace and serial output against
+// each expectation and explains every check that failed.
+
+use crate::model::{Assessment, PinCheck};
+use crate::sim::Simulator;
+use crate::trace::{Signal, Trace};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// Most statements run between two stimuli before the program is reported as stuck;
+/// far more than any sketch needs for a few seconds of simulated time
+const MAX_GRADE_STEPS: usize = 50_000_000;
+
+/// Most problems listed for one check before the rest are only counted
+const MAX_LISTED_PROBLEMS: usize = 5;
+
+/* ---------- REPORTS ---------- */
+
+/// The outcome of one check of an assessment
+#[derive(Clone, Debug)]
+pub struct CheckResult {
+    /// The expectation in words, e.g. "Pin 12 toggles every 100 ms ± 5 ms ..."
+    pub description: String,
+    pub passed: bool,
+    /// What was measured, and for failures where the run went wrong
+    pub details: Vec<String>,
+}
+
+impl CheckResult {
+    /// A result with a single line of detail
+    fn new(description: String, passed: bool, detail: String) -> Self {
+        CheckResult { description, passed, details: vec![detail] }
+    }
+}
+
+/// The outcome of grading one workspace
+#[derive(Clone, Debug)]
+pub struct GradeReport {
+    /// Name of the assessment the workspace was graded against
+    pub assessment: String,
+    pub results: Vec<CheckResult>,
+    /// Why the program could not be built or stopped early, if it did
+    pub error: Option<String>,
+}
+
+impl GradeReport {
+    /// A report for a program that could not be started at all
+    pub fn not_run(assessment: &Assessment, error: String) -> Self {
+        GradeReport {
+            assessment: assessment.name.clone(),
+            results: Vec::new(),
+            error: Some(error),
+        }
+    }
+
+    /// True if the program ran without errors and met every check
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.results.iter().all(|result| result.passed)
+    }
+
+    /// Number of checks met
+    pub fn passed_count(&self) -> usize {
+        self.results.iter().filter(|result| result.passed).count()
+    }
+
+    /// Formats the report for the terminal, one check per line with its details
+    /// indented below it
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}: {} ({}/{} checks passed)",
+            self.assessment,
+            if self.passed() { "PASS" } else { "FAIL" },
+            self.passed_count(),
+            self.results.len()
+        );
+        if let Some(error) = &self.error {
+            let _ = writeln!(out, "  Program error: {}", error.replace('\n', "\n    "));
+        }
+        for result in &self.results {
+            let _ = writeln!(
+                out,
+                "  [{}] {}",
+                if result.passed { "PASS" } else { "FAIL" },
+                result.description
+            );
+            for detail in &result.details {
+                let _ = writeln!(out, "         {detail}");
+            }
+        }
+        out
+    }
+}
+
+/* ---------- GRADING ---------- */
+
+/// Runs a program for the assessment's duration and checks the result.
+///
+/// # Arguments
+/// * `assessment` - The expectations to check and the stimuli to apply
+/// * `simulation` - The program, freshly started, with any breadboard inputs applied
+///
+/// # Returns
+/// The report; a runtime error is recorded in it and the checks are made against
+/// what the program did until then
+pub fn grade(assessment: &Assessment, mut simulation: Simulator) -> GradeReport {
+    let end_us = ms_to_us(assessment.duration_ms);
+    let error = run(assessment, &mut simulation, end_us).err();
+    let trace = Trace::from_board(&simulation.board, end_us);
+    let results = assessment
+        .checks
+        .iter()
+        .map(|check| check_one(check, &simulation, &trace, end_us))
+        .collect();
+    GradeReport {
+        assessment: assessment.name.clone(),
+        results,
+        error,
+    }
+}
+
+/// Runs the program to `end_us`, applying each stimulus when its time comes
+fn run(assessment: &Assessment, simulation: &mut Simulator, end_us: u64) -> Result<(), String> {
+    let mut stimuli: Vec<_> = assessment
+        .stimuli
+        .iter()
+        .filter(|stimulus| ms_to_us(stimulus.at_ms) <= end_us)
+        .collect();
+    stimuli.sort_by(|a, b| a.at_ms.total_cmp(&b.at_ms));
+
+    let mut elapsed = 0;
+    for stimulus in stimuli {
+        run_until(simulation, &mut elapsed, ms_to_us(stimulus.at_ms))?;
+        let pin = simulation
+            .board
+            .pin_number(&stimulus.pin)
+            .ok_or_else(|| format!("The stimulus pin '{}' is not a pin on the board.", stimulus.pin))?;
+        simulation.board.set_input(pin, stimulus.value);
+    }
+    run_until(simulation, &mut elapsed, end_us)
+}
+
+/// Advances the simulated clock from `elapsed` to `until_us`
+fn run_until(simulation: &mut Simulator, elapsed: &mut u64, until_us: u64) -> Result<(), String> {
+    simulation.advance(until_us.saturating_sub(*elapsed), MAX_GRADE_STEPS, &HashSet::new())?;
+    *elapsed = until_us;
+    if simulation.time_us < until_us {
+        return Err(format!(
+            "The program was still at {} ms after {MAX_GRADE_STEPS} statements.",
+            format_ms(simulation.time_us)
+        ));
+    }
+    Ok(())
+}
+
+/// Checks one expectation against the finished run
+fn check_one(check: &PinCheck, simulation: &Simulator, trace: &Trace, end_us: u64) -> CheckResult {
+    match check {
+        PinCheck::Toggles {
+            pin,
+            period_ms,
+            tolerance_ms,
+            from_ms,
+            to_ms,
+        } => {
+            let (from, to) = window(*from_ms, *to_ms, end_us);
+            let description = format!(
+                "Pin {pin} toggles every {period_ms} ms ± {tolerance_ms} ms from {} to {} ms",
+                format_ms(from),
+                format_ms(to)
+            );
+            match find_signal(simulation, trace, pin) {
+                Ok(signal) => check_toggles(
+                    description,
+                    change_times(signal, from, to),
+                    (from, to),
+                    ms_to_us(*period_ms),
+                    ms_to_us(*tolerance_ms),
+                ),
+                Err(err) => CheckResult::new(description, false, err),
+            }
+        }
+        PinCheck::Level { pin, value, at_ms } => {
+            let description = format!("Pin {pin} is {value} at {at_ms} ms");
+            match find_signal(simulation, trace, pin) {
+                Ok(signal) => match signal.and_then(|signal| signal.value_at(ms_to_us(*at_ms))) {
+                    Some(level) => CheckResult::new(description, level == *value, format!("It was {level}.")),
+                    None => CheckResult::new(description, false, "The program had not driven it yet.".to_string()),
+                },
+                Err(err) => CheckResult::new(description, false, err),
+            }
+        }
+        PinCheck::Changes {
+            pin,
+            min,
+            max,
+            from_ms,
+            to_ms,
+        } => {
+            let (from, to) = window(*from_ms, *to_ms, end_us);
+            let limits = match max {
+                Some(max) if max == min => format!("exactly {min}"),
+                Some(max) => format!("{min} to {max}"),
+                None => format!("at least {min}"),
+            };
+            let description = format!(
+                "Pin {pin} changes {limits} times from {} to {} ms",
+                format_ms(from),
+                format_ms(to)
+            );
+            match find_signal(simulation, trace, pin) {
+                Ok(signal) => {
+                    let count = change_times(signal, from, to).len();
+                    let passed = count >= *min && max.is_none_or(|max| count <= max);
+                    CheckResult::new(description, passed, format!("It changed {count} times."))
+                }
+                Err(err) => CheckResult::new(description, false, err),
+            }
+        }
+        PinCheck::Serial { contains } => {
+            let description = format!("The serial monitor shows \"{contains}\"");
+            let serial = &simulation.board.serial;
+            if serial.contains(contains.as_str()) {
+                CheckResult::new(description, true, "Found in the serial output.".to_string())
+            } else if serial.is_empty() {
+                CheckResult::new(description, false, "Nothing was printed.".to_string())
+            } else {
+                let shown: String = serial.chars().take(80).collect();
+                let more = if shown.len() < serial.len() { "…" } else { "" };
+                CheckResult::new(description, false, format!("The output was: {:?}{more}", shown))
+            }
+        }
+    }
+}
+
+/// Checks that changes come one period apart and cover the whole window
+fn check_toggles(
+    description: String,
+    times: Vec<u64>,
+    (from, to): (u64, u64),
+    period: u64,
+    tolerance: u64,
+) -> CheckResult {
+    if times.len() < 2 {
+        let detail = format!(
+            "It changed {} time{} in that window, so it never toggled.",
+            times.len(),
+            if times.len() == 1 { "" } else { "s" }
+        );
+        return CheckResult::new(description, false, detail);
+    }
+
+    let mut problems = Vec::new();
+    let first = times[0];
+    let last = times[times.len() - 1];
+    if first - from > period + tolerance {
+        problems.push(format!("It did not change until {} ms.", format_ms(first)));
+    }
+    for pair in times.windows(2) {
+        let gap = pair[1] - pair[0];
+        if gap.abs_diff(period) > tolerance {
+            problems.push(format!(
+                "It changed {} ms apart at {} ms and {} ms.",
+                format_ms(gap),
+                format_ms(pair[0]),
+                format_ms(pair[1])
+            ));
+        }
+    }
+    if to - last > period + tolerance {
+        problems.push(format!("It stopped changing after {} ms.", format_ms(last)));
+    }
+
+    if problems.is_empty() {
+        let gaps = times.windows(2).map(|pair| pair[1] - pair[0]);
+        let shortest = gaps.clone().min().unwrap_or(0);
+        let longest = gaps.max().unwrap_or(0);
+        let detail = format!(
+            "{} changes, {}–{} ms apart.",
+            times.len(),
+            format_ms(shortest),
+            format_ms(longest)
+        );
+        return CheckResult::new(description, true, detail);
+    }
+
+    let count = problems.len();
+    let mut details: Vec<String> = problems.into_iter().take(MAX_LISTED_PROBLEMS).collect();
+    if count > MAX_LISTED_PROBLEMS {
+        details.push(format!("…and {} more.", count - MAX_LISTED_PROBLEMS));
+    }
+    CheckResult {
+        description,
+        passed: false,
+        details,
+    }
+}
+
+/// Finds the recorded signal of a pin named in a check. Ok(None) means the program
+/// never drove the pin.
+fn find_signal<'a>(simulation: &Simulator, trace: &'a Trace, pin: &str) -> Result<Option<&'a Signal>, String> {
+    let number = simulation
+        .board
+        .pin_number(pin)
+        .ok_or_else(|| format!("'{pin}' is not a pin on the board."))?;
+    Ok(trace.signal(number))
+}
+
+/// Times at which a signal changed within a window, oldest first
+fn change_times(signal: Option<&Signal>, from: u64, to: u64) -> Vec<u64> {
+    signal
+        .map(|signal| {
+            signal
+                .changes
+                .iter()
+                .map(|(time, _)| *time)
+                .filter(|time| (from..=to).contains(time))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Resolves a check's window to microseconds, defaulting the end to the end of the run
+fn window(from_ms: f64, to_ms: Option<f64>, end_us: u64) -> (u64, u64) {
+    let to = to_ms.map_or(end_us, ms_to_us).min(end_us);
+    (ms_to_us(from_ms).min(to), to)
+}
+
+/// Converts milliseconds from an assessment file to whole microseconds
+fn ms_to_us(ms: f64) -> u64 {
+    (ms.max(0.0) * 1_000.0).round() as u64
+}
+
+/// Formats a time in microseconds as milliseconds, e.g. "100" or "99.96"
+fn format_ms(us: u64) -> String {
+    (us as f64 / 1_000.0).to_string()
+}
@@ -27,4 +27,30 @@ pub fn parse_hex_colour(hex: &str) -> egui::Color32 {
 
     // Return default color if parsing fails
     egui::Color32::LIGHT_GRAY
-}
\ No newline at end of file
+}
+
+/// Calculates the Levenshtein edit distance between two strings (case-sensitive).
+/// Used to suggest the intended name when the user mistypes one.
+///
+/// # Arguments
+/// * `a` - The first string
+/// * `b` - The second string
+///
+/// # Returns
+/// The minimum number of single-character insertions, deletions and substitutions
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
@@ -0,0 +1,145 @@
+// Syntax highlighting for generated Arduino C++ shown in the code preview
+use eframe::egui;
+use egui::text::{LayoutJob, TextFormat};
+
+/// C++ keywords used by generated sketches
+const KEYWORDS: [&str; 12] = [
+    "if", "else", "while", "for", "do", "return", "switch", "case", "break", "continue",
+    "true", "false",
+];
+
+/// Built-in types and type qualifiers
+const TYPES: [&str; 10] = [
+    "void", "int", "long", "unsigned", "float", "bool", "byte", "char", "const", "static",
+];
+
+/// The kinds of token the highlighter distinguishes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TokenKind {
+    Plain,
+    Keyword,
+    Type,
+    Number,
+    Str,
+    Comment,
+    Preprocessor,
+    Function,
+}
+
+/// Splits one line of C++ into coloured tokens.
+///
+/// # Arguments
+/// * `line` - A single line of source text
+///
+/// # Returns
+/// The line's tokens in order; concatenating them gives back the original line
+fn tokenize(line: &str) -> Vec<(TokenKind, &str)> {
+    let mut tokens = Vec::new();
+
+    if line.trim_start().starts_with('#') {
+        tokens.push((TokenKind::Preprocessor, line));
+        return tokens;
+    }
+
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i] as char;
+
+        let kind = if line[i..].starts_with("//") {
+            i = bytes.len();
+            TokenKind::Comment
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            while i < bytes.len() && bytes[i] as char != c {
+                if bytes[i] == b'\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i = (i + 1).min(bytes.len());
+            TokenKind::Str
+        } else if c.is_ascii_digit() {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                i += 1;
+            }
+            TokenKind::Number
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            let word = &line[start..i];
+            if KEYWORDS.contains(&word) {
+                TokenKind::Keyword
+            } else if TYPES.contains(&word) {
+                TokenKind::Type
+            } else if line[i..].trim_start().starts_with('(') {
+                TokenKind::Function
+            } else {
+                TokenKind::Plain
+            }
+        } else {
+            // Punctuation and whitespace up to the next interesting character
+            i += line[i..].chars().next().map_or(1, char::len_utf8);
+            while i < bytes.len() {
+                let next = bytes[i] as char;
+                if next.is_ascii_alphanumeric()
+                    || next == '_'
+                    || next == '"'
+                    || next == '\''
+                    || line[i..].starts_with("//")
+                {
+                    break;
+                }
+                i += line[i..].chars().next().map_or(1, char::len_utf8);
+            }
+            TokenKind::Plain
+        };
+
+        tokens.push((kind, &line[start..i]));
+    }
+
+    tokens
+}
+
+/// Builds a monospace, syntax highlighted layout for one line of generated code.
+///
+/// # Arguments
+/// * `prefix` - Text drawn before the code in a neutral colour (e.g. the line number)
+/// * `line` - The line of C++ source
+/// * `visuals` - The current egui visuals, used to pick colours for light or dark mode
+///
+/// # Returns
+/// A LayoutJob ready to be shown with a label or selectable label
+pub fn highlight_line(prefix: &str, line: &str, visuals: &egui::Visuals) -> LayoutJob {
+    let font = egui::FontId::monospace(13.0);
+    let dark = visuals.dark_mode;
+    let colour = |kind: TokenKind| match (kind, dark) {
+        (TokenKind::Plain, _) => visuals.text_color(),
+        (TokenKind::Keyword, true) => egui::Color32::from_rgb(198, 120, 221),
+        (TokenKind::Keyword, false) => egui::Color32::from_rgb(166, 38, 164),
+        (TokenKind::Type, true) => egui::Color32::from_rgb(86, 182, 194),
+        (TokenKind::Type, false) => egui::Color32::from_rgb(1, 132, 188),
+        (TokenKind::Number, true) => egui::Color32::from_rgb(209, 154, 102),
+        (TokenKind::Number, false) => egui::Color32::from_rgb(152, 104, 1),
+        (TokenKind::Str, true) => egui::Color32::from_rgb(152, 195, 121),
+        (TokenKind::Str, false) => egui::Color32::from_rgb(80, 161, 79),
+        (TokenKind::Comment, _) => egui::Color32::GRAY,
+        (TokenKind::Preprocessor, true) => egui::Color32::from_rgb(224, 108, 117),
+        (TokenKind::Preprocessor, false) => egui::Color32::from_rgb(202, 18, 67),
+        (TokenKind::Function, true) => egui::Color32::from_rgb(97, 175, 239),
+        (TokenKind::Function, false) => egui::Color32::from_rgb(64, 120, 242),
+    };
+
+    let mut job = LayoutJob::default();
+    job.append(
+        prefix,
+        0.0,
+        TextFormat::simple(font.clone(), visuals.weak_text_color()),
+    );
+    for (kind, text) in tokenize(line) {
+        job.append(text, 0.0, TextFormat::simple(font.clone(), colour(kind)));
+    }
+    job
+}
@@ -0,0 +1,1010 @@
+// Typed intermediate representation for generated Arduino code
+// Block templates are parsed into statements, compound blocks and expressions so that
+// nesting and indentation are decided structurally instead of by scanning for braces.
+
+/* ---------- IR TYPES ---------- */
+
+/// An expression inside a generated statement or control-flow header
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    /// Numeric literal kept exactly as written (e.g. "1000", "0x1F", "1000UL")
+    Number(String),
+    /// String literal including its quotes
+    Str(String),
+    /// Character literal including its quotes
+    Char(String),
+    /// Identifier, possibly qualified with '.' or '::' (e.g. "HIGH", "Serial.begin")
+    Ident(String),
+    /// Function call such as digitalWrite(12, HIGH)
+    Call { name: String, args: Vec<Expr> },
+    /// Prefix operator such as !x, -x or ++x
+    Unary { op: String, expr: Box<Expr> },
+    /// Postfix operator such as x++ or x--
+    Postfix { op: String, expr: Box<Expr> },
+    /// Binary operator such as a > b or a && b
+    Binary {
+        op: String,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    /// Assignment or compound assignment such as x = 1 or x += 2
+    Assign {
+        op: String,
+        target: Box<Expr>,
+        value: Box<Expr>,
+    },
+    /// Parenthesised expression, kept so printing preserves the author's grouping
+    Paren(Box<Expr>),
+}
+
+/// Header of a compound statement (the part before its opening brace)
+#[derive(Clone, Debug, PartialEq)]
+pub enum Header {
+    /// if (condition)
+    If(Expr),
+    /// else if (condition)
+    ElseIf(Expr),
+    /// else
+    Else,
+    /// while (condition)
+    While(Expr),
+    /// Any other header (for loops, function signatures, switch, ...) emitted verbatim
+    Raw(String),
+}
+
+/// The different kinds of statement that can appear in generated code
+#[derive(Clone, Debug, PartialEq)]
+pub enum StmtKind {
+    /// Expression statement, printed with a trailing semicolon
+    Expr(Expr),
+    /// Variable declaration such as `int counter = 0;`
+    Decl {
+        ty: String,
+        name: String,
+        init: Option<Expr>,
+    },
+    /// Statement text that could not be parsed, emitted verbatim
+    Raw(String),
+    /// Single-line comment text (without the leading "//")
+    Comment(String),
+    /// An empty separator line
+    Blank,
+    /// A header followed by a braced body of statements
+    Compound { header: Header, body: Vec<Stmt> },
+    /// A {@name} placeholder for the statements of a named slot; replaced before printing
+    Slot(String),
+}
+
+/// A single statement together with the block instance that produced it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    /// instance_id of the block this statement was generated from (if any)
+    pub origin: Option<String>,
+}
+
+impl Stmt {
+    /// Creates a statement that is not tied to any block instance
+    pub fn new(kind: StmtKind) -> Self {
+        Self { kind, origin: None }
+    }
+
+    /// Creates a compound statement with a verbatim header, e.g. "void setup()"
+    pub fn compound(header: &str, body: Vec<Stmt>) -> Self {
+        Self::new(StmtKind::Compound {
+            header: Header::Raw(header.to_string()),
+            body,
+        })
+    }
+
+    /// Sets the origin on this statement and every nested statement that has none yet
+    pub fn set_origin(&mut self, origin: &str) {
+        if self.origin.is_none() {
+            self.origin = Some(origin.to_string());
+        }
+        if let StmtKind::Compound { body, .. } = &mut self.kind {
+            for stmt in body {
+                stmt.set_origin(origin);
+            }
+        }
+    }
+}
+
+/// A whole sketch split into the parts of the .ino file that blocks can contribute to
+#[derive(Default)]
+pub struct Sketch {
+    /// Comment lines printed at the very top of the file
+    pub comments: Vec<String>,
+    /// Library headers, e.g. "<Servo.h>", printed as sorted #include lines at the top
+    pub includes: Vec<String>,
+    /// Global library object declarations such as `Servo myServo;`, printed sorted below
+    /// the includes
+    pub declarations: Vec<Stmt>,
+    /// Statements at global scope, above setup()
+    pub globals: Vec<Stmt>,
+    /// User-defined function definitions, placed between the globals and setup()
+    pub functions: Vec<Stmt>,
+    /// Statements inside setup()
+    pub setup: Vec<Stmt>,
+    /// Statements inside loop()
+    pub loop_body: Vec<Stmt>,
+}
+
+impl Sketch {
+    /// Appends statements to a sketch part, skipping any that are already present
+    /// (ignoring which block they came from) so shared setup code is emitted once.
+    pub fn push_unique(part: &mut Vec<Stmt>, stmts: Vec<Stmt>) {
+        for stmt in stmts {
+            if !part.iter().any(|existing| existing.kind == stmt.kind) {
+                part.push(stmt);
+            }
+        }
+    }
+
+    /// Adds a library header, accepting "Servo.h", "<Servo.h>", "\"local.h\"" or a full
+    /// "#include <Servo.h>" line. Headers already present are skipped.
+    pub fn add_include(&mut self, header: &str) {
+        let header = header.trim();
+        let header = header.strip_prefix("#include").unwrap_or(header).trim();
+        let header = if header.starts_with('<') || header.starts_with('"') {
+            header.to_string()
+        } else {
+            format!("<{header}>")
+        };
+        if !self.includes.contains(&header) {
+            self.includes.push(header);
+        }
+    }
+
+    /// Assembles the sketch into IR: header comments, includes, library objects, globals, user functions,
+    /// then setup() and loop(). Includes and library objects are sorted so the output
+    /// does not depend on where blocks sit on the canvas.
+    pub fn to_program(&self) -> Vec<Stmt> {
+        let mut program = Vec::new();
+
+        for comment in &self.comments {
+            program.push(Stmt::new(StmtKind::Comment(comment.clone())));
+        }
+        if !self.comments.is_empty() {
+            program.push(Stmt::new(StmtKind::Blank));
+        }
+
+        let mut includes = self.includes.clone();
+        includes.sort();
+        for header in &includes {
+            program.push(Stmt::new(StmtKind::Raw(format!("#include {header}"))));
+        }
+        if !includes.is_empty() {
+            program.push(Stmt::new(StmtKind::Blank));
+        }
+
+        let mut declarations = self.declarations.clone();
+        declarations.sort_by_cached_key(|stmt| print_stmts(std::slice::from_ref(stmt)).text);
+        if !declarations.is_empty() {
+            program.extend(declarations);
+            program.push(Stmt::new(StmtKind::Blank));
+        }
+
+        if !self.globals.is_empty() {
+            program.extend(self.globals.iter().cloned());
+            program.push(Stmt::new(StmtKind::Blank));
+        }
+        for function in &self.functions {
+            program.push(function.clone());
+            program.push(Stmt::new(StmtKind::Blank));
+        }
+        program.push(Stmt::compound("void setup()", self.setup.clone()));
+        program.push(Stmt::new(StmtKind::Blank));
+        program.push(Stmt::compound("void loop()", self.loop_body.clone()));
+        program
+    }
+
+    /// Prints the sketch as .ino source text, with the block behind each line
+    pub fn print(&self) -> MappedSource {
+        print_stmts(&self.to_program())
+    }
+}
+
+/// Replaces every {@name} placeholder for `slot` with the given statements.
+/// Returns true if the placeholder was found.
+pub fn fill_slot(stmts: &mut Vec<Stmt>, slot: &str, body: &[Stmt]) -> bool {
+    let mut found = false;
+    let mut i = 0;
+    while i < stmts.len() {
+        match &mut stmts[i].kind {
+            StmtKind::Slot(name) if name == slot => {
+                stmts.splice(i..=i, body.iter().cloned());
+                i += body.len();
+                found = true;
+                continue;
+            }
+            StmtKind::Compound { body: inner, .. } => {
+                found |= fill_slot(inner, slot, body);
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    found
+}
+
+/// Removes any slot placeholders that were not filled (empty slots)
+pub fn remove_slots(stmts: &mut Vec<Stmt>) {
+    stmts.retain(|stmt| !matches!(stmt.kind, StmtKind::Slot(_)));
+    for stmt in stmts {
+        if let StmtKind::Compound { body, .. } = &mut stmt.kind {
+            remove_slots(body);
+        }
+    }
+}
+
+/// Returns the slot name if the text starting at `start` is a {@name} placeholder,
+/// together with the index just after its closing brace.
+fn slot_placeholder(chars: &[char], start: usize) -> Option<(String, usize)> {
+    if chars.get(start + 1) != Some(&'@') {
+        return None;
+    }
+    let name_start = start + 2;
+    let mut end = name_start;
+    while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+        end += 1;
+    }
+    if end == name_start || chars.get(end) != Some(&'}') {
+        return None;
+    }
+    Some((chars[name_start..end].iter().collect(), end + 1))
+}
+
+/* ---------- TEMPLATE PARSING ---------- */
+
+/// Appends the text accumulated so far as a statement (or discards it when blank)
+fn flush_statement(text: &mut String, out: &mut Vec<Stmt>) {
+    let trimmed = text.trim();
+    if !trimmed.is_empty() {
+        out.push(Stmt::new(statement_kind(trimmed)));
+    }
+    text.clear();
+}
+
+/// Variable types recognised in declarations, longest first so "unsigned long" wins over "long"
+const DECL_TYPES: [&str; 11] = [
+    "unsigned long",
+    "unsigned int",
+    "boolean",
+    "double",
+    "String",
+    "float",
+    "bool",
+    "byte",
+    "char",
+    "long",
+    "int",
+];
+
+/// Classifies a complete statement. Statements ending in ';' are parsed as declarations
+/// or expressions when possible; everything else is kept as raw text.
+fn statement_kind(text: &str) -> StmtKind {
+    if let Some(body) = text.strip_suffix(';') {
+        if let Some(decl) = parse_declaration(body) {
+            return decl;
+        }
+        if let Some(expr) = parse_expr(body) {
+            return StmtKind::Expr(expr);
+        }
+    }
+    StmtKind::Raw(text.to_string())
+}
+
+/// Returns true if the text is a valid C++ identifier
+pub fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parses "type name" or "type name = value" (without the semicolon) as a declaration
+fn parse_declaration(text: &str) -> Option<StmtKind> {
+    let text = text.trim();
+    let ty = DECL_TYPES.iter().find(|ty| {
+        text.strip_prefix(**ty)
+            .is_some_and(|rest| rest.starts_with(char::is_whitespace))
+    })?;
+    let rest = text[ty.len()..].trim();
+
+    let (name, init) = match rest.split_once('=') {
+        Some((name, value)) => (name.trim(), Some(parse_expr(value)?)),
+        None => (rest, None),
+    };
+    if !is_identifier(name) {
+        return None;
+    }
+
+    Some(StmtKind::Decl {
+        ty: ty.to_string(),
+        name: name.to_string(),
+        init,
+    })
+}
+
+/// Classifies the text before an opening brace as a control-flow header
+fn parse_header(text: &str) -> Header {
+    let text = text.trim();
+
+    if let Some(rest) = keyword_rest(text, "else") {
+        if rest.is_empty() {
+            return Header::Else;
+        }
+        if let Some(expr) = condition_after(rest, "if") {
+            return Header::ElseIf(expr);
+        }
+    } else if let Some(expr) = condition_after(text, "if") {
+        return Header::If(expr);
+    } else if let Some(expr) = condition_after(text, "while") {
+        return Header::While(expr);
+    }
+
+    Header::Raw(text.to_string())
+}
+
+/// Returns the trimmed text after a leading keyword, if the text starts with that keyword
+fn keyword_rest<'a>(text: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = text.strip_prefix(keyword)?;
+    if rest
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return None;
+    }
+    Some(rest.trim())
+}
+
+/// Parses the parenthesised condition that follows a keyword, e.g. "while (x > 1)"
+fn condition_after(text: &str, keyword: &str) -> Option<Expr> {
+    keyword_rest(text, keyword)
+        .and_then(parenthesised)
+        .and_then(parse_expr)
+        .map(Expr::without_parens)
+}
+
+/// Returns the inside of text that is entirely wrapped in one pair of parentheses
+fn parenthesised(text: &str) -> Option<&str> {
+    let inner = text.strip_prefix('(')?.strip_suffix(')')?;
+    let mut depth = 0i32;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth < 0 {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+    }
+    (depth == 0).then_some(inner)
+}
+
+/// Parses a filled-in code template into a list of statements.
+///
+/// Comments, string literals and character literals are skipped over when looking for
+/// braces and semicolons, so a '}' inside them never ends a block. A {@name} placeholder
+/// becomes a Slot statement.
+/// Returns an error if the template's braces are unbalanced.
+pub fn parse_template(source: &str) -> Result<Vec<Stmt>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    // Stack of open compounds: (header, statements collected so far)
+    let mut stack: Vec<(Option<Header>, Vec<Stmt>)> = vec![(None, Vec::new())];
+    let mut text = String::new();
+    let mut paren_depth = 0i32;
+    let mut i = 0usize;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let current = &mut stack.last_mut().expect("stack is never empty").1;
+
+        match c {
+            '/' if next == Some('/') => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|&ch| ch == '\n')
+                    .map_or(chars.len(), |p| i + p);
+                let comment: String = chars[i + 2..end].iter().collect();
+                // A trailing comment keeps any unterminated statement text before it
+                flush_statement(&mut text, current);
+                current.push(Stmt::new(StmtKind::Comment(comment.trim().to_string())));
+                i = end;
+                continue;
+            }
+            '/' if next == Some('*') => {
+                let mut end = i + 2;
+                while end + 1 < chars.len() && !(chars[end] == '*' && chars[end + 1] == '/') {
+                    end += 1;
+                }
+                let comment: String = chars[i + 2..end.min(chars.len())].iter().collect();
+                flush_statement(&mut text, current);
+                for line in comment.lines().map(str::trim).filter(|l| !l.is_empty()) {
+                    current.push(Stmt::new(StmtKind::Comment(line.to_string())));
+                }
+                i = (end + 2).min(chars.len());
+                continue;
+            }
+            '"' | '\'' => {
+                // Copy the whole literal, honouring backslash escapes
+                text.push(c);
+                i += 1;
+                while i < chars.len() {
+                    let ch = chars[i];
+                    text.push(ch);
+                    i += 1;
+                    if ch == '\\' {
+                        if let Some(&escaped) = chars.get(i) {
+                            text.push(escaped);
+                            i += 1;
+                        }
+                    } else if ch == c {
+                        break;
+                    }
+                }
+                continue;
+            }
+            '#' if text.trim().is_empty() => {
+                // Preprocessor directive runs to the end of the line
+                let end = chars[i..]
+                    .iter()
+                    .position(|&ch| ch == '\n')
+                    .map_or(chars.len(), |p| i + p);
+                let directive: String = chars[i..end].iter().collect();
+                text.clear();
+                current.push(Stmt::new(StmtKind::Raw(directive.trim_end().to_string())));
+                i = end;
+                continue;
+            }
+            '(' => {
+                paren_depth += 1;
+                text.push(c);
+            }
+            ')' => {
+                paren_depth -= 1;
+                text.push(c);
+            }
+            ';' if paren_depth <= 0 => {
+                text.push(c);
+                flush_statement(&mut text, current);
+            }
+            '{' if slot_placeholder(&chars, i).is_some() => {
+                let (name, end) = slot_placeholder(&chars, i).expect("checked by guard");
+                flush_statement(&mut text, current);
+                current.push(Stmt::new(StmtKind::Slot(name)));
+                i = end;
+                continue;
+            }
+            '{' => {
+                let header = parse_header(&text);
+                text.clear();
+                paren_depth = 0;
+                stack.push((Some(header), Vec::new()));
+            }
+            '}' => {
+                flush_statement(&mut text, current);
+                if stack.len() == 1 {
+                    return Err("Template has a '}' without a matching '{'.".to_string());
+                }
+                let (header, body) = stack.pop().expect("checked above");
+                let header = header.expect("only the root frame has no header");
+                stack
+                    .last_mut()
+                    .expect("root frame remains")
+                    .1
+                    .push(Stmt::new(StmtKind::Compound { header, body }));
+            }
+            _ => text.push(c),
+        }
+        i += 1;
+    }
+
+    if stack.len() != 1 {
+        return Err("Template has a '{' without a matching '}'.".to_string());
+    }
+
+    let mut root = stack.pop().expect("root frame").1;
+    flush_statement(&mut text, &mut root);
+    Ok(root)
+}
+
+/* ---------- EXPRESSION PARSING ---------- */
+
+/// Splits expression text into tokens. Returns None on characters that are not understood.
+fn tokenize(text: &str) -> Option<Vec<String>> {
+    const OPERATORS: [&str; 34] = [
+        "<<=", ">>=", "::", "++", "--", "==", "!=", "<=", ">=", "&&", "||", "<<", ">>", "+=",
+        "-=", "*=", "/=", "%=", "&=", "|=", "^=", "+", "-", "*", "/", "%", "<", ">", "=", "!",
+        "~", "&", "|", "^",
+    ];
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0usize;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            // Identifiers may be qualified with '.' or '::' (Serial.begin, Foo::bar)
+            let start = i;
+            while i < chars.len() {
+                let ch = chars[i];
+                if ch.is_ascii_alphanumeric()
+                    || ch == '_'
+                    || (ch == '.'
+                        && chars
+                            .get(i + 1)
+                            .is_some_and(|n| n.is_ascii_alphabetic() || *n == '_'))
+                {
+                    i += 1;
+                } else if ch == ':'
+                    && chars.get(i + 1) == Some(&':')
+                    && chars
+                        .get(i + 2)
+                        .is_some_and(|n| n.is_ascii_alphabetic() || *n == '_')
+                {
+                    i += 2;
+                } else {
+                    break;
+                }
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit()))
+        {
+            let start = i;
+            let hex = c == '0' && matches!(chars.get(i + 1), Some('x' | 'X'));
+            while i < chars.len() {
+                let ch = chars[i];
+                // An exponent may be signed, as in 1e-5; in hex literals 'e' is a digit
+                let exponent_sign = matches!(ch, '+' | '-') && !hex && matches!(chars[i - 1], 'e' | 'E');
+                if ch.is_ascii_alphanumeric() || ch == '.' || exponent_sign {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else if c == '"' || c == '\'' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return None;
+            }
+            i += 1;
+            tokens.push(chars[start..i].iter().collect());
+        } else if matches!(c, '(' | ')' | ',') {
+            tokens.push(c.to_string());
+            i += 1;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+            let op = OPERATORS.iter().find(|op| rest.starts_with(*op))?;
+            tokens.push((*op).to_string());
+            i += op.chars().count();
+        }
+    }
+
+    Some(tokens)
+}
+
+/// Binary operators grouped by precedence, lowest first
+const BINARY_LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+const ASSIGN_OPS: [&str; 11] = [
+    "=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "<<=", ">>=",
+];
+
+/// Recursive-descent parser over a token list
+struct ExprParser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// assignment := binary (assign_op assignment)?
+    fn assignment(&mut self) -> Option<Expr> {
+        let target = self.binary(0)?;
+        if let Some(op) = self.peek().filter(|t| ASSIGN_OPS.contains(t)) {
+            let op = op.to_string();
+            self.pos += 1;
+            let value = self.assignment()?;
+            return Some(Expr::Assign {
+                op,
+                target: Box::new(target),
+                value: Box::new(value),
+            });
+        }
+        Some(target)
+    }
+
+    /// Left-associative binary operators using the BINARY_LEVELS precedence table
+    fn binary(&mut self, level: usize) -> Option<Expr> {
+        if level == BINARY_LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self.peek().filter(|t| BINARY_LEVELS[level].contains(t)) {
+            let op = op.to_string();
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+        Some(lhs)
+    }
+
+    fn unary(&mut self) -> Option<Expr> {
+        if let Some(op) = self
+            .peek()
+            .filter(|t| matches!(*t, "!" | "-" | "+" | "~" | "++" | "--"))
+        {
+            let op = op.to_string();
+            self.pos += 1;
+            let expr = self.unary()?;
+            return Some(Expr::Unary {
+                op,
+                expr: Box::new(expr),
+            });
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Option<Expr> {
+        let mut expr = self.primary()?;
+        while let Some(op) = self.peek().filter(|t| matches!(*t, "++" | "--")) {
+            let op = op.to_string();
+            self.pos += 1;
+            expr = Expr::Postfix {
+                op,
+                expr: Box::new(expr),
+            };
+        }
+        Some(expr)
+    }
+
+    fn primary(&mut self) -> Option<Expr> {
+        let token = self.next()?;
+        let first = token.chars().next()?;
+
+        if token == "(" {
+            let inner = self.assignment()?;
+            return self.eat(")").then(|| Expr::Paren(Box::new(inner)));
+        }
+        if first.is_ascii_digit() || first == '.' {
+            return Some(Expr::Number(token));
+        }
+        if first == '"' {
+            return Some(Expr::Str(token));
+        }
+        if first == '\'' {
+            return Some(Expr::Char(token));
+        }
+        if first.is_ascii_alphabetic() || first == '_' {
+            if self.eat("(") {
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.assignment()?.without_parens());
+                        if self.eat(")") {
+                            break;
+                        }
+                        if !self.eat(",") {
+                            return None;
+                        }
+                    }
+                }
+                return Some(Expr::Call { name: token, args });
+            }
+            return Some(Expr::Ident(token));
+        }
+        None
+    }
+}
+
+/// Parses C++ expression text into an Expr. Returns None if the text is not a
+/// complete expression this parser understands (the caller then keeps it raw).
+pub fn parse_expr(text: &str) -> Option<Expr> {
+    let tokens = tokenize(text)?;
+    if tokens.is_empty() {
+        return None;
+    }
+    let mut parser = ExprParser { tokens, pos: 0 };
+    let expr = parser.assignment()?;
+    (parser.pos == parser.tokens.len()).then_some(expr)
+}
+
+/* ---------- PRINTING ---------- */
+
+impl Expr {
+    /// Removes redundant outer parentheses, used where the surrounding syntax already
+    /// delimits the expression (conditions and call arguments)
+    pub fn without_parens(self) -> Expr {
+        match self {
+            Expr::Paren(inner) => inner.without_parens(),
+            other => other,
+        }
+    }
+
+    /// Collects the plain identifiers (not function names) used in the expression
+    pub fn identifiers<'a>(&'a self, out: &mut Vec<&'a str>) {
+        match self {
+            Expr::Ident(name) => out.push(name),
+            Expr::Number(_) | Expr::Str(_) | Expr::Char(_) => {}
+            Expr::Call { args, .. } => {
+                for arg in args {
+                    arg.identifiers(out);
+                }
+            }
+            Expr::Unary { expr, .. } | Expr::Postfix { expr, .. } | Expr::Paren(expr) => {
+                expr.identifiers(out)
+            }
+            Expr::Binary { lhs, rhs, .. } => {
+                lhs.identifiers(out);
+                rhs.identifiers(out);
+            }
+            Expr::Assign { target, value, .. } => {
+                target.identifiers(out);
+                value.identifiers(out);
+            }
+        }
+    }
+
+    /// Formats the expression as C++ source text
+    pub fn to_source(&self) -> String {
+        match self {
+            Expr::Number(text)
+            | Expr::Str(text)
+            | Expr::Char(text)
+            | Expr::Ident(text) => text.clone(),
+            Expr::Call { name, args } => {
+                let args: Vec<String> = args.iter().map(Expr::to_source).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            Expr::Unary { op, expr } => {
+                // "- -x" must not run together into the decrement "--x"
+                let inner = expr.to_source();
+                match op.chars().last() {
+                    Some(sign @ ('+' | '-')) if inner.starts_with(sign) => format!("{} {}", op, inner),
+                    _ => format!("{}{}", op, inner),
+                }
+            }
+            Expr::Postfix { op, expr } => format!("{}{}", expr.to_source(), op),
+            Expr::Binary { op, lhs, rhs } => {
+                format!("{} {} {}", lhs.to_source(), op, rhs.to_source())
+            }
+            Expr::Assign { op, target, value } => {
+                format!("{} {} {}", target.to_source(), op, value.to_source())
+            }
+            Expr::Paren(inner) => format!("({})", inner.to_source()),
+        }
+    }
+}
+
+impl Header {
+    /// Formats the header text that precedes the opening brace
+    fn to_source(&self) -> String {
+        match self {
+            Header::If(cond) => format!("if ({})", cond.to_source()),
+            Header::ElseIf(cond) => format!("else if ({})", cond.to_source()),
+            Header::Else => "else".to_string(),
+            Header::While(cond) => format!("while ({})", cond.to_source()),
+            Header::Raw(text) => text.clone(),
+        }
+    }
+}
+
+/// Generated source text together with the block instance behind each line
+pub struct MappedSource {
+    pub text: String,
+    /// instance_id of the block that produced each line (index 0 is line 1)
+    pub line_origins: Vec<Option<String>>,
+}
+
+/// Collects printed lines and their origins while tracking the current indentation level
+struct Printer {
+    lines: Vec<String>,
+    origins: Vec<Option<String>>,
+    indent: usize,
+    origin: Option<String>,
+}
+
+impl Printer {
+    fn push(&mut self, text: &str) {
+        self.lines.push(format!("{}{}", "  ".repeat(self.indent), text));
+        self.origins.push(self.origin.clone());
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let outer = std::mem::replace(&mut self.origin, stmt.origin.clone());
+        match &stmt.kind {
+            StmtKind::Expr(expr) => self.push(&format!("{};", expr.to_source())),
+            StmtKind::Decl { ty, name, init } => match init {
+                Some(init) => self.push(&format!("{} {} = {};", ty, name, init.to_source())),
+                None => self.push(&format!("{} {};", ty, name)),
+            },
+            // Text kept as written may span lines; each printed line maps to the block
+            StmtKind::Raw(text) => {
+                for line in text.lines() {
+                    self.push(line.trim_end());
+                }
+            }
+            StmtKind::Comment(text) => self.push(&format!("// {}", text)),
+            StmtKind::Blank => self.push(""),
+            StmtKind::Slot(_) => {}
+            StmtKind::Compound { header, body } => {
+                let header_text = header.to_source();
+                let closing = format!("{}}}", "  ".repeat(self.indent));
+
+                // Join "else" onto the closing brace of the preceding if block
+                let chains = matches!(header, Header::Else | Header::ElseIf(_));
+                if chains && self.lines.last() == Some(&closing) {
+                    self.lines.pop();
+                    self.origins.pop();
+                    self.push(&format!("}} {} {{", header_text));
+                } else {
+                    self.push(&format!("{} {{", header_text));
+                }
+
+                self.indent += 1;
+                for child in body {
+                    self.stmt(child);
+                }
+                self.indent -= 1;
+                self.push("}");
+            }
+        }
+        self.origin = outer;
+    }
+}
+
+/// Prints a list of statements as C++ source with two-space indentation, recording
+/// which block each output line came from
+pub fn print_stmts(stmts: &[Stmt]) -> MappedSource {
+    let mut printer = Printer {
+        lines: Vec::new(),
+        origins: Vec::new(),
+        indent: 0,
+        origin: None,
+    };
+    for stmt in stmts {
+        printer.stmt(stmt);
+    }
+
+    let mut text = printer.lines.join("\n");
+    text.push('\n');
+    MappedSource {
+        text,
+        line_origins: printer.origins,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a template and prints it back
+    fn round_trip(template: &str) -> String {
+        print_stmts(&parse_template(template).expect("template should parse")).text
+    }
+
+    #[test]
+    fn number_literals_are_kept_as_written() {
+        assert_eq!(
+            round_trip("float x = 1e-5; int y = 0x1F; long z = 1000UL; float w = 2.5E+3f;"),
+            "float x = 1e-5;\nint y = 0x1F;\nlong z = 1000UL;\nfloat w = 2.5E+3f;\n"
+        );
+        assert_eq!(parse_expr("1e-5"), Some(Expr::Number("1e-5".to_string())));
+
+        // In a hex literal 'e' is a digit, so the minus is an operator
+        assert_eq!(round_trip("y = 0x1e-5;"), "y = 0x1e - 5;\n");
+    }
+
+    #[test]
+    fn nested_unary_operators_keep_their_meaning() {
+        assert_eq!(
+            round_trip("a = - -x; b = + +x; c = - --x; d = -(-x); e = !!x; f = -x--;"),
+            "a = - -x;\nb = + +x;\nc = - --x;\nd = -(-x);\ne = !!x;\nf = -x--;\n"
+        );
+    }
+
+    #[test]
+    fn comments_stay_in_order() {
+        assert_eq!(
+            round_trip("a = 1\n/* note\n   more */\nb = 2;"),
+            "a = 1\n// note\n// more\nb = 2;\n"
+        );
+        assert_eq!(
+            round_trip("a = 1; /* between */ b = 2; // after"),
+            "a = 1;\n// between\nb = 2;\n// after\n"
+        );
+    }
+
+    #[test]
+    fn compound_statements_are_indented() {
+        assert_eq!(
+            round_trip("if (a > 1) { a = 0; } else if (b) { b = 0; } else { x++; }"),
+            "if (a > 1) {\n  a = 0;\n} else if (b) {\n  b = 0;\n} else {\n  x++;\n}\n"
+        );
+        assert_eq!(
+            round_trip("for (int i = 0; i < 3; i++) { Serial.println(\"a;b}\"); }"),
+            "for (int i = 0; i < 3; i++) {\n  Serial.println(\"a;b}\");\n}\n"
+        );
+        assert_eq!(
+            round_trip("#include <Servo.h>\nvoid blink(int pin) { digitalWrite(pin, !digitalRead(pin)); }"),
+            "#include <Servo.h>\nvoid blink(int pin) {\n  digitalWrite(pin, !digitalRead(pin));\n}\n"
+        );
+        assert!(parse_template("if (a) { b = 1;").is_err());
+        assert!(parse_template("b = 1; }").is_err());
+    }
+
+    #[test]
+    fn slots_are_filled_with_the_nested_blocks() {
+        let mut stmts = parse_template("while (true) { {@body} }").unwrap();
+        let mut body = parse_template("delay(10);").unwrap();
+        body[0].set_origin("inner");
+        assert!(fill_slot(&mut stmts, "body", &body));
+        assert!(!fill_slot(&mut stmts, "missing", &body));
+
+        let printed = print_stmts(&stmts);
+        assert_eq!(printed.text, "while (true) {\n  delay(10);\n}\n");
+        assert_eq!(printed.line_origins, [None, Some("inner".to_string()), None]);
+    }
+
+    #[test]
+    fn multi_line_raw_text_keeps_its_origin_on_every_line() {
+        let mut stmt = Stmt::new(StmtKind::Raw("#if defined(X)\n#endif".to_string()));
+        stmt.set_origin("block");
+        let printed = print_stmts(&[stmt]);
+        assert_eq!(printed.text, "#if defined(X)\n#endif\n");
+        assert_eq!(printed.line_origins, vec![Some("block".to_string()); 2]);
+    }
+}
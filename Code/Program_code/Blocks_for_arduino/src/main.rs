@@ -1,28 +1,52 @@
+// Style lints that would only rewrite the original code, e.g. nested ifs into let-chains
+#![allow(clippy::collapsible_if, clippy::unnecessary_map_or)]
+
 // Module declarations for the application
 mod app;
 mod model;
 mod helper;
+mod ir;
+mod highlight;
+mod pattern;
+mod sim;
+mod trace;
+mod grade;
 
 use std::fs;
+use std::path::PathBuf;
 use eframe::egui;
 use app::BlocksForArduino;
+use grade::GradeReport;
 
 /// Entry point for the Blocks for Arduino application.
-/// 
+///
 /// Initializes the egui GUI framework with a 1000x650 window and loads all
-/// block definitions from JSON files in the "Json_files" directory. Also
-/// generates and syncs valid sequences based on loaded blocks.
+/// block definitions from JSON files in the "Json_files" directory, board
+/// profiles from the "Boards" directory and assessments from the "Assessments"
+/// directory.
+///
+/// Run as `Blocks_for_arduino --grade <assessment.json> [workspaces dir]` to grade
+/// every saved workspace without opening a window.
 fn main() -> eframe::Result<()> {
     // Collect all JSON files from the "Json_files" directory
-    let mut files = Vec::new();
+    let files = files_in("Json_files");
 
-    if let Ok(entries) = fs::read_dir("Json_files") {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                files.push(path);
-            }
-        }
+    // Collect all board profiles from the "Boards" directory
+    let board_files = files_in("Boards");
+
+    // Collect the assessments offered in the grader
+    let assessment_files = files_in("Assessments");
+
+    // Batch grading instead of the GUI
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--grade") {
+        let Some(assessment) = args.get(2) else {
+            eprintln!("Usage: {} --grade <assessment.json> [workspaces dir]", args[0]);
+            std::process::exit(2);
+        };
+        let dir = args.get(3).map_or("workspaces", String::as_str);
+        let app = load_app(&files, &board_files, &[]);
+        std::process::exit(grade_batch(app, assessment, dir));
     }
 
     // Configure the application window
@@ -37,18 +61,91 @@ fn main() -> eframe::Result<()> {
     eframe::run_native(
         "Blocks for Arduino",
         options,
-        Box::new(|_cc| {
-            let mut app = BlocksForArduino::default();
+        Box::new(|_cc| Ok(Box::new(load_app(&files, &board_files, &assessment_files)))),
+    )
+}
+
+/// Lists the files in a directory, sorted by path. A missing directory gives an
+/// empty list.
+fn files_in(dir: &str) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
 
-            // Load all block JSON files to populate the palette
-            for path in files.iter().filter_map(|p| p.to_str()) {
-                app.load_block_json(path);
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_file() {
+                paths.push(path);
             }
+        }
+    }
+    paths.sort();
+    paths
+}
 
-            // Generate valid block sequences based on loaded blocks
-            app.sync_valid_sequences_with_loaded_blocks();
+/// Creates the application with its palette, board profiles and assessments loaded
+fn load_app(files: &[PathBuf], board_files: &[PathBuf], assessment_files: &[PathBuf]) -> BlocksForArduino {
+    let mut app = BlocksForArduino::default();
 
-            Ok(Box::new(app))
-        }),
-    )
-}
\ No newline at end of file
+    // Load all block JSON files to populate the palette
+    for path in files.iter().filter_map(|p| p.to_str()) {
+        app.load_block_json(path);
+    }
+
+    // Load the board profiles offered in the toolbar
+    for path in board_files.iter().filter_map(|p| p.to_str()) {
+        app.load_board_json(path);
+    }
+
+    // Load the assessments offered in the grader
+    for path in assessment_files.iter().filter_map(|p| p.to_str()) {
+        app.load_assessment_json(path);
+    }
+
+    app
+}
+
+/// Grades every workspace in a directory against one assessment, printing a report
+/// for each and a summary at the end.
+///
+/// # Returns
+/// The process exit code: 0 if every workspace passed, 1 if any failed and 2 if the
+/// assessment could not be read or there was nothing to grade
+fn grade_batch(mut app: BlocksForArduino, assessment_path: &str, dir: &str) -> i32 {
+    let assessment = match BlocksForArduino::read_assessment(assessment_path) {
+        Ok(assessment) => assessment,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 2;
+        }
+    };
+
+    let workspaces: Vec<PathBuf> = files_in(dir)
+        .into_iter()
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("json"))
+        .collect();
+    if workspaces.is_empty() {
+        eprintln!("No workspaces found in {}", dir);
+        return 2;
+    }
+
+    let mut passed = 0;
+    for path in &workspaces {
+        println!("== {} ==", path.display());
+        let report = match app.load_workspace_file(path) {
+            Ok(note) => {
+                if let Some(note) = note {
+                    println!("{}", note);
+                }
+                app.grade_workspace(&assessment)
+            }
+            Err(err) => GradeReport::not_run(&assessment, err),
+        };
+        print!("{}", report.to_text());
+        if report.passed() {
+            passed += 1;
+        }
+    }
+
+    println!("{} of {} workspaces passed", passed, workspaces.len());
+    if passed == workspaces.len() { 0 } else { 1 }
+}
@@ -13,6 +13,17 @@ pub enum BlockListEntry {
     Group(Vec<String>),
 }
 
+/// One entry of Valid_sequences.txt: either a pattern over block ids such as
+/// "PinMode (Pin_toggle delay)+", or one exact sequence written as a list
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum SequenceRule {
+    /// Pattern text compiled by `SequencePattern::compile`
+    Pattern(String),
+    /// A single allowed sequence of block ids
+    Sequence(Vec<String>),
+}
+
 /// Wrapper structure for deserializing block definitions from JSON files
 #[derive(Deserialize, Clone)]
 pub struct BlockFile {
@@ -51,14 +62,56 @@ pub struct BlockSection {
     #[serde(rename = "Code_Equivelant")]
     pub code_equivelant: Option<String>,
 
+    /// Code template emitted once at global scope (from the "Global" section)
+    #[serde(skip)]
+    pub global_code: Option<String>,
+
+    /// Code template emitted once inside setup() (from the "Setup" section)
+    #[serde(skip)]
+    pub setup_code: Option<String>,
+
+    /// Code template emitted once at the top of loop() (from the "Loop" section)
+    #[serde(skip)]
+    pub loop_code: Option<String>,
+
     /// Offset for positioning child blocks relative to this block
     #[serde(default)]
     pub child_offset: Option<Offset>,
 
+    /// Library headers the generated code needs, e.g. "Servo.h"
+    #[serde(default)]
+    pub includes: Vec<String>,
+
+    /// Global object declaration templates, e.g. "Servo {servo};", filled with the
+    /// block's inputs and emitted once below the includes
+    #[serde(default)]
+    pub declarations: Vec<String>,
+
+    /// How the block uses the pins named in its inputs, for pin-usage analysis
+    #[serde(default)]
+    pub pin_usage: Vec<PinUsage>,
+
+    /// Named statement slots (e.g. "then" and "else"), each filled by a {@name} placeholder
+    #[serde(default)]
+    pub slots: Vec<SlotDefinition>,
+
     /// Input definitions specifying which values the block requires
     #[serde(default)]
     pub inputs: Vec<InputDefinition>,
 
+    /// Marks this block as a hat block that starts a stack and says where it runs
+    #[serde(default)]
+    pub hat: Option<HatKind>,
+
+    /// Output type of a value (reporter) block, e.g. "number" or "boolean".
+    /// Reporter blocks plug into an input socket instead of joining a stack.
+    #[serde(default)]
+    pub output: Option<String>,
+
+    /// Rules on where the block may be connected, checked by "Check connections"
+    #[serde(default)]
+    pub connections: ConnectionRules,
+
     /* ---------- RUNTIME ONLY (not serialized) ---------- */
 
     /// Current position of this block in the editor canvas
@@ -73,6 +126,14 @@ pub struct BlockSection {
     #[serde(skip)]
     pub children: Vec<usize>,
 
+    /// Name of the parent's statement slot this block sits in (None = directly below the parent)
+    #[serde(skip)]
+    pub attached_slot: Option<String>,
+
+    /// Name of the parent's input this reporter block is plugged into
+    #[serde(skip)]
+    pub attached_input: Option<String>,
+
     /// Input values entered by the user for this block instance
     #[serde(skip)]
     pub input_values: HashMap<String, String>,
@@ -84,6 +145,73 @@ pub struct BlockSection {
     /// True if this is a palette block (template), false if code block (instance)
     #[serde(skip)]
     pub is_palette: bool,
+
+    /// For palette entries generated from a "Define function" block: the function they call
+    #[serde(skip)]
+    pub procedure: Option<String>,
+}
+
+/// Constraints a block places on its position in the program. Blocks are named by
+/// unique_id; empty lists impose no constraint.
+#[derive(Deserialize, Clone, Default)]
+pub struct ConnectionRules {
+    /// Blocks this block may sit directly below or in a slot of
+    #[serde(default)]
+    pub parents: Vec<String>,
+    /// Blocks that may sit directly below this block or in its slots
+    #[serde(default)]
+    pub children: Vec<String>,
+    /// Blocks that must not run before this one
+    #[serde(default)]
+    pub before: Vec<String>,
+    /// Containers (hat or slot blocks) this block must be somewhere inside
+    #[serde(default)]
+    pub inside: Vec<String>,
+    /// Most instances of the block allowed in a workspace
+    #[serde(default)]
+    pub max_count: Option<usize>,
+}
+
+/// Where the stack below a hat block is placed in the generated sketch
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HatKind {
+    /// "On start" – the stack runs once inside setup()
+    Setup,
+    /// "Forever" – the stack runs repeatedly inside loop()
+    Loop,
+    /// "Define function" – the stack becomes the body of a C++ function above setup()
+    Function,
+}
+
+/// Declares that a block configures, reads or writes the pin held in one of its inputs
+#[derive(Deserialize, Clone, Debug)]
+pub struct PinUsage {
+    /// Name of the input holding the pin, e.g. "Pin"
+    pub input: String,
+    /// What the block does with the pin
+    pub access: PinAccess,
+    /// For "configure": the mode set, either fixed ("OUTPUT") or an input placeholder ("{mode}")
+    #[serde(default)]
+    pub mode: Option<String>,
+}
+
+/// The ways a block can use a pin
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PinAccess {
+    /// pinMode()
+    Configure,
+    /// digitalRead()
+    Read,
+    /// digitalWrite()
+    Write,
+    /// analogRead()
+    AnalogRead,
+    /// analogWrite() – sets the pin to OUTPUT itself
+    AnalogWrite,
+    /// Servo.attach() – takes over the pin
+    Servo,
 }
 
 /// Definition for an input field on a block, including validation rules
@@ -91,12 +219,82 @@ pub struct BlockSection {
 pub struct InputDefinition {
     /// Name of the input field
     pub name: String,
-    /// Optional validation rule. Supported values:
-    ///   "arduino_pin"       – 0–13 or A0–A5
+    /// Optional validation rule. Built-in values include:
+    ///   "arduino_pin"       – a pin on the selected board
     ///   "arduino_state"     – HIGH / LOW / 1 / 0
     ///   "positive_integer"  – whole number > 0
+    /// Any other name must be a custom rule registered in Validation_rules.json.
     #[serde(default)]
     pub validation: Option<String>,
+    /// Constraints written on the input itself, checked after the named rule
+    #[serde(flatten)]
+    pub constraints: InputConstraints,
+    /// Optional value type accepted by this input's socket (e.g. "number", "boolean").
+    /// Reporter blocks can only plug in when their output type matches; any reporter
+    /// fits an input without a type. A "boolean" input without a validation rule is
+    /// edited with a checkbox, and a "colour" input with a colour picker ("#rrggbb").
+    #[serde(default)]
+    #[serde(rename = "type")]
+    pub input_type: Option<String>,
+}
+
+/// Declarative constraints on an input value, written inline on an input or as part
+/// of a custom rule in Validation_rules.json
+#[derive(Deserialize, Clone, Default)]
+pub struct InputConstraints {
+    /// Smallest whole number allowed
+    #[serde(default)]
+    pub min: Option<i64>,
+    /// Largest whole number allowed
+    #[serde(default)]
+    pub max: Option<i64>,
+    /// The only values allowed, matched exactly
+    #[serde(default)]
+    pub choices: Vec<String>,
+    /// Regular expression the whole value must match
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// Unit shown beside the value, such as "ms" or "°"
+    #[serde(default)]
+    pub unit: Option<String>,
+}
+
+impl InputConstraints {
+    /// Whether any constraint restricts the value (a unit alone does not)
+    pub fn is_empty(&self) -> bool {
+        self.min.is_none() && self.max.is_none() && self.choices.is_empty() && self.pattern.is_none()
+    }
+
+    /// Whether the value must be a whole number
+    pub fn is_numeric(&self) -> bool {
+        self.min.is_some() || self.max.is_some()
+    }
+}
+
+/// A custom validation rule registered in Validation_rules.json, used by naming it in
+/// an input's "validation" field
+#[derive(Deserialize, Clone)]
+pub struct ValidationRule {
+    /// Name blocks use to refer to the rule
+    pub name: String,
+    /// What a valid value is, used in error messages (e.g. "a valid servo angle")
+    pub description: String,
+    /// How to fix an invalid value; generated from the constraints if missing
+    #[serde(default)]
+    pub suggestion: Option<String>,
+    #[serde(flatten)]
+    pub constraints: InputConstraints,
+}
+
+/// A named statement slot on a block, such as the "then" or "else" body of an if
+#[derive(Deserialize, Clone)]
+pub struct SlotDefinition {
+    /// Slot name, used as the {@name} placeholder in the code template
+    pub name: String,
+    /// Offset of the slot's first block from the left edge of the block and the top of
+    /// the slot (defaults to an indent of 30 pixels)
+    #[serde(default)]
+    pub offset: Option<Offset>,
 }
 
 /// X and Y offset coordinates for positioning child blocks
@@ -125,6 +323,25 @@ pub struct BlockSnapshot {
     pub instance_id: String,
     /// Instance ID of the parent block (if attached)
     pub attached_to_instance_id: Option<String>,
+    /// Name of the parent's statement slot the block sits in (if any)
+    #[serde(default)]
+    pub attached_slot: Option<String>,
+    /// Name of the parent's input this reporter block is plugged into (if any)
+    #[serde(default)]
+    pub attached_input: Option<String>,
+}
+
+/// A project-level variable, declared as a global in the generated sketch
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VariableDefinition {
+    /// C++ identifier used by the blocks that read or change the variable
+    pub name: String,
+    /// C++ type, e.g. "int", "float" or "bool"
+    #[serde(rename = "type")]
+    pub cpp_type: String,
+    /// Initial value (empty means the type's default of 0 / false)
+    #[serde(default)]
+    pub initial_value: String,
 }
 
 /// Complete workspace snapshot including all blocks and metadata
@@ -134,6 +351,426 @@ pub struct WorkspaceSnapshot {
     pub name: String,
     /// All non-palette blocks in the workspace
     pub blocks: Vec<BlockSnapshot>,
+    /// Project-level variable table
+    #[serde(default)]
+    pub variables: Vec<VariableDefinition>,
+    /// id of the board profile the workspace targets (e.g. "uno")
+    #[serde(default)]
+    pub board: Option<String>,
+    /// Parts placed on the virtual breadboard
+    #[serde(default)]
+    pub components: Vec<Component>,
+}
+
+/// Kinds of part that can be placed on the virtual breadboard
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComponentKind {
+    /// Lights up with its pin's level or PWM duty
+    Led,
+    /// Drives its pin to the active level while held down
+    Button,
+    /// Gives analogRead a value from 0 to 1023
+    Potentiometer,
+    /// Sounds while its pin is driven HIGH or with PWM
+    Buzzer,
+}
+
+impl ComponentKind {
+    /// Every kind, in the order the breadboard offers them
+    pub const ALL: [ComponentKind; 4] = [
+        ComponentKind::Led,
+        ComponentKind::Button,
+        ComponentKind::Potentiometer,
+        ComponentKind::Buzzer,
+    ];
+
+    /// Name shown on the breadboard
+    pub fn name(self) -> &'static str {
+        match self {
+            ComponentKind::Led => "LED",
+            ComponentKind::Button => "Push button",
+            ComponentKind::Potentiometer => "Potentiometer",
+            ComponentKind::Buzzer => "Buzzer",
+        }
+    }
+
+    /// Pin a new part of this kind is wired to, as in the usual example sketches
+    pub fn default_pin(self) -> &'static str {
+        match self {
+            ComponentKind::Led => "13",
+            ComponentKind::Button => "2",
+            ComponentKind::Potentiometer => "A0",
+            ComponentKind::Buzzer => "8",
+        }
+    }
+}
+
+/// A part on the virtual breadboard, wired to one pin of the board.
+///
+/// Buttons are wired the way their pin's mode expects: to ground when the pin uses
+/// INPUT_PULLUP, so pressing reads LOW, and to 5V with a pull-down resistor otherwise,
+/// so pressing reads HIGH.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Component {
+    pub kind: ComponentKind,
+    /// Pin name as used in sketches, e.g. "13" or "A0"
+    pub pin: String,
+    /// LED colour as "#rrggbb"
+    #[serde(default)]
+    pub colour: String,
+    /// Knob position of a potentiometer, 0 to 1023
+    #[serde(default)]
+    pub position: i64,
+    /// Whether a button is held down at the moment
+    #[serde(skip)]
+    pub pressed: bool,
+}
+
+/// A board profile loaded from Boards/*.json, describing which pins the board has and
+/// what they can do
+#[derive(Deserialize, Clone, Debug)]
+pub struct BoardProfile {
+    /// Short identifier stored in workspaces, e.g. "uno"
+    pub id: String,
+    /// Display name, e.g. "Arduino Uno"
+    pub name: String,
+    /// Fully qualified board name used by arduino-cli, e.g. "arduino:avr:uno"
+    pub fqbn: String,
+    /// Pins usable for digital input/output, by number
+    pub digital_pins: Vec<u8>,
+    /// Analog input pins, by name (e.g. "A0")
+    pub analog_pins: Vec<String>,
+    /// Pin numbers of the analog pins, in the order of `analog_pins`. When missing they
+    /// follow the highest digital pin, as on AVR boards.
+    #[serde(default)]
+    pub analog_pin_numbers: Vec<u8>,
+    /// Pin of the on-board LED that LED_BUILTIN names, if the board has one
+    #[serde(default)]
+    pub led_builtin: Option<u8>,
+    /// Digital pins that support PWM output (analogWrite)
+    #[serde(default)]
+    pub pwm_pins: Vec<u8>,
+    /// Digital pins that support external interrupts (attachInterrupt)
+    #[serde(default)]
+    pub interrupt_pins: Vec<u8>,
+    /// Pins that exist but should not be used by sketches
+    #[serde(default)]
+    pub reserved_pins: Vec<ReservedPin>,
+}
+
+/// A pin a board reserves for another purpose, with the reason shown to the user
+#[derive(Deserialize, Clone, Debug)]
+pub struct ReservedPin {
+    /// Pin number or name, e.g. "0" or "A4"
+    pub pin: String,
+    /// Why it is reserved, e.g. "used by the USB serial connection"
+    pub reason: String,
+}
+
+impl Default for BoardProfile {
+    /// The Arduino Uno, used until profiles are loaded from Boards/
+    fn default() -> Self {
+        Self {
+            id: "uno".to_string(),
+            name: "Arduino Uno".to_string(),
+            fqbn: "arduino:avr:uno".to_string(),
+            digital_pins: (0..=13).collect(),
+            analog_pins: (0..=5).map(|n| format!("A{n}")).collect(),
+            analog_pin_numbers: (14..=19).collect(),
+            led_builtin: Some(13),
+            pwm_pins: vec![3, 5, 6, 9, 10, 11],
+            interrupt_pins: vec![2, 3],
+            reserved_pins: Vec::new(),
+        }
+    }
+}
+
+impl BoardProfile {
+    /// Normalises a pin as typed by the user ("13", " a0 ") to the form used in the
+    /// profile ("13", "A0")
+    pub fn normalise_pin(text: &str) -> String {
+        text.trim().to_ascii_uppercase()
+    }
+
+    /// Returns true if the pin (a number or an analog name) exists on this board
+    pub fn has_pin(&self, pin: &str) -> bool {
+        let pin = Self::normalise_pin(pin);
+        pin.parse::<u8>().is_ok_and(|n| self.digital_pins.contains(&n))
+            || self.analog_pins.contains(&pin)
+    }
+
+    /// Pairs each analog pin name with its pin number
+    pub fn analog_pin_table(&self) -> Vec<(String, i64)> {
+        let after_digital = self.digital_pins.iter().max().map_or(0, |&pin| pin as i64 + 1);
+        self.analog_pins
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let number = self
+                    .analog_pin_numbers
+                    .get(i)
+                    .map_or(after_digital + i as i64, |&pin| pin as i64);
+                (name.clone(), number)
+            })
+            .collect()
+    }
+
+    /// Returns the reason a pin is reserved, if it is
+    pub fn reserved_reason(&self, pin: &str) -> Option<&str> {
+        let pin = Self::normalise_pin(pin);
+        self.reserved_pins
+            .iter()
+            .find(|reserved| Self::normalise_pin(&reserved.pin) == pin)
+            .map(|reserved| reserved.reason.as_str())
+    }
+
+    /// Formats pin numbers compactly for messages, e.g. "0–13" or "3, 5, 6, 9–11"
+    pub fn describe_pins(pins: &[u8]) -> String {
+        let mut sorted = pins.to_vec();
+        sorted.sort_unstable();
+        sorted.dedup();
+
+        let mut parts = Vec::new();
+        let mut i = 0;
+        while i < sorted.len() {
+            let start = sorted[i];
+            while i + 1 < sorted.len() && sorted[i + 1] == sorted[i] + 1 {
+                i += 1;
+            }
+            let end = sorted[i];
+            parts.push(match end - start {
+                0 => start.to_string(),
+                1 => format!("{start}, {end}"),
+                _ => format!("{start}–{end}"),
+            });
+            i += 1;
+        }
+        parts.join(", ")
+    }
+}
+
+/// An assessment loaded from Assessments/*.json: how long to run a workspace on the
+/// virtual board, what to feed its inputs and which pin behaviour to expect
+#[derive(Deserialize, Clone, Debug)]
+pub struct Assessment {
+    /// Title shown in the grader, e.g. "Blink pin 12"
+    pub name: String,
+    /// What the task asks students to build
+    #[serde(default)]
+    pub description: String,
+    /// Simulated time to run the program for, in milliseconds
+    pub duration_ms: f64,
+    /// Input levels applied while the program runs, e.g. a button press
+    #[serde(default)]
+    pub stimuli: Vec<Stimulus>,
+    /// Expectations the run must meet to pass
+    pub checks: Vec<PinCheck>,
+}
+
+/// An input applied to a pin at a point in simulated time
+#[derive(Deserialize, Clone, Debug)]
+pub struct Stimulus {
+    /// Pin number or name, e.g. "2" or "A0"
+    pub pin: String,
+    pub at_ms: f64,
+    /// 0 or 1 for digital inputs, 0–1023 for analog inputs
+    pub value: i64,
+}
+
+/// One expectation of an assessment, chosen by its "expect" field
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "expect", rename_all = "snake_case")]
+pub enum PinCheck {
+    /// The pin changes level every `period_ms` ± `tolerance_ms` over the whole window,
+    /// which runs from `from_ms` to `to_ms` (the end of the run if not given)
+    Toggles {
+        pin: String,
+        period_ms: f64,
+        #[serde(default)]
+        tolerance_ms: f64,
+        #[serde(default)]
+        from_ms: f64,
+        #[serde(default)]
+        to_ms: Option<f64>,
+    },
+    /// The pin holds `value` (0/1, or a PWM duty from analogWrite) at `at_ms`
+    Level { pin: String, value: i64, at_ms: f64 },
+    /// The pin changes at least `min` and at most `max` times within the window
+    Changes {
+        pin: String,
+        #[serde(default)]
+        min: usize,
+        #[serde(default)]
+        max: Option<usize>,
+        #[serde(default)]
+        from_ms: f64,
+        #[serde(default)]
+        to_ms: Option<f64>,
+    },
+    /// The serial monitor shows `contains` somewhere in its output
+    Serial { contains: String },
+}
+
+/// One line of an exported sketch and the block instance that generated it
+#[derive(Serialize, Debug)]
+pub struct SourceMapLine {
+    /// 1-based line number in the .ino file
+    pub line: usize,
+    /// instance_id of the block that produced the line
+    pub instance_id: String,
+    /// unique_id of that block's definition, e.g. "delay"
+    pub block: String,
+}
+
+/// Sidecar file written next to an exported sketch ("<name>.map.json") so compiler
+/// errors can be traced back to blocks
+#[derive(Serialize)]
+pub struct SourceMapFile {
+    /// File name of the sketch the map belongs to
+    pub sketch: String,
+    /// id of the board profile the sketch was generated for
+    pub board: String,
+    /// Lines that were generated by a block (lines such as "void setup() {" are omitted)
+    pub lines: Vec<SourceMapLine>,
+}
+
+/// How serious a diagnostic is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Prevents export
+    Error,
+    /// The sketch compiles but will probably not do what was intended
+    Warning,
+}
+
+/// One problem found while checking the workspace
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// instance_id of the block the problem belongs to (None for workspace-wide problems)
+    pub instance_id: Option<String>,
+    /// Name of the offending input on that block, if any
+    pub input: Option<String>,
+    /// What is wrong
+    pub message: String,
+    /// How to fix it, if a fix can be suggested
+    pub suggestion: Option<String>,
+    /// Edit that applies the suggestion, if it can be done automatically
+    pub fix: Option<QuickFix>,
+    /// Corrected sequences offered for a stack that matches no sequence pattern,
+    /// closest first
+    pub candidates: Vec<SequenceCandidate>,
+}
+
+/// A corrected block sequence suggested for a stack, with the edits that produce it
+#[derive(Clone, Debug, PartialEq)]
+pub struct SequenceCandidate {
+    pub sequence: Vec<String>,
+    /// The edit script, e.g. "move delay after Pin_toggle"
+    pub edits: Vec<String>,
+    /// Reorders the stack when the candidate only moves blocks
+    pub fix: Option<QuickFix>,
+}
+
+/// An edit that resolves a diagnostic, offered by the Apply button in the diagnostics
+/// panel
+#[derive(Clone, Debug, PartialEq)]
+pub enum QuickFix {
+    /// Replace the value of one input
+    SetInput {
+        instance_id: String,
+        input: String,
+        value: String,
+    },
+    /// Put the blocks of a stack in a new order; lists their instance_ids, top first
+    ReorderStack { order: Vec<String> },
+    /// Add a block with preset inputs to the end of the "On start" stack, creating the
+    /// stack if there is none
+    AddToSetup {
+        block: String,
+        values: Vec<(String, String)>,
+    },
+}
+
+impl QuickFix {
+    /// Text for the button that applies the fix
+    pub fn label(&self) -> String {
+        match self {
+            QuickFix::SetInput { value, .. } => format!("Change to '{value}'"),
+            QuickFix::ReorderStack { .. } => "Reorder stack".to_string(),
+            QuickFix::AddToSetup { block, .. } => format!("Add {block} to \"On start\""),
+        }
+    }
+}
+
+/// The editable state of the workspace, saved on the undo stack before a fix is applied
+#[derive(Clone)]
+pub struct EditSnapshot {
+    pub sections: Vec<BlockSection>,
+    pub variables: Vec<VariableDefinition>,
+}
+
+impl Diagnostic {
+    /// Builds a diagnostic from a validator message, splitting off a trailing
+    /// "Suggested: ..." part into the suggestion
+    pub fn from_message(
+        severity: Severity,
+        instance_id: Option<&str>,
+        input: Option<&str>,
+        text: &str,
+    ) -> Self {
+        let (message, suggestion) = match text.split_once("\nSuggested: ") {
+            Some((message, suggestion)) => (message, Some(suggestion.trim().to_string())),
+            None => (text, None),
+        };
+        Self {
+            severity,
+            instance_id: instance_id.map(str::to_string),
+            input: input.map(str::to_string),
+            message: message.trim().to_string(),
+            suggestion,
+            fix: None,
+            candidates: Vec::new(),
+        }
+    }
+
+    /// Attaches an automatic fix to the diagnostic
+    pub fn with_fix(mut self, fix: Option<QuickFix>) -> Self {
+        self.fix = fix;
+        self
+    }
+
+    /// Attaches suggested sequences to the diagnostic
+    pub fn with_candidates(mut self, candidates: Vec<SequenceCandidate>) -> Self {
+        self.candidates = candidates;
+        self
+    }
+}
+
+/// The widget used to edit an input on the canvas, chosen from the input's rule, type
+/// and constraints. Every widget stores its value as text in `input_values`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputWidget {
+    /// Single-line text entry
+    Text,
+    /// Dropdown of the allowed values
+    Choice(Vec<String>),
+    /// Drag-value for whole numbers within optional bounds
+    Integer { min: Option<i64>, max: Option<i64> },
+    /// Checkbox storing "true" or "false"
+    Checkbox,
+    /// Colour picker storing "#rrggbb"
+    Colour,
+}
+
+/// Whether the program on the virtual board is running
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunState {
+    /// Not started, finished by the user or stopped by an error
+    Stopped,
+    Running,
+    /// Waiting in front of a block after a step, a pause or a breakpoint
+    Paused,
 }
 
 impl Offset {
@@ -0,0 +1,717 @@
+// Whole-sequence patterns: regular expressions over block unique_ids
+// A pattern such as `PinMode (Pin_toggle delay)+` is compiled once into a
+// nondeterministic automaton, which is then run over the block sequence of each stack.
+
+/* ---------- AUTOMATON TYPES ---------- */
+
+/// What a transition between two automaton states consumes
+#[derive(Clone, Debug, PartialEq)]
+enum Edge {
+    /// Exactly this block unique_id
+    Block(String),
+    /// Any single block (written `.`)
+    Any,
+}
+
+/// One automaton state with its consuming and empty transitions
+#[derive(Clone, Debug, Default)]
+struct State {
+    edges: Vec<(Edge, usize)>,
+    epsilon: Vec<usize>,
+}
+
+/// A piece of automaton under construction with one entry and one exit state
+#[derive(Clone, Copy)]
+struct Fragment {
+    start: usize,
+    end: usize,
+}
+
+/// A compiled sequence pattern
+#[derive(Clone, Debug)]
+pub struct SequencePattern {
+    /// The pattern text as written, shown in messages
+    pub source: String,
+    states: Vec<State>,
+    start: usize,
+    accept: usize,
+}
+
+/// Where a sequence leaves a pattern
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    /// Index of the first block the pattern cannot accept, or the sequence length when
+    /// the sequence ends before the pattern is complete
+    pub position: usize,
+    /// The blocks the pattern would accept at that position; "any block" for `.`
+    pub expected: Vec<String>,
+    /// True when the pattern could also have ended at that position
+    pub can_end: bool,
+}
+
+/// One step of an edit script that turns a sequence into one the pattern accepts
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    /// Add a new block after the named one (None: at the top)
+    Insert { block: String, after: Option<String> },
+    /// Remove the block at this position of the original sequence
+    Delete { block: String, position: usize },
+    /// Swap the block at this position for a different one
+    Substitute { from: String, to: String, position: usize },
+    /// Take an existing block out and put it back after the named one (None: at the top)
+    Move { block: String, after: Option<String> },
+}
+
+impl Edit {
+    /// Search cost of the edit. A substitution costs a little more than a move, so
+    /// swapping two blocks is described as moving them rather than replacing both.
+    fn cost(&self) -> usize {
+        match self {
+            Edit::Substitute { .. } => 3,
+            _ => 2,
+        }
+    }
+
+    /// Describes the edit, e.g. "move delay after Pin_toggle"
+    pub fn label(&self) -> String {
+        match self {
+            Edit::Insert { block, after: Some(after) } => format!("insert {block} after {after}"),
+            Edit::Insert { block, after: None } => format!("insert {block} at the top"),
+            Edit::Delete { block, position } => format!("delete {block} (block {})", position + 1),
+            Edit::Substitute { from, to, position } => {
+                format!("replace {from} (block {}) with {to}", position + 1)
+            }
+            Edit::Move { block, after: Some(after) } => format!("move {block} after {after}"),
+            Edit::Move { block, after: None } => format!("move {block} to the top"),
+        }
+    }
+}
+
+/// A sequence the pattern accepts, with the edits that produce it from the original
+#[derive(Clone, Debug, PartialEq)]
+pub struct Correction {
+    pub sequence: Vec<String>,
+    pub edits: Vec<Edit>,
+}
+
+impl Correction {
+    /// Total search cost of the edit script; lower is closer
+    pub fn cost(&self) -> usize {
+        self.edits.iter().map(Edit::cost).sum()
+    }
+}
+
+/// A partial correction explored by the search in `corrections`
+#[derive(Clone)]
+struct SearchNode {
+    position: usize,
+    state: usize,
+    /// Deleted blocks that may still be put back for free, turning the delete into a move,
+    /// with the index of their Delete edit
+    carried: Vec<(String, usize)>,
+    /// Inserted blocks that may still be taken from later in the sequence for free,
+    /// turning the insert into a move, with the index of their Insert edit
+    owed: Vec<(String, usize)>,
+    output: Vec<String>,
+    edits: Vec<Edit>,
+}
+
+/// How many deleted or inserted blocks the search keeps open as possible moves
+const MAX_OPEN_MOVES: usize = 2;
+
+/// How many partial corrections the search may expand before giving up
+const SEARCH_BUDGET: usize = 5_000;
+
+/* ---------- PATTERN PARSING ---------- */
+
+/// Splits pattern text into block ids and the operators ( ) | * + ? .
+/// Each token is returned with the column it starts at, for error messages.
+fn tokenize(source: &str) -> Result<Vec<(usize, String)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((col, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if "()|*+?.".contains(c) {
+            tokens.push((col, c.to_string()));
+            continue;
+        }
+        if !(c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("unexpected '{}' at column {}", c, col + 1));
+        }
+        let mut word = c.to_string();
+        while let Some(&(_, next)) = chars.peek() {
+            if !(next.is_ascii_alphanumeric() || next == '_' || next == '-') {
+                break;
+            }
+            word.push(next);
+            chars.next();
+        }
+        tokens.push((col, word));
+    }
+    Ok(tokens)
+}
+
+/// Recursive-descent parser that builds the automaton while it reads the pattern
+/// (Thompson's construction)
+struct PatternParser {
+    tokens: Vec<(usize, String)>,
+    pos: usize,
+    states: Vec<State>,
+    source_len: usize,
+}
+
+impl PatternParser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|(_, t)| t.as_str())
+    }
+
+    /// Column of the current token (or the end of the pattern), counted from 1
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.source_len, |(col, _)| *col) + 1
+    }
+
+    fn state(&mut self) -> usize {
+        self.states.push(State::default());
+        self.states.len() - 1
+    }
+
+    fn link(&mut self, from: usize, to: usize) {
+        self.states[from].epsilon.push(to);
+    }
+
+    /// alternation := sequence ('|' sequence)*
+    fn alternation(&mut self) -> Result<Fragment, String> {
+        let first = self.sequence()?;
+        if self.peek() != Some("|") {
+            return Ok(first);
+        }
+        let start = self.state();
+        let end = self.state();
+        let mut branch = first;
+        loop {
+            self.link(start, branch.start);
+            self.link(branch.end, end);
+            if self.peek() != Some("|") {
+                break;
+            }
+            self.pos += 1;
+            branch = self.sequence()?;
+        }
+        Ok(Fragment { start, end })
+    }
+
+    /// sequence := repeat+
+    fn sequence(&mut self) -> Result<Fragment, String> {
+        let mut fragment: Option<Fragment> = None;
+        while let Some(token) = self.peek() {
+            if token == "|" || token == ")" {
+                break;
+            }
+            let next = self.repeat()?;
+            fragment = Some(match fragment {
+                Some(prev) => {
+                    self.link(prev.end, next.start);
+                    Fragment { start: prev.start, end: next.end }
+                }
+                None => next,
+            });
+        }
+        fragment.ok_or_else(|| format!("expected a block at column {}", self.column()))
+    }
+
+    /// repeat := atom ('*' | '+' | '?')*
+    fn repeat(&mut self) -> Result<Fragment, String> {
+        let mut fragment = self.atom()?;
+        while let Some(op) = self.peek().filter(|t| matches!(*t, "*" | "+" | "?")) {
+            let op = op.to_string();
+            self.pos += 1;
+            let start = self.state();
+            let end = self.state();
+            self.link(start, fragment.start);
+            self.link(fragment.end, end);
+            if op != "+" {
+                self.link(start, end);
+            }
+            if op != "?" {
+                self.link(fragment.end, fragment.start);
+            }
+            fragment = Fragment { start, end };
+        }
+        Ok(fragment)
+    }
+
+    /// atom := block_id | '.' | '(' alternation ')'
+    fn atom(&mut self) -> Result<Fragment, String> {
+        let column = self.column();
+        let Some((_, token)) = self.tokens.get(self.pos).cloned() else {
+            return Err(format!("expected a block at column {column}"));
+        };
+        self.pos += 1;
+        let edge = match token.as_str() {
+            "(" => {
+                let inner = self.alternation()?;
+                if self.peek() != Some(")") {
+                    return Err(format!(
+                        "expected ')' at column {} to close the '(' at column {}",
+                        self.column(),
+                        column
+                    ));
+                }
+                self.pos += 1;
+                return Ok(inner);
+            }
+            "." => Edge::Any,
+            ")" | "|" | "*" | "+" | "?" => {
+                return Err(format!("unexpected '{token}' at column {column}"));
+            }
+            _ => Edge::Block(token),
+        };
+        let start = self.state();
+        let end = self.state();
+        self.states[start].edges.push((edge, end));
+        Ok(Fragment { start, end })
+    }
+}
+
+/* ---------- MATCHING ---------- */
+
+impl SequencePattern {
+    /// Compiles pattern text. Block ids are separated by spaces; `( )` groups, `|`
+    /// separates alternatives, `*` `+` `?` repeat the item before them and `.` matches
+    /// any block.
+    ///
+    /// # Returns
+    /// The compiled pattern, or a message naming the column of the syntax error
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let mut parser = PatternParser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            states: Vec::new(),
+            source_len: source.len(),
+        };
+        let fragment = parser.alternation()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(format!("unexpected ')' at column {}", parser.column()));
+        }
+        Ok(Self {
+            source: source.trim().to_string(),
+            states: parser.states,
+            start: fragment.start,
+            accept: fragment.end,
+        })
+    }
+
+    /// Builds a pattern that accepts exactly one sequence of blocks
+    pub fn literal(sequence: &[String]) -> Self {
+        let mut states = vec![State::default()];
+        for id in sequence {
+            let from = states.len() - 1;
+            states.push(State::default());
+            states[from].edges.push((Edge::Block(id.clone()), from + 1));
+        }
+        Self {
+            source: sequence.join(" "),
+            accept: states.len() - 1,
+            states,
+            start: 0,
+        }
+    }
+
+    /// Lists the block ids the pattern mentions
+    pub fn block_ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = Vec::new();
+        for state in &self.states {
+            for (edge, _) in &state.edges {
+                if let Edge::Block(id) = edge
+                    && !ids.contains(&id.as_str())
+                {
+                    ids.push(id);
+                }
+            }
+        }
+        ids
+    }
+
+    /// Extends a set of states with every state reachable through empty transitions
+    fn closure(&self, mut set: Vec<usize>) -> Vec<usize> {
+        let mut seen = vec![false; self.states.len()];
+        for &state in &set {
+            seen[state] = true;
+        }
+        let mut i = 0;
+        while i < set.len() {
+            for &next in &self.states[set[i]].epsilon {
+                if !seen[next] {
+                    seen[next] = true;
+                    set.push(next);
+                }
+            }
+            i += 1;
+        }
+        set.sort_unstable();
+        set
+    }
+
+    /// Follows every transition that accepts the block from a set of states
+    fn step(&self, set: &[usize], id: &str) -> Vec<usize> {
+        let next = set
+            .iter()
+            .flat_map(|&state| &self.states[state].edges)
+            .filter(|(edge, _)| match edge {
+                Edge::Block(block) => block == id,
+                Edge::Any => true,
+            })
+            .map(|(_, to)| *to)
+            .collect();
+        self.closure(next)
+    }
+
+    /// Describes the blocks a set of states can accept next
+    fn expected(&self, set: &[usize]) -> Vec<String> {
+        let mut expected: Vec<String> = Vec::new();
+        for (edge, _) in set.iter().flat_map(|&state| &self.states[state].edges) {
+            let label = match edge {
+                Edge::Block(id) => id.clone(),
+                Edge::Any => "any block".to_string(),
+            };
+            if !expected.contains(&label) {
+                expected.push(label);
+            }
+        }
+        expected
+    }
+
+    /// Runs the automaton over a sequence of block ids.
+    ///
+    /// # Returns
+    /// Ok if the whole sequence matches, otherwise where it leaves the pattern
+    pub fn check(&self, sequence: &[String]) -> Result<(), Mismatch> {
+        let mut set = self.closure(vec![self.start]);
+        for (position, id) in sequence.iter().enumerate() {
+            let next = self.step(&set, id);
+            if next.is_empty() {
+                return Err(Mismatch {
+                    position,
+                    expected: self.expected(&set),
+                    can_end: set.contains(&self.accept),
+                });
+            }
+            set = next;
+        }
+        if set.contains(&self.accept) {
+            return Ok(());
+        }
+        Err(Mismatch {
+            position: sequence.len(),
+            expected: self.expected(&set),
+            can_end: false,
+        })
+    }
+
+    /// Finds the sequences closest to the given one that the pattern accepts, cheapest
+    /// first. Inserting, deleting, substituting and moving a block each count as one edit.
+    ///
+    /// # Arguments
+    /// * `sequence` - The block ids of the stack, top to bottom
+    /// * `limit` - The most candidates to return
+    ///
+    /// # Returns
+    /// Up to `limit` distinct corrections, each with its edit script
+    pub fn corrections(&self, sequence: &[String], limit: usize) -> Vec<Correction> {
+        use std::cmp::Reverse;
+        use std::collections::{BinaryHeap, HashMap};
+
+        let mut nodes = vec![SearchNode {
+            position: 0,
+            state: self.start,
+            carried: Vec::new(),
+            owed: Vec::new(),
+            output: Vec::new(),
+            edits: Vec::new(),
+        }];
+        let mut queue = BinaryHeap::from([Reverse((0, 0))]);
+        let mut expanded: HashMap<(usize, usize, Vec<String>, Vec<String>), usize> = HashMap::new();
+        let mut found: Vec<Correction> = Vec::new();
+        let mut budget = SEARCH_BUDGET;
+
+        while let Some(Reverse((_, id))) = queue.pop() {
+            if found.len() == limit || budget == 0 {
+                break;
+            }
+            budget -= 1;
+            let node = nodes[id].clone();
+
+            if node.position == sequence.len() && node.state == self.accept {
+                if !found.iter().any(|c| c.sequence == node.output) {
+                    found.push(Correction { sequence: node.output, edits: node.edits });
+                }
+                continue;
+            }
+
+            let ids = |open: &[(String, usize)]| open.iter().map(|(id, _)| id.clone()).collect();
+            let key = (node.position, node.state, ids(&node.carried), ids(&node.owed));
+            let seen = expanded.entry(key).or_default();
+            if *seen > limit {
+                continue;
+            }
+            *seen += 1;
+
+            let mut next = Vec::new();
+            let current = sequence.get(node.position);
+
+            for &to in &self.states[node.state].epsilon {
+                next.push(SearchNode { state: to, ..node.clone() });
+            }
+
+            for (edge, to) in &self.states[node.state].edges {
+                // Match or substitute the current block
+                if let Some(current) = current {
+                    let mut step = SearchNode { state: *to, position: node.position + 1, ..node.clone() };
+                    match edge {
+                        Edge::Block(block) if block != current => {
+                            step.output.push(block.clone());
+                            step.edits.push(Edit::Substitute {
+                                from: current.clone(),
+                                to: block.clone(),
+                                position: node.position,
+                            });
+                        }
+                        _ => step.output.push(current.clone()),
+                    }
+                    next.push(step);
+                }
+
+                // Insert a block, for free if it was deleted earlier (a move)
+                let insertable: Vec<String> = match edge {
+                    Edge::Block(block) => vec![block.clone()],
+                    Edge::Any => node.carried.iter().map(|(id, _)| id.clone()).collect(),
+                };
+                for block in insertable {
+                    let mut step = SearchNode { state: *to, ..node.clone() };
+                    let after = step.output.last().cloned();
+                    match step.carried.iter().position(|(id, _)| *id == block) {
+                        Some(k) => {
+                            let (_, edit) = step.carried.remove(k);
+                            step.edits[edit] = Edit::Move { block: block.clone(), after };
+                        }
+                        None => {
+                            if step.owed.len() < MAX_OPEN_MOVES {
+                                step.owed.push((block.clone(), step.edits.len()));
+                            }
+                            step.edits.push(Edit::Insert { block: block.clone(), after });
+                        }
+                    }
+                    step.output.push(block);
+                    next.push(step);
+                }
+            }
+
+            // Delete the current block, for free if it was inserted earlier (a move)
+            if let Some(current) = current {
+                let mut step = SearchNode { position: node.position + 1, ..node.clone() };
+                match step.owed.iter().position(|(id, _)| id == current) {
+                    Some(k) => {
+                        let (_, edit) = step.owed.remove(k);
+                        if let Edit::Insert { block, after } = step.edits[edit].clone() {
+                            step.edits[edit] = Edit::Move { block, after };
+                        }
+                    }
+                    None => {
+                        if step.carried.len() < MAX_OPEN_MOVES {
+                            step.carried.push((current.clone(), step.edits.len()));
+                        }
+                        step.edits.push(Edit::Delete { block: current.clone(), position: node.position });
+                    }
+                }
+                next.push(step);
+            }
+
+            for step in next {
+                let cost = step.edits.iter().map(Edit::cost).sum::<usize>();
+                nodes.push(step);
+                queue.push(Reverse((cost, nodes.len() - 1)));
+            }
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a space-separated list of block ids
+    fn seq(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn pattern(source: &str) -> SequencePattern {
+        SequencePattern::compile(source).expect("pattern should compile")
+    }
+
+    #[test]
+    fn literal_sequences_match_exactly() {
+        let compiled = pattern("PinMode Pin_toggle delay");
+        assert_eq!(compiled.check(&seq("PinMode Pin_toggle delay")), Ok(()));
+
+        let built = SequencePattern::literal(&seq("PinMode Pin_toggle delay"));
+        assert_eq!(built.source, "PinMode Pin_toggle delay");
+        assert_eq!(built.check(&seq("PinMode Pin_toggle delay")), Ok(()));
+        assert!(built.check(&seq("PinMode delay Pin_toggle")).is_err());
+        assert_eq!(built.block_ids(), ["PinMode", "Pin_toggle", "delay"]);
+    }
+
+    #[test]
+    fn alternation_accepts_each_branch() {
+        let compiled = pattern("PinMode (Pin_on | Pin_off) delay");
+        assert_eq!(compiled.check(&seq("PinMode Pin_on delay")), Ok(()));
+        assert_eq!(compiled.check(&seq("PinMode Pin_off delay")), Ok(()));
+        assert_eq!(
+            compiled.check(&seq("PinMode delay")),
+            Err(Mismatch {
+                position: 1,
+                expected: seq("Pin_on Pin_off"),
+                can_end: false,
+            })
+        );
+    }
+
+    #[test]
+    fn repetition_operators() {
+        let plus = pattern("PinMode (Pin_toggle delay)+");
+        assert_eq!(plus.check(&seq("PinMode Pin_toggle delay")), Ok(()));
+        assert_eq!(plus.check(&seq("PinMode Pin_toggle delay Pin_toggle delay")), Ok(()));
+        assert!(plus.check(&seq("PinMode")).is_err());
+
+        let star = pattern("PinMode delay*");
+        assert_eq!(star.check(&seq("PinMode")), Ok(()));
+        assert_eq!(star.check(&seq("PinMode delay delay delay")), Ok(()));
+
+        let optional = pattern("PinMode delay? Pin_toggle");
+        assert_eq!(optional.check(&seq("PinMode Pin_toggle")), Ok(()));
+        assert_eq!(optional.check(&seq("PinMode delay Pin_toggle")), Ok(()));
+        assert!(optional.check(&seq("PinMode delay delay Pin_toggle")).is_err());
+
+        let any = pattern("PinMode . delay");
+        assert_eq!(any.check(&seq("PinMode Serial_print delay")), Ok(()));
+    }
+
+    #[test]
+    fn non_matching_sequences_report_where_they_leave_the_pattern() {
+        let compiled = pattern("PinMode (Pin_toggle delay)+");
+
+        // A wrong block part way through
+        assert_eq!(
+            compiled.check(&seq("PinMode Pin_toggle Pin_toggle")),
+            Err(Mismatch {
+                position: 2,
+                expected: seq("delay"),
+                can_end: false,
+            })
+        );
+
+        // The sequence ends before the pattern does
+        assert_eq!(
+            compiled.check(&seq("PinMode Pin_toggle")),
+            Err(Mismatch {
+                position: 2,
+                expected: seq("delay"),
+                can_end: false,
+            })
+        );
+
+        // An extra block after a complete match
+        assert_eq!(
+            pattern("PinMode delay").check(&seq("PinMode delay delay")),
+            Err(Mismatch {
+                position: 2,
+                expected: Vec::new(),
+                can_end: true,
+            })
+        );
+
+        assert_eq!(
+            pattern("PinMode .").check(&seq("delay")),
+            Err(Mismatch {
+                position: 0,
+                expected: seq("PinMode"),
+                can_end: false,
+            })
+        );
+    }
+
+    #[test]
+    fn syntax_errors_name_the_column() {
+        assert_eq!(
+            SequencePattern::compile("PinMode (delay").unwrap_err(),
+            "expected ')' at column 15 to close the '(' at column 9"
+        );
+        assert_eq!(SequencePattern::compile("PinMode delay)").unwrap_err(), "unexpected ')' at column 14");
+        assert_eq!(SequencePattern::compile("PinMode | ").unwrap_err(), "expected a block at column 11");
+        assert_eq!(SequencePattern::compile("PinMode $").unwrap_err(), "unexpected '$' at column 9");
+    }
+
+    /// The cheapest correction of a sequence against a pattern
+    fn best(source: &str, sequence: &str) -> Correction {
+        let corrections = pattern(source).corrections(&seq(sequence), 3);
+        corrections.into_iter().next().expect("a correction should be found")
+    }
+
+    #[test]
+    fn missing_block_is_inserted() {
+        let correction = best("PinMode Pin_toggle delay", "PinMode delay");
+        assert_eq!(correction.sequence, seq("PinMode Pin_toggle delay"));
+        assert_eq!(
+            correction.edits,
+            [Edit::Insert { block: "Pin_toggle".into(), after: Some("PinMode".into()) }]
+        );
+        assert_eq!(correction.edits[0].label(), "insert Pin_toggle after PinMode");
+    }
+
+    #[test]
+    fn extra_block_is_deleted() {
+        let correction = best("PinMode Pin_toggle delay", "PinMode Pin_toggle Serial_print delay");
+        assert_eq!(correction.sequence, seq("PinMode Pin_toggle delay"));
+        assert_eq!(
+            correction.edits,
+            [Edit::Delete { block: "Serial_print".into(), position: 2 }]
+        );
+        assert_eq!(correction.edits[0].label(), "delete Serial_print (block 3)");
+    }
+
+    #[test]
+    fn wrong_block_is_substituted() {
+        let correction = best("PinMode Pin_toggle delay", "PinMode Pin_toggle Serial_print");
+        assert_eq!(correction.sequence, seq("PinMode Pin_toggle delay"));
+        assert_eq!(
+            correction.edits,
+            [Edit::Substitute { from: "Serial_print".into(), to: "delay".into(), position: 2 }]
+        );
+    }
+
+    #[test]
+    fn swapped_blocks_are_moved() {
+        let correction = best("PinMode Pin_toggle delay", "PinMode delay Pin_toggle");
+        assert_eq!(correction.sequence, seq("PinMode Pin_toggle delay"));
+        assert_eq!(correction.edits.len(), 1);
+        assert!(matches!(correction.edits[0], Edit::Move { .. }));
+        assert_eq!(correction.cost(), 2);
+    }
+
+    #[test]
+    fn corrections_are_distinct_and_cheapest_first() {
+        let compiled = pattern("PinMode (Pin_on | Pin_off) delay");
+        let corrections = compiled.corrections(&seq("PinMode delay"), 3);
+        assert_eq!(corrections[0].sequence, seq("PinMode Pin_on delay"));
+        assert_eq!(corrections[1].sequence, seq("PinMode Pin_off delay"));
+        assert!(corrections.windows(2).all(|pair| pair[0].cost() <= pair[1].cost()));
+        assert!(corrections.iter().all(|c| compiled.check(&c.sequence).is_ok()));
+
+        // A sequence that already matches needs no edits
+        let exact = best("PinMode delay", "PinMode delay");
+        assert!(exact.edits.is_empty());
+    }
+}
@@ -0,0 +1,1146 @@
+// Interpreter for generated sketches
+// Runs the IR built from the block tree against a virtual board with pin states and a
+// simulated clock, so a program can be tried without flashing a real board.
+
+use crate::ir::{Expr, Header, Sketch, Stmt, StmtKind};
+use crate::model::BoardProfile;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+
+/// Virtual time one statement takes, roughly a digitalWrite on a 16 MHz AVR. Keeps the
+/// clock moving in loops that never call delay().
+const STATEMENT_TIME_US: u64 = 10;
+
+/// Most nested function calls before the program is stopped as runaway recursion
+const MAX_CALL_DEPTH: usize = 64;
+
+/// Most statements a function called while the globals are initialised may run
+const MAX_INIT_STEPS: usize = 100_000;
+
+/* ---------- VIRTUAL BOARD ---------- */
+
+/// The mode a pin was given with pinMode()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinMode {
+    Input,
+    InputPullup,
+    Output,
+}
+
+/// The state of one pin of the virtual board
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PinState {
+    /// Mode set by pinMode(), None until the sketch sets one
+    pub mode: Option<PinMode>,
+    /// Level driven by the sketch: 0 or 1 from digitalWrite, 0–255 from analogWrite
+    pub output: i64,
+    /// True while the output is a PWM duty cycle from analogWrite
+    pub pwm: bool,
+    /// Angle written by a servo attached to the pin
+    pub servo: Option<i64>,
+    /// True once the sketch has driven the pin
+    pub written: bool,
+    /// Level applied from outside the board: 0 or 1 for digitalRead, 0–1023 for
+    /// analogRead. None reads as LOW, or HIGH with INPUT_PULLUP.
+    pub input: Option<i64>,
+    /// True while a breadboard button connected to the pin is held down
+    pub pressed: bool,
+}
+
+impl PinState {
+    /// The level digitalRead sees: the output level on output pins, otherwise the
+    /// applied input or button
+    pub fn level(&self) -> i64 {
+        match self.mode {
+            Some(PinMode::Output) => (self.output != 0) as i64,
+            // A pressed button connects a pulled-up pin to ground, any other to 5 V
+            mode if self.pressed => (mode != Some(PinMode::InputPullup)) as i64,
+            Some(PinMode::InputPullup) => self.input.map_or(1, |level| (level != 0) as i64),
+            _ => self.input.map_or(0, |level| (level != 0) as i64),
+        }
+    }
+}
+
+/// A change of a pin's output, recorded for the pin history
+#[derive(Clone, Debug, PartialEq)]
+pub struct PinEvent {
+    /// Virtual time of the change, in microseconds since the board started
+    pub time_us: u64,
+    pub pin: i64,
+    /// The new level, duty cycle or servo angle
+    pub value: i64,
+}
+
+/// A board with pins, a serial monitor and nothing attached to it
+#[derive(Clone, Debug, Default)]
+pub struct VirtualBoard {
+    /// Pins the sketch has touched, by number
+    pub pins: BTreeMap<i64, PinState>,
+    /// Every change of a pin's output, oldest first
+    pub events: Vec<PinEvent>,
+    /// Everything printed with Serial.print and Serial.println
+    pub serial: String,
+    /// Analog pin names with their pin numbers, A0 first
+    analog: Vec<(String, i64)>,
+    /// Pin of the on-board LED, if the board has one
+    led_builtin: Option<i64>,
+}
+
+impl VirtualBoard {
+    /// Creates an idle board laid out like the given profile, with its analog pin
+    /// numbers and on-board LED
+    pub fn new(profile: &BoardProfile) -> Self {
+        Self {
+            analog: profile.analog_pin_table(),
+            led_builtin: profile.led_builtin.map(i64::from),
+            ..Default::default()
+        }
+    }
+
+    /// True for the pins named A0, A1, ...
+    pub fn is_analog(&self, pin: i64) -> bool {
+        self.analog.iter().any(|(_, number)| *number == pin)
+    }
+
+    /// Names a pin the way sketches do, e.g. "13" or "A0"
+    pub fn pin_label(&self, pin: i64) -> String {
+        match self.analog.iter().find(|(_, number)| *number == pin) {
+            Some((name, _)) => name.clone(),
+            None => pin.to_string(),
+        }
+    }
+
+    /// Looks up a pin by the name sketches use for it, e.g. "13" or "A0"
+    pub fn pin_number(&self, name: &str) -> Option<i64> {
+        let name = name.trim();
+        if let Ok(pin) = name.parse::<i64>() {
+            return Some(pin);
+        }
+        self.analog
+            .iter()
+            .find(|(analog, _)| analog.eq_ignore_ascii_case(name))
+            .map(|(_, number)| *number)
+    }
+
+    /// Sets the level applied to a pin from outside, e.g. by a potentiometer. It takes
+    /// over from a pressed button.
+    pub fn set_input(&mut self, pin: i64, level: i64) {
+        let state = self.pins.entry(pin).or_default();
+        state.input = Some(level);
+        state.pressed = false;
+    }
+
+    /// Presses or releases a button on a pin. A released button leaves the pin to its
+    /// pull-up or pull-down; a pressed one pulls it the other way, so the level depends
+    /// on the mode the pin has when it is read.
+    pub fn set_button(&mut self, pin: i64, pressed: bool) {
+        let state = self.pins.entry(pin).or_default();
+        state.pressed = pressed;
+        state.input = None;
+    }
+
+    /// Drives a pin from the sketch, recording the change
+    fn write(&mut self, time_us: u64, pin: i64, value: i64, pwm: bool) {
+        let state = self.pins.entry(pin).or_default();
+        let changed = state.output != value || state.pwm != pwm || !state.written;
+        state.output = value;
+        state.pwm = pwm;
+        state.written = true;
+        if changed {
+            self.events.push(PinEvent { time_us, pin, value });
+        }
+    }
+
+    /// Reads a pin with digitalRead. The pin is listed from then on so its input can
+    /// be set.
+    fn digital_read(&mut self, pin: i64) -> i64 {
+        self.pins.entry(pin).or_default().level()
+    }
+
+    /// Reads a pin with analogRead. Channel numbers (0 for A0) are accepted as well,
+    /// unless the number is itself an analog pin. The pin is listed from then on so its
+    /// input can be set.
+    fn analog_read(&mut self, pin: i64) -> i64 {
+        let pin = if self.is_analog(pin) {
+            pin
+        } else {
+            self.pin_number(&format!("A{pin}")).unwrap_or(pin)
+        };
+        self.pins.entry(pin).or_default().input.unwrap_or(0).clamp(0, 1023)
+    }
+}
+
+/* ---------- VALUES AND FRAMES ---------- */
+
+/// A value computed by the sketch
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl Value {
+    fn as_f64(&self) -> f64 {
+        match self {
+            Value::Int(n) => *n as f64,
+            Value::Float(x) => *x,
+            Value::Str(_) => 0.0,
+        }
+    }
+
+    fn as_i64(&self) -> i64 {
+        match self {
+            Value::Int(n) => *n,
+            Value::Float(x) => *x as i64,
+            Value::Str(_) => 0,
+        }
+    }
+
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Int(n) => *n != 0,
+            Value::Float(x) => *x != 0.0,
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+
+    /// Formats the value as Serial.print does; floats get two decimals
+    pub fn to_text(&self) -> String {
+        match self {
+            Value::Int(n) => n.to_string(),
+            Value::Float(x) => format!("{x:.2}"),
+            Value::Str(s) => s.clone(),
+        }
+    }
+
+    /// Converts the value to a declared C++ type
+    fn cast(self, ty: &str) -> Value {
+        match ty {
+            "float" | "double" => Value::Float(self.as_f64()),
+            "bool" | "boolean" => Value::Int(self.truthy() as i64),
+            "byte" => Value::Int(self.as_i64().rem_euclid(256)),
+            "String" => Value::Str(self.to_text()),
+            _ if matches!(self, Value::Str(_)) => self,
+            _ => Value::Int(self.as_i64()),
+        }
+    }
+}
+
+/// A variable with the type it was declared with
+#[derive(Clone, Debug)]
+struct Variable {
+    ty: String,
+    value: Value,
+}
+
+/// A user-defined function from a "Define function" block
+#[derive(Clone, Debug)]
+struct Function {
+    /// (type, name) of each parameter
+    params: Vec<(String, String)>,
+    body: Rc<Vec<Stmt>>,
+}
+
+/// Why a list of statements is running
+#[derive(Clone, Debug)]
+enum FrameKind {
+    /// setup(); loop() starts when it ends
+    Setup,
+    /// loop(); starts again when it ends
+    Loop,
+    /// The body of an if or else
+    Branch,
+    /// The body of a while loop, repeated while the condition holds
+    While(Expr),
+    /// A function body; variables of the frames below are out of scope
+    Call,
+}
+
+/// A list of statements being executed, with the variables declared in it
+#[derive(Clone, Debug)]
+struct Frame {
+    kind: FrameKind,
+    body: Rc<Vec<Stmt>>,
+    next: usize,
+    locals: HashMap<String, Variable>,
+    /// Whether a branch of the current if / else if / else chain has already run
+    branch_taken: bool,
+}
+
+impl Frame {
+    fn new(kind: FrameKind, body: Rc<Vec<Stmt>>) -> Self {
+        Self {
+            kind,
+            body,
+            next: 0,
+            locals: HashMap::new(),
+            branch_taken: false,
+        }
+    }
+}
+
+/* ---------- SIMULATOR ---------- */
+
+/// Executes a sketch one statement at a time against a virtual board and clock
+pub struct Simulator {
+    pub board: VirtualBoard,
+    /// Virtual time since the board started, in microseconds
+    pub time_us: u64,
+    /// instance_id of the block behind the statement that ran last
+    pub current_block: Option<String>,
+    /// The error that stopped the program, if any
+    pub error: Option<String>,
+    /// Real time the simulation has been given so far; the program runs until its
+    /// clock catches up with this
+    wall_us: u64,
+    /// Origin of the statement that ran last, cleared whenever a body starts over or
+    /// ends, so the next statement with an origin begins a new block
+    last_origin: Option<String>,
+    globals: HashMap<String, Variable>,
+    functions: HashMap<String, Function>,
+    /// Servo objects by name, with the pin they are attached to
+    servos: HashMap<String, Option<i64>>,
+    loop_body: Rc<Vec<Stmt>>,
+    frames: Vec<Frame>,
+    /// Most statements the current advance() or step_block() may run, which also
+    /// bounds a function called inside an expression
+    step_budget: usize,
+}
+
+impl Simulator {
+    /// Prepares a sketch to run: declares its library objects, global variables and
+    /// functions, and stops before the first statement of setup().
+    ///
+    /// # Returns
+    /// The simulator, or an error for global code it cannot run
+    pub fn new(sketch: &Sketch, profile: &BoardProfile) -> Result<Self, String> {
+        let mut sim = Self {
+            board: VirtualBoard::new(profile),
+            time_us: 0,
+            current_block: None,
+            error: None,
+            wall_us: 0,
+            last_origin: None,
+            globals: HashMap::new(),
+            functions: HashMap::new(),
+            servos: HashMap::new(),
+            loop_body: Rc::new(sketch.loop_body.clone()),
+            frames: Vec::new(),
+            step_budget: MAX_INIT_STEPS,
+        };
+
+        for stmt in &sketch.declarations {
+            sim.declare_object(stmt)?;
+        }
+        for stmt in &sketch.globals {
+            match &stmt.kind {
+                StmtKind::Decl { ty, name, init } => {
+                    let value = match init {
+                        Some(init) => sim.eval(init)?,
+                        None => Value::Int(0),
+                    };
+                    sim.globals.insert(name.clone(), Variable { ty: ty.clone(), value: value.cast(ty) });
+                }
+                StmtKind::Comment(_) | StmtKind::Blank => {}
+                _ => sim.declare_object(stmt)?,
+            }
+        }
+        for stmt in &sketch.functions {
+            if let StmtKind::Compound { header: Header::Raw(header), body } = &stmt.kind {
+                let (name, function) = parse_function(header, body)?;
+                sim.functions.insert(name, function);
+            }
+        }
+
+        sim.frames.push(Frame::new(FrameKind::Setup, Rc::new(sketch.setup.clone())));
+        Ok(sim)
+    }
+
+    /// Declares a library object such as `Servo arm;`
+    fn declare_object(&mut self, stmt: &Stmt) -> Result<(), String> {
+        if let StmtKind::Raw(text) = &stmt.kind
+            && let Some(name) = text.strip_prefix("Servo ").and_then(|rest| rest.strip_suffix(';'))
+        {
+            self.servos.insert(name.trim().to_string(), None);
+            return Ok(());
+        }
+        Err(format!("The simulator cannot run the global statement: {}", stmt_text(stmt)))
+    }
+
+    /// Gives the program more real time and runs it until its clock catches up, or
+    /// until `max_steps` statements have run so one call never blocks for long. Stops
+    /// early in front of a block whose instance_id is in `breakpoints`.
+    ///
+    /// # Returns
+    /// True if a breakpoint was reached, or an error if the program stopped with a
+    /// runtime error
+    pub fn advance(
+        &mut self,
+        us: u64,
+        max_steps: usize,
+        breakpoints: &HashSet<String>,
+    ) -> Result<bool, String> {
+        self.wall_us = self.wall_us.saturating_add(us);
+        self.step_budget = max_steps;
+        for _ in 0..max_steps {
+            if self.time_us >= self.wall_us {
+                break;
+            }
+            self.step()?;
+            if self.at_block_start()
+                && self.next_block().is_some_and(|block| breakpoints.contains(block))
+            {
+                // Time does not run on while the program waits at the breakpoint
+                self.wall_us = self.time_us;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Runs the rest of the current block and stops in front of the next one
+    pub fn step_block(&mut self, max_steps: usize) -> Result<(), String> {
+        self.step_budget = max_steps;
+        for _ in 0..max_steps {
+            self.step()?;
+            if self.at_block_start() {
+                break;
+            }
+        }
+        self.wall_us = self.wall_us.max(self.time_us);
+        Ok(())
+    }
+
+    /// instance_id of the block the next statement belongs to, if the current body has
+    /// one left
+    pub fn next_block(&self) -> Option<&str> {
+        let frame = self.frames.last()?;
+        frame.body[frame.next.min(frame.body.len())..]
+            .iter()
+            .find(|stmt| !matches!(stmt.kind, StmtKind::Comment(_) | StmtKind::Blank | StmtKind::Slot(_)))
+            .and_then(|stmt| stmt.origin.as_deref())
+    }
+
+    /// True when the next statement starts a block rather than continuing the last one
+    pub fn at_block_start(&self) -> bool {
+        self.next_block().is_some_and(|block| self.last_origin.as_deref() != Some(block))
+    }
+
+    /// The block to highlight: the one about to run when stopped in front of a block,
+    /// otherwise the one that ran last
+    pub fn position(&self) -> Option<String> {
+        if self.at_block_start() {
+            self.next_block().map(str::to_string)
+        } else {
+            self.current_block.clone()
+        }
+    }
+
+    /// Lists the variables in scope with their current values: the globals by name,
+    /// then the locals of the running function, innermost last
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        let mut out: Vec<(String, Value)> = self
+            .globals
+            .iter()
+            .map(|(name, variable)| (name.clone(), variable.value.clone()))
+            .collect();
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        let start = self
+            .frames
+            .iter()
+            .rposition(|frame| matches!(frame.kind, FrameKind::Call))
+            .unwrap_or(0);
+        for frame in &self.frames[start.min(self.frames.len())..] {
+            let mut locals: Vec<_> = frame.locals.iter().collect();
+            locals.sort_by(|a, b| a.0.cmp(b.0));
+            for (name, variable) in locals {
+                out.retain(|(existing, _)| existing != name);
+                out.push((name.clone(), variable.value.clone()));
+            }
+        }
+        out
+    }
+
+    /// Runs the next statement. Errors stop the program for good.
+    fn step(&mut self) -> Result<(), String> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        let result = self.step_inner();
+        if let Err(error) = &result {
+            self.error = Some(error.clone());
+        }
+        result
+    }
+
+    fn step_inner(&mut self) -> Result<(), String> {
+        let Some(frame) = self.frames.last_mut() else {
+            self.frames.push(Frame::new(FrameKind::Loop, self.loop_body.clone()));
+            self.last_origin = None;
+            return Ok(());
+        };
+
+        // End of a body: repeat it, return from it or move on from setup() to loop()
+        if frame.next >= frame.body.len() {
+            self.last_origin = None;
+            match frame.kind.clone() {
+                FrameKind::Loop => {
+                    frame.next = 0;
+                    frame.locals.clear();
+                    self.time_us = self.time_us.saturating_add(STATEMENT_TIME_US);
+                }
+                FrameKind::While(condition) => {
+                    frame.locals.clear();
+                    if self.eval(&condition)?.truthy() {
+                        if let Some(frame) = self.frames.last_mut() {
+                            frame.next = 0;
+                        }
+                    } else {
+                        self.frames.pop();
+                    }
+                    self.time_us = self.time_us.saturating_add(STATEMENT_TIME_US);
+                }
+                FrameKind::Setup => {
+                    self.frames.pop();
+                    self.frames.push(Frame::new(FrameKind::Loop, self.loop_body.clone()));
+                }
+                FrameKind::Branch | FrameKind::Call => {
+                    self.frames.pop();
+                }
+            }
+            return Ok(());
+        }
+
+        let stmt = frame.body[frame.next].clone();
+        frame.next += 1;
+        if !matches!(stmt.kind, StmtKind::Compound { header: Header::ElseIf(_) | Header::Else, .. }) {
+            frame.branch_taken = false;
+        }
+        if matches!(stmt.kind, StmtKind::Comment(_) | StmtKind::Blank | StmtKind::Slot(_)) {
+            return Ok(());
+        }
+        if stmt.origin.is_some() {
+            self.current_block = stmt.origin.clone();
+        }
+        self.last_origin = stmt.origin.clone();
+        self.time_us = self.time_us.saturating_add(STATEMENT_TIME_US);
+
+        match stmt.kind {
+            StmtKind::Expr(Expr::Call { name, args }) if self.functions.contains_key(&name) => {
+                self.call(&name, &args)?;
+            }
+            StmtKind::Expr(expr) => {
+                self.eval(&expr)?;
+            }
+            StmtKind::Decl { ty, name, init } => {
+                let value = match init {
+                    Some(init) => self.eval(&init)?,
+                    None => Value::Int(0),
+                };
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.locals.insert(name, Variable { value: value.cast(&ty), ty });
+                }
+            }
+            StmtKind::Compound { header, body } => {
+                let run = match &header {
+                    Header::If(condition) => self.eval(condition)?.truthy(),
+                    Header::ElseIf(condition) => !self.branch_taken() && self.eval(condition)?.truthy(),
+                    Header::Else => !self.branch_taken(),
+                    Header::While(condition) => self.eval(condition)?.truthy(),
+                    Header::Raw(text) => {
+                        return Err(format!("The simulator cannot run '{text}' blocks."));
+                    }
+                };
+                if run {
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.branch_taken = true;
+                    }
+                    let kind = match header {
+                        Header::While(condition) => FrameKind::While(condition),
+                        _ => FrameKind::Branch,
+                    };
+                    self.frames.push(Frame::new(kind, Rc::new(body)));
+                }
+            }
+            StmtKind::Raw(_) => {
+                return Err(format!("The simulator cannot run: {}", stmt_text(&stmt)));
+            }
+            StmtKind::Comment(_) | StmtKind::Blank | StmtKind::Slot(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Whether a branch of the if chain the current statement belongs to already ran
+    fn branch_taken(&self) -> bool {
+        self.frames.last().is_some_and(|frame| frame.branch_taken)
+    }
+
+    /// Starts a user-defined function: its parameters become the locals of a new frame
+    fn call(&mut self, name: &str, args: &[Expr]) -> Result<(), String> {
+        let function = self.functions[name].clone();
+        if args.len() != function.params.len() {
+            return Err(format!(
+                "{name}() takes {} argument{} but was given {}.",
+                function.params.len(),
+                if function.params.len() == 1 { "" } else { "s" },
+                args.len()
+            ));
+        }
+        let calls = self.frames.iter().filter(|f| matches!(f.kind, FrameKind::Call)).count();
+        if calls >= MAX_CALL_DEPTH {
+            return Err(format!("{name}() calls itself too deeply."));
+        }
+
+        let mut frame = Frame::new(FrameKind::Call, function.body);
+        for ((ty, param), arg) in function.params.iter().zip(args) {
+            let value = self.eval(arg)?.cast(ty);
+            frame.locals.insert(param.clone(), Variable { ty: ty.clone(), value });
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    /// Finds a variable in the innermost scope that declares it: the frames of the
+    /// current function, then the globals
+    fn variable_mut(&mut self, name: &str) -> Option<&mut Variable> {
+        let start = self
+            .frames
+            .iter()
+            .rposition(|frame| matches!(frame.kind, FrameKind::Call))
+            .unwrap_or(0);
+        match self.frames[start..].iter_mut().rev().find(|frame| frame.locals.contains_key(name)) {
+            Some(frame) => frame.locals.get_mut(name),
+            None => self.globals.get_mut(name),
+        }
+    }
+
+    /* ---------- EXPRESSIONS ---------- */
+
+    /// Evaluates an expression, running any built-in calls it contains
+    fn eval(&mut self, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::Number(text) => parse_number(text),
+            Expr::Str(text) => Ok(Value::Str(unquote(text))),
+            Expr::Char(text) => Ok(Value::Int(unquote(text).chars().next().map_or(0, |c| c as i64))),
+            Expr::Ident(name) => self.lookup(name),
+            Expr::Paren(inner) => self.eval(inner),
+            Expr::Call { name, args } => self.call_builtin(name, args),
+            Expr::Unary { op, expr } => match op.as_str() {
+                "++" | "--" => {
+                    let delta = if op == "++" { 1 } else { -1 };
+                    let value = Value::Int(self.eval(expr)?.as_i64().wrapping_add(delta));
+                    self.assign(expr, value)
+                }
+                _ => {
+                    let value = self.eval(expr)?;
+                    Ok(match (op.as_str(), value) {
+                        ("!", value) => Value::Int(!value.truthy() as i64),
+                        ("-", Value::Float(x)) => Value::Float(-x),
+                        ("-", value) => Value::Int(value.as_i64().wrapping_neg()),
+                        ("~", value) => Value::Int(!value.as_i64()),
+                        (_, value) => value,
+                    })
+                }
+            },
+            Expr::Postfix { op, expr } => {
+                let before = self.eval(expr)?;
+                let delta = if op == "++" { 1 } else { -1 };
+                self.assign(expr, Value::Int(before.as_i64().wrapping_add(delta)))?;
+                Ok(before)
+            }
+            Expr::Binary { op, lhs, rhs } => {
+                let left = self.eval(lhs)?;
+                // && and || only evaluate their right side when needed
+                match op.as_str() {
+                    "&&" if !left.truthy() => return Ok(Value::Int(0)),
+                    "||" if left.truthy() => return Ok(Value::Int(1)),
+                    _ => {}
+                }
+                let right = self.eval(rhs)?;
+                binary(op, &left, &right)
+            }
+            Expr::Assign { op, target, value } => {
+                let value = self.eval(value)?;
+                let value = match op.strip_suffix('=').filter(|op| !op.is_empty()) {
+                    Some(op) => binary(op, &self.eval(target)?, &value)?,
+                    None => value,
+                };
+                self.assign(target, value)
+            }
+        }
+    }
+
+    /// Stores a value in a variable, converted to its declared type
+    fn assign(&mut self, target: &Expr, value: Value) -> Result<Value, String> {
+        let Expr::Ident(name) = target.clone().without_parens() else {
+            return Err(format!("Cannot assign to {}.", target.to_source()));
+        };
+        let variable = self
+            .variable_mut(&name)
+            .ok_or_else(|| format!("The variable '{name}' is not declared."))?;
+        variable.value = value.cast(&variable.ty);
+        Ok(variable.value.clone())
+    }
+
+    /// Resolves a name to a variable or an Arduino constant
+    fn lookup(&mut self, name: &str) -> Result<Value, String> {
+        if let Some(variable) = self.variable_mut(name) {
+            return Ok(variable.value.clone());
+        }
+        let value = match name {
+            "HIGH" | "true" | "OUTPUT" => 1,
+            "LOW" | "false" | "INPUT" => 0,
+            "INPUT_PULLUP" => 2,
+            "LED_BUILTIN" => self
+                .board
+                .led_builtin
+                .ok_or("This board has no on-board LED for LED_BUILTIN.")?,
+            _ => match self.board.pin_number(name) {
+                Some(pin) => pin,
+                None => return Err(format!("The name '{name}' is not declared.")),
+            },
+        };
+        Ok(Value::Int(value))
+    }
+
+    /// Runs an Arduino library call or a servo method
+    fn call_builtin(&mut self, name: &str, args: &[Expr]) -> Result<Value, String> {
+        if self.functions.contains_key(name) {
+            // A function used inside an expression runs to completion before it returns
+            let depth = self.frames.len();
+            self.call(name, args)?;
+            for _ in 0..self.step_budget {
+                if self.frames.len() <= depth {
+                    return Ok(Value::Int(0));
+                }
+                self.step_inner()?;
+            }
+            if self.frames.len() > depth {
+                return Err(format!("{name}() did not return after {} statements.", self.step_budget));
+            }
+            return Ok(Value::Int(0));
+        }
+
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(self.eval(arg)?);
+        }
+        let int = |i: usize| values.get(i).map_or(0, Value::as_i64);
+        let expect = |count: usize| {
+            if values.len() == count {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{name}() takes {count} argument{} but was given {}.",
+                    if count == 1 { "" } else { "s" },
+                    values.len()
+                ))
+            }
+        };
+
+        if let Some((object, method)) = name.split_once('.')
+            && self.servos.contains_key(object)
+        {
+            return self.servo_call(object, method, &values);
+        }
+
+        match name {
+            "pinMode" => {
+                expect(2)?;
+                let mode = match int(1) {
+                    1 => PinMode::Output,
+                    2 => PinMode::InputPullup,
+                    _ => PinMode::Input,
+                };
+                self.board.pins.entry(int(0)).or_default().mode = Some(mode);
+            }
+            "digitalWrite" => {
+                expect(2)?;
+                self.board.write(self.time_us, int(0), (int(1) != 0) as i64, false);
+            }
+            "analogWrite" => {
+                expect(2)?;
+                self.board.write(self.time_us, int(0), int(1).clamp(0, 255), true);
+            }
+            "digitalRead" => {
+                expect(1)?;
+                return Ok(Value::Int(self.board.digital_read(int(0))));
+            }
+            "analogRead" => {
+                expect(1)?;
+                return Ok(Value::Int(self.board.analog_read(int(0))));
+            }
+            "delay" => {
+                expect(1)?;
+                let us = (int(0).max(0) as u64).saturating_mul(1000);
+                self.time_us = self.time_us.saturating_add(us);
+            }
+            "delayMicroseconds" => {
+                expect(1)?;
+                self.time_us = self.time_us.saturating_add(int(0).max(0) as u64);
+            }
+            "millis" => return Ok(Value::Int((self.time_us / 1000) as i64)),
+            "micros" => return Ok(Value::Int(self.time_us as i64)),
+            "Serial.begin" => {}
+            "Serial.print" | "Serial.println" => {
+                if let Some(value) = values.first() {
+                    self.board.serial.push_str(&value.to_text());
+                }
+                if name == "Serial.println" {
+                    self.board.serial.push('\n');
+                }
+            }
+            "abs" => {
+                expect(1)?;
+                return Ok(match &values[0] {
+                    Value::Float(x) => Value::Float(x.abs()),
+                    value => Value::Int(value.as_i64().wrapping_abs()),
+                });
+            }
+            "min" | "max" => {
+                expect(2)?;
+                let pick_first = (values[0].as_f64() < values[1].as_f64()) == (name == "min");
+                return Ok(values[if pick_first { 0 } else { 1 }].clone());
+            }
+            "constrain" => {
+                expect(3)?;
+                return Ok(Value::Int(int(0).clamp(int(1), int(2).max(int(1)))));
+            }
+            "map" => {
+                expect(5)?;
+                let span = int(2).wrapping_sub(int(1));
+                if span == 0 {
+                    return Err("map() was given an empty input range.".to_string());
+                }
+                let scaled = int(0).wrapping_sub(int(1)).wrapping_mul(int(4).wrapping_sub(int(3)));
+                return Ok(Value::Int(scaled.wrapping_div(span).wrapping_add(int(3))));
+            }
+            _ => return Err(format!("The simulator does not support {name}().")),
+        }
+        Ok(Value::Int(0))
+    }
+
+    /// Runs a method of a Servo object: attach(pin), write(angle) or read()
+    fn servo_call(&mut self, object: &str, method: &str, values: &[Value]) -> Result<Value, String> {
+        let pin = self.servos.get(object).copied().flatten();
+        match (method, values.first()) {
+            ("attach", Some(value)) => {
+                let pin = value.as_i64();
+                self.servos.insert(object.to_string(), Some(pin));
+                self.board.pins.entry(pin).or_default().mode = Some(PinMode::Output);
+            }
+            ("write", Some(value)) => {
+                let pin = pin.ok_or_else(|| format!("{object}.write() was called before {object}.attach()."))?;
+                let angle = value.as_i64().clamp(0, 180);
+                let state = self.board.pins.entry(pin).or_default();
+                if state.servo != Some(angle) {
+                    state.servo = Some(angle);
+                    self.board.events.push(PinEvent { time_us: self.time_us, pin, value: angle });
+                }
+            }
+            ("read", None) => {
+                let angle = pin.and_then(|pin| self.board.pins.get(&pin)?.servo);
+                return Ok(Value::Int(angle.unwrap_or(0)));
+            }
+            _ => return Err(format!("The simulator does not support {object}.{method}().")),
+        }
+        Ok(Value::Int(0))
+    }
+}
+
+/* ---------- HELPERS ---------- */
+
+/// Prints a statement on one line for error messages
+fn stmt_text(stmt: &Stmt) -> String {
+    crate::ir::print_stmts(std::slice::from_ref(stmt)).text.trim().replace('\n', " ")
+}
+
+/// Parses a function definition header such as "void blink(int pin, long wait)"
+fn parse_function(header: &str, body: &[Stmt]) -> Result<(String, Function), String> {
+    let invalid = || format!("The simulator cannot read the function '{header}'.");
+    let (signature, params) = header.split_once('(').ok_or_else(invalid)?;
+    let params = params.trim_end().strip_suffix(')').ok_or_else(invalid)?;
+    let name = signature.split_whitespace().last().ok_or_else(invalid)?;
+
+    let mut parsed = Vec::new();
+    for param in params.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (ty, param_name) = param.rsplit_once(char::is_whitespace).ok_or_else(invalid)?;
+        parsed.push((ty.trim().to_string(), param_name.to_string()));
+    }
+    Ok((
+        name.to_string(),
+        Function {
+            params: parsed,
+            body: Rc::new(body.to_vec()),
+        },
+    ))
+}
+
+/// Parses a C++ numeric literal: decimal, hex, binary or floating point, with any
+/// U/L suffix
+fn parse_number(text: &str) -> Result<Value, String> {
+    let invalid = || format!("'{text}' is not a number the simulator understands.");
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        let digits = hex.trim_end_matches(['u', 'l']);
+        return i64::from_str_radix(digits, 16).map(Value::Int).map_err(|_| invalid());
+    }
+    if let Some(bin) = lower.strip_prefix("0b") {
+        let digits = bin.trim_end_matches(['u', 'l']);
+        return i64::from_str_radix(digits, 2).map(Value::Int).map_err(|_| invalid());
+    }
+    if lower.contains('.') || lower.contains('e') {
+        return lower.trim_end_matches('f').parse().map(Value::Float).map_err(|_| invalid());
+    }
+    lower.trim_end_matches(['u', 'l']).parse().map(Value::Int).map_err(|_| invalid())
+}
+
+/// Removes the quotes from a string or character literal and resolves its escapes
+fn unquote(text: &str) -> String {
+    let inner = text.get(1..text.len().saturating_sub(1)).unwrap_or("");
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Applies a binary operator. Integer operands give integer results, as in C++.
+fn binary(op: &str, left: &Value, right: &Value) -> Result<Value, String> {
+    if op == "+" && (matches!(left, Value::Str(_)) || matches!(right, Value::Str(_))) {
+        return Ok(Value::Str(format!("{}{}", left.to_text(), right.to_text())));
+    }
+    let float = matches!(left, Value::Float(_)) || matches!(right, Value::Float(_));
+    let compare = |ordering: fn(std::cmp::Ordering) -> bool| {
+        let ord = if float {
+            left.as_f64().partial_cmp(&right.as_f64()).unwrap_or(std::cmp::Ordering::Equal)
+        } else {
+            left.as_i64().cmp(&right.as_i64())
+        };
+        Value::Int(ordering(ord) as i64)
+    };
+    let (a, b) = (left.as_i64(), right.as_i64());
+    let (x, y) = (left.as_f64(), right.as_f64());
+    Ok(match op {
+        "==" => compare(|o| o.is_eq()),
+        "!=" => compare(|o| o.is_ne()),
+        "<" => compare(|o| o.is_lt()),
+        "<=" => compare(|o| o.is_le()),
+        ">" => compare(|o| o.is_gt()),
+        ">=" => compare(|o| o.is_ge()),
+        "&&" => Value::Int((left.truthy() && right.truthy()) as i64),
+        "||" => Value::Int((left.truthy() || right.truthy()) as i64),
+        "+" if float => Value::Float(x + y),
+        "-" if float => Value::Float(x - y),
+        "*" if float => Value::Float(x * y),
+        "/" if float => Value::Float(x / y),
+        "+" => Value::Int(a.wrapping_add(b)),
+        "-" => Value::Int(a.wrapping_sub(b)),
+        "*" => Value::Int(a.wrapping_mul(b)),
+        "/" | "%" if b == 0 => return Err("Division by zero.".to_string()),
+        "/" => Value::Int(a.wrapping_div(b)),
+        "%" => Value::Int(a.wrapping_rem(b)),
+        "&" => Value::Int(a & b),
+        "|" => Value::Int(a | b),
+        "^" => Value::Int(a ^ b),
+        "<<" => Value::Int(a.wrapping_shl(b as u32)),
+        ">>" => Value::Int(a.wrapping_shr(b as u32)),
+        _ => return Err(format!("The simulator does not support the '{op}' operator.")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::parse_template;
+
+    /// Statements are plentiful here; the tests only stop on time or errors
+    const STEPS: usize = 1_000_000;
+
+    /// Builds a sketch from C++ source for the parts of a generated sketch
+    fn sketch(globals: &str, functions: &str, setup: &str, loop_body: &str) -> Sketch {
+        let parse = |source: &str| parse_template(source).expect("test source should parse");
+        Sketch {
+            globals: parse(globals),
+            functions: parse(functions),
+            setup: parse(setup),
+            loop_body: parse(loop_body),
+            ..Default::default()
+        }
+    }
+
+    fn start(sketch: &Sketch) -> Simulator {
+        Simulator::new(sketch, &BoardProfile::default()).expect("sketch should start")
+    }
+
+    /// Runs a sketch for `ms` of virtual time
+    fn run(sketch: &Sketch, ms: u64) -> (Simulator, Result<bool, String>) {
+        let mut simulation = start(sketch);
+        let result = simulation.advance(ms * 1000, STEPS, &HashSet::new());
+        (simulation, result)
+    }
+
+    #[test]
+    fn blink_toggles_every_delay() {
+        let blink = sketch(
+            "",
+            "",
+            "pinMode(LED_BUILTIN, OUTPUT);",
+            "digitalWrite(LED_BUILTIN, HIGH); delay(500); digitalWrite(LED_BUILTIN, LOW); delay(500);",
+        );
+        let (simulation, result) = run(&blink, 2_000);
+        assert_eq!(result, Ok(false));
+
+        let events: Vec<(u64, i64)> = simulation
+            .board
+            .events
+            .iter()
+            .map(|event| {
+                assert_eq!(event.pin, 13);
+                (event.time_us, event.value)
+            })
+            .collect();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events.iter().map(|(_, value)| *value).collect::<Vec<_>>(), [1, 0, 1, 0]);
+
+        // Each statement takes a few microseconds on top of the delays
+        for pair in events.windows(2) {
+            let gap = pair[1].0 - pair[0].0;
+            assert!((500_000..500_100).contains(&gap), "changes {gap} us apart");
+        }
+        assert!(simulation.time_us >= 2_000_000);
+    }
+
+    #[test]
+    fn digital_read_follows_the_applied_input() {
+        let follow = sketch(
+            "int level = 0;",
+            "",
+            "pinMode(2, INPUT_PULLUP); pinMode(3, INPUT); pinMode(13, OUTPUT);",
+            "level = digitalRead(2); digitalWrite(13, level); delay(10);",
+        );
+        let mut simulation = start(&follow);
+
+        // Nothing applied: the pull-up holds the pin HIGH
+        simulation.advance(20_000, STEPS, &HashSet::new()).unwrap();
+        assert_eq!(simulation.board.pins[&13].output, 1);
+
+        simulation.board.set_input(2, 0);
+        simulation.advance(20_000, STEPS, &HashSet::new()).unwrap();
+        assert_eq!(simulation.board.pins[&13].output, 0);
+        assert_eq!(simulation.board.digital_read(3), 0);
+
+        simulation.board.set_input(3, 1);
+        assert_eq!(simulation.board.digital_read(3), 1);
+    }
+
+    #[test]
+    fn buttons_read_by_pin_mode() {
+        for (mode, released, pressed) in [(PinMode::Input, 0, 1), (PinMode::InputPullup, 1, 0)] {
+            let mut board = VirtualBoard::new(&BoardProfile::default());
+
+            // The button may be wired before the sketch sets the pin mode
+            board.set_button(2, false);
+            board.pins.get_mut(&2).unwrap().mode = Some(mode);
+            assert_eq!(board.digital_read(2), released, "released with {mode:?}");
+
+            board.set_button(2, true);
+            assert_eq!(board.digital_read(2), pressed, "pressed with {mode:?}");
+
+            board.set_button(2, false);
+            assert_eq!(board.digital_read(2), released, "released again with {mode:?}");
+        }
+    }
+
+    #[test]
+    fn analog_pins_and_led_come_from_the_profile() {
+        let esp32 = BoardProfile {
+            analog_pins: vec!["A0".to_string(), "A3".to_string()],
+            analog_pin_numbers: vec![36, 39],
+            led_builtin: Some(2),
+            ..Default::default()
+        };
+
+        let board = VirtualBoard::new(&esp32);
+        assert_eq!(board.pin_number("A3"), Some(39));
+        assert_eq!(board.pin_label(36), "A0");
+        assert!(board.is_analog(39) && !board.is_analog(15));
+
+        let blink = sketch("", "", "pinMode(LED_BUILTIN, OUTPUT); digitalWrite(LED_BUILTIN, HIGH);", "");
+        let mut simulation = Simulator::new(&blink, &esp32).unwrap();
+        simulation.advance(1_000, STEPS, &HashSet::new()).unwrap();
+        assert_eq!(simulation.board.pins[&2].output, 1);
+
+        // Boards without a profile list number their analog pins after the digital ones
+        let uno = VirtualBoard::new(&BoardProfile {
+            analog_pin_numbers: Vec::new(),
+            ..Default::default()
+        });
+        assert_eq!(uno.pin_number("A0"), Some(14));
+    }
+
+    #[test]
+    fn runtime_errors_stop_the_program() {
+        let error = |globals: &str, functions: &str, loop_body: &str| {
+            let (simulation, result) = run(&sketch(globals, functions, "", loop_body), 100);
+            assert_eq!(simulation.error, result.clone().err());
+            result.unwrap_err()
+        };
+
+        assert_eq!(error("int zero = 0;", "", "int x = 5 / zero;"), "Division by zero.");
+        assert_eq!(error("", "", "missing = 1;"), "The variable 'missing' is not declared.");
+        assert_eq!(error("", "", "tone(8, 440);"), "The simulator does not support tone().");
+        assert_eq!(
+            error("", "", "digitalWrite(13);"),
+            "digitalWrite() takes 2 arguments but was given 1."
+        );
+        assert_eq!(
+            error("", "void again() { again(); }", "again();"),
+            "again() calls itself too deeply."
+        );
+        assert_eq!(
+            error("", "void spin() { while (true) { } }", "int x = spin();"),
+            format!("spin() did not return after {STEPS} statements.")
+        );
+
+        let no_led = BoardProfile {
+            led_builtin: None,
+            ..Default::default()
+        };
+        let blink = sketch("", "", "pinMode(LED_BUILTIN, OUTPUT);", "");
+        let mut simulation = Simulator::new(&blink, &no_led).unwrap();
+        assert_eq!(
+            simulation.advance(1_000, STEPS, &HashSet::new()),
+            Err("This board has no on-board LED for LED_BUILTIN.".to_string())
+        );
+
+        // The error sticks until the program is started again
+        assert!(simulation.advance(1_000, STEPS, &HashSet::new()).is_err());
+    }
+
+    #[test]
+    fn arithmetic_edge_cases_do_not_panic() {
+        let min = Value::Int(i64::MIN);
+        let minus_one = Value::Int(-1);
+        assert_eq!(binary("/", &min, &minus_one), Ok(Value::Int(i64::MIN)));
+        assert_eq!(binary("%", &min, &minus_one), Ok(Value::Int(0)));
+
+        // The clock stops at its limit instead of overflowing
+        let mut simulation = start(&sketch("", "", "delay(9223372036854775807); delay(1000);", ""));
+        assert_eq!(simulation.step_block(10), Ok(()));
+        assert_eq!(simulation.time_us, u64::MAX);
+    }
+}
@@ -0,0 +1,264 @@
+// Pin activity traces recorded by the virtual board
+// Turns the board's list of pin changes into one signal per pin, for the timeline
+// panel, and writes them out as a Value Change Dump that GTKWave and other logic
+// analyzer viewers can open.
+
+use crate::sim::VirtualBoard;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// First and last printable ASCII characters, the alphabet of VCD signal identifiers
+const ID_FIRST: u8 = b'!';
+const ID_LAST: u8 = b'~';
+
+/* ---------- SIGNALS ---------- */
+
+/// The levels one pin went through during a run
+#[derive(Clone, Debug)]
+pub struct Signal {
+    pub pin: i64,
+    /// Name shown in the timeline and written to the dump, e.g. "D13" or "A0"
+    pub name: String,
+    /// Bits needed for the largest value: 1 for digital pins, more for PWM and servos
+    pub width: u32,
+    /// (time_us, value) pairs, oldest first, at most one per point in time
+    pub changes: Vec<(u64, i64)>,
+}
+
+impl Signal {
+    /// Value the pin held at the given time, or None before the sketch first drove it
+    pub fn value_at(&self, time_us: u64) -> Option<i64> {
+        let after = self.changes.partition_point(|(time, _)| *time <= time_us);
+        after.checked_sub(1).map(|i| self.changes[i].1)
+    }
+
+    /// Largest value the pin can show with its width, used to scale the timeline
+    pub fn full_scale(&self) -> i64 {
+        (1i64 << self.width) - 1
+    }
+}
+
+/// Every pin the sketch drove, from the start of the run to `end_us`
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    pub signals: Vec<Signal>,
+    pub end_us: u64,
+}
+
+impl Trace {
+    /// Collects the board's recorded pin changes into one signal per pin, ordered by
+    /// pin number. Pins that were only read do not appear.
+    pub fn from_board(board: &VirtualBoard, end_us: u64) -> Self {
+        let mut by_pin: BTreeMap<i64, Vec<(u64, i64)>> = BTreeMap::new();
+        for event in board.events.iter().filter(|event| event.time_us <= end_us) {
+            let changes = by_pin.entry(event.pin).or_default();
+            match changes.last_mut() {
+                // Two writes in the same instant: only the last one is visible
+                Some(last) if last.0 == event.time_us => last.1 = event.value,
+                _ => changes.push((event.time_us, event.value)),
+            }
+        }
+
+        let signals = by_pin
+            .into_iter()
+            .map(|(pin, changes)| {
+                let max = changes.iter().map(|(_, value)| *value).max().unwrap_or(0).max(1);
+                let label = board.pin_label(pin);
+                let name = if board.is_analog(pin) { label } else { format!("D{label}") };
+                Signal {
+                    pin,
+                    name,
+                    width: 64 - (max as u64).leading_zeros(),
+                    changes,
+                }
+            })
+            .collect();
+
+        Trace { signals, end_us }
+    }
+
+    /// The signal of a pin, or None if the sketch never drove it
+    pub fn signal(&self, pin: i64) -> Option<&Signal> {
+        self.signals.iter().find(|signal| signal.pin == pin)
+    }
+
+    /* ---------- VCD EXPORT ---------- */
+
+    /// Writes the trace as a Value Change Dump with a 1 us timescale.
+    ///
+    /// The output has no date or other run-dependent header, so dumps of the same
+    /// program are identical and can be compared directly.
+    ///
+    /// # Arguments
+    /// * `module` - Name of the scope holding the pin signals, usually the workspace name
+    pub fn to_vcd(&self, module: &str) -> String {
+        let ids: Vec<String> = (0..self.signals.len()).map(vcd_identifier).collect();
+        let mut out = String::new();
+
+        let _ = writeln!(out, "$version Blocks for Arduino virtual board $end");
+        let _ = writeln!(out, "$timescale 1us $end");
+        let _ = writeln!(out, "$scope module {} $end", vcd_name(module));
+        for (signal, id) in self.signals.iter().zip(&ids) {
+            let _ = writeln!(out, "$var wire {} {} {} $end", signal.width, id, signal.name);
+        }
+        let _ = writeln!(out, "$upscope $end");
+        let _ = writeln!(out, "$enddefinitions $end");
+
+        // Initial values; pins the sketch had not driven yet are unknown
+        let _ = writeln!(out, "#0");
+        let _ = writeln!(out, "$dumpvars");
+        for (signal, id) in self.signals.iter().zip(&ids) {
+            out.push_str(&vcd_value(signal, signal.value_at(0), id));
+        }
+        let _ = writeln!(out, "$end");
+
+        // Every later change, grouped by time
+        let mut by_time: BTreeMap<u64, Vec<(usize, i64)>> = BTreeMap::new();
+        for (index, signal) in self.signals.iter().enumerate() {
+            for (time, value) in signal.changes.iter().filter(|(time, _)| *time > 0) {
+                by_time.entry(*time).or_default().push((index, *value));
+            }
+        }
+        let last_change = by_time.keys().next_back().copied().unwrap_or(0);
+        for (time, changes) in by_time {
+            let _ = writeln!(out, "#{time}");
+            for (index, value) in changes {
+                out.push_str(&vcd_value(&self.signals[index], Some(value), &ids[index]));
+            }
+        }
+
+        // Mark the end of the run so viewers show the last levels up to it; timestamps
+        // must increase, so there is none when the run ended on a change
+        if self.end_us > last_change {
+            let _ = writeln!(out, "#{}", self.end_us);
+        }
+        out
+    }
+}
+
+/// Short identifier code for the n-th signal: "!", "\"", ... "~", then "!!", "\"!", ...
+fn vcd_identifier(mut index: usize) -> String {
+    let base = (ID_LAST - ID_FIRST + 1) as usize;
+    let mut id = String::new();
+    loop {
+        id.push((ID_FIRST + (index % base) as u8) as char);
+        index /= base;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+/// Replaces characters a VCD scope name cannot contain
+fn vcd_name(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if cleaned.is_empty() { "sketch".to_string() } else { cleaned }
+}
+
+/// One value change line: "1!" for a single bit, "b1010 \"" for wider signals
+fn vcd_value(signal: &Signal, value: Option<i64>, id: &str) -> String {
+    match (signal.width, value) {
+        (1, Some(value)) => format!("{}{id}\n", (value != 0) as u8),
+        (1, None) => format!("x{id}\n"),
+        (_, Some(value)) => format!("b{:b} {id}\n", value.max(0)),
+        (_, None) => format!("bx {id}\n"),
+    }
+}
+
+/// Spacing of the time axis ticks: the smallest 1, 2 or 5 times a power of ten that
+/// puts at most `max_ticks` ticks across `span_us`
+pub fn tick_step(span_us: u64, max_ticks: u64) -> u64 {
+    let mut magnitude = 1u64;
+    loop {
+        for factor in [1, 2, 5] {
+            let step = factor * magnitude;
+            if span_us / step <= max_ticks.max(1) {
+                return step;
+            }
+        }
+        magnitude *= 10;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A digital pin that blinks and a PWM pin that fades, on an Uno
+    fn two_pin_trace(end_us: u64) -> Trace {
+        Trace {
+            signals: vec![
+                Signal {
+                    pin: 3,
+                    name: "D3".to_string(),
+                    width: 8,
+                    changes: vec![(500, 128), (1_500, 255)],
+                },
+                Signal {
+                    pin: 13,
+                    name: "D13".to_string(),
+                    width: 1,
+                    changes: vec![(0, 1), (1_000, 0), (1_500, 1)],
+                },
+            ],
+            end_us,
+        }
+    }
+
+    #[test]
+    fn vcd_matches_the_golden_dump() {
+        let expected = "\
+$version Blocks for Arduino virtual board $end
+$timescale 1us $end
+$scope module Blink_test $end
+$var wire 8 ! D3 $end
+$var wire 1 \" D13 $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+bx !
+1\"
+$end
+#500
+b10000000 !
+#1000
+0\"
+#1500
+b11111111 !
+1\"
+#2000
+";
+        assert_eq!(two_pin_trace(2_000).to_vcd("Blink test"), expected);
+    }
+
+    #[test]
+    fn vcd_end_marker_only_follows_the_last_change() {
+        let ended_on_change = two_pin_trace(1_500).to_vcd("sketch");
+        assert!(ended_on_change.ends_with("#1500\nb11111111 !\n1\"\n"));
+        assert_eq!(ended_on_change.matches("#1500").count(), 1);
+
+        let idle = Trace { signals: Vec::new(), end_us: 0 }.to_vcd("");
+        assert!(idle.contains("$scope module sketch $end"));
+        assert!(idle.ends_with("#0\n$dumpvars\n$end\n"));
+    }
+
+    #[test]
+    fn signal_values_and_identifiers() {
+        let trace = two_pin_trace(2_000);
+        let led = trace.signal(13).unwrap();
+        assert_eq!(led.value_at(999), Some(1));
+        assert_eq!(led.value_at(1_000), Some(0));
+        assert_eq!(trace.signal(3).unwrap().value_at(0), None);
+        assert_eq!(trace.signal(3).unwrap().full_scale(), 255);
+        assert!(trace.signal(7).is_none());
+
+        assert_eq!(vcd_identifier(0), "!");
+        assert_eq!(vcd_identifier(93), "~");
+        assert_eq!(vcd_identifier(94), "!!");
+    }
+}